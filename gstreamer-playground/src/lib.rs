@@ -0,0 +1,5 @@
+//! Reusable helpers for the GStreamer examples in `src/bin/`.
+
+mod linker;
+
+pub use crate::linker::{make, BranchFactory, LinkError, PadLinker};
@@ -0,0 +1,274 @@
+use gstreamer::{
+    prelude::*, Bin, Element, ElementFactory, Pad, PadLinkError, State,
+};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    sync::{Arc, Mutex},
+};
+
+/// Something which builds the sink end of a branch (e.g. an `autoaudiosink`,
+/// or a [`Bin`] with a ghost `sink` pad), given the name it should use.
+pub type BranchFactory =
+    Arc<dyn Fn(&str) -> Result<Element, LinkError> + Send + Sync>;
+
+/// Links the pads a `decodebin` (or `uridecodebin`) adds as it discovers
+/// streams, sending each one to a freshly built branch.
+///
+/// Branches are chosen by the media type of the pad's caps, so every audio
+/// stream in a file gets its own copy of the audio branch instead of only
+/// the first one being used. Raw audio and video have converters inserted
+/// in front of the branch automatically.
+///
+/// ```text
+/// decodebin ┬→ audioconvert → audioresample → <audio-0>
+///           ├→ audioconvert → audioresample → <audio-1>
+///           └→ videoconvert → videoscale → <video-0>
+/// ```
+///
+/// Cloning a `PadLinker` gives another handle to the same branches.
+#[derive(Clone)]
+pub struct PadLinker {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    bin: Bin,
+    branches: Mutex<Vec<Branch>>,
+    links: Mutex<Vec<Link>>,
+}
+
+struct Branch {
+    name: String,
+    media_type: String,
+    factory: BranchFactory,
+    /// The index given to the next stream sent to this branch. It's handed
+    /// out before the branch is built, so streams which turn up at the same
+    /// time never share a name.
+    next_index: usize,
+}
+
+/// The elements added for a particular pad.
+struct Link {
+    pad: Pad,
+    elements: Vec<Element>,
+}
+
+impl PadLinker {
+    /// Create a linker which adds its branches to `bin` (normally the
+    /// pipeline).
+    pub fn new<B: IsA<Bin>>(bin: &B) -> PadLinker {
+        PadLinker {
+            inner: Arc::new(Inner {
+                bin: bin.clone().upcast(),
+                branches: Mutex::new(Vec::new()),
+                links: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Send any stream whose media type starts with `media_type` (e.g.
+    /// `"audio/x-raw"`, or `"audio/"` for any kind of audio) to a branch
+    /// built by `factory`.
+    ///
+    /// Each stream's branch is named after the branch and the stream's
+    /// index, e.g. `audio-0`. A stream which couldn't be linked still uses up
+    /// its index. Branches are checked in the order they were added.
+    pub fn add_branch<F>(&self, name: &str, media_type: &str, factory: F)
+    where
+        F: Fn(&str) -> Result<Element, LinkError> + Send + Sync + 'static,
+    {
+        self.inner.branches.lock().unwrap().push(Branch {
+            name: name.to_string(),
+            media_type: media_type.to_string(),
+            factory: Arc::new(factory),
+            next_index: 0,
+        });
+    }
+
+    /// Link every pad `source` adds from now on, removing its branch again
+    /// when the pad goes away. `on_pad` is told the name of the branch each
+    /// pad was linked to, or why it couldn't be linked.
+    pub fn attach<F>(&self, source: &Element, on_pad: F)
+    where
+        F: Fn(&Pad, Result<String, LinkError>) + Send + Sync + 'static,
+    {
+        let linker = self.clone();
+        source.connect_pad_added(move |_, pad| on_pad(pad, linker.link(pad)));
+
+        let linker = self.clone();
+        source.connect_pad_removed(move |_, pad| linker.unlink(pad));
+    }
+
+    /// Build a branch for `pad` and link it up, returning the branch's
+    /// name.
+    pub fn link(&self, pad: &Pad) -> Result<String, LinkError> {
+        let caps = pad
+            .get_current_caps()
+            .ok_or_else(|| LinkError::NoCaps(pad.get_name().to_string()))?;
+        let media_type = caps
+            .get_structure(0)
+            .map(|s| s.get_name().to_string())
+            .ok_or_else(|| LinkError::NoCaps(pad.get_name().to_string()))?;
+
+        let (name, factory) = {
+            let mut branches = self.inner.branches.lock().unwrap();
+            let branch = branches
+                .iter_mut()
+                .find(|b| media_type.starts_with(&b.media_type))
+                .ok_or_else(|| LinkError::NoBranch {
+                    pad: pad.get_name().to_string(),
+                    caps: caps.to_string(),
+                })?;
+
+            // decodebin adds pads from several streaming threads, so the
+            // index is reserved up front (and skipped if linking fails)
+            let name = format!("{}-{}", branch.name, branch.next_index);
+            branch.next_index += 1;
+
+            (name, Arc::clone(&branch.factory))
+        };
+
+        // the factory is free to add branches of its own (or take as long as
+        // it likes), so it's called without holding the lock
+        let sink = factory(&name)?;
+        if sink.get_static_pad("sink").is_none() {
+            return Err(LinkError::NoSinkPad(name));
+        }
+
+        let mut elements = Vec::new();
+        for factory in converters_for(&media_type) {
+            elements.push(make(factory, &format!("{}-{}", name, factory))?);
+        }
+        elements.push(sink);
+
+        let first = elements[0].get_static_pad("sink").unwrap();
+        if let Err(e) = self.add_elements(pad, &first, &elements) {
+            self.remove_elements(&elements);
+            return Err(e);
+        }
+
+        self.inner.links.lock().unwrap().push(Link {
+            pad: pad.clone(),
+            elements,
+        });
+
+        Ok(name)
+    }
+
+    /// Tear down the branch which was built for `pad`, if there is one.
+    pub fn unlink(&self, pad: &Pad) {
+        let link = {
+            let mut links = self.inner.links.lock().unwrap();
+            match links.iter().position(|link| link.pad == *pad) {
+                Some(index) => links.remove(index),
+                None => return,
+            }
+        };
+
+        self.remove_elements(&link.elements);
+    }
+
+    fn add_elements(
+        &self,
+        pad: &Pad,
+        sink_pad: &Pad,
+        elements: &[Element],
+    ) -> Result<(), LinkError> {
+        let refs: Vec<&Element> = elements.iter().collect();
+        self.inner.bin.add_many(&refs)?;
+        Element::link_many(&refs)?;
+
+        // the rest of the pipeline is probably already playing, so the new
+        // elements need to catch up before any data arrives
+        for element in elements {
+            element.sync_state_with_parent()?;
+        }
+
+        pad.link(sink_pad).map_err(|e| LinkError::PadLink {
+            pad: pad.get_name().to_string(),
+            error: e,
+        })?;
+
+        Ok(())
+    }
+
+    fn remove_elements(&self, elements: &[Element]) {
+        for element in elements {
+            // the element may never have made it into the bin
+            let _ = element.set_state(State::Null);
+            let _ = self.inner.bin.remove(element);
+        }
+    }
+}
+
+/// The elements needed to get a stream into a form any sink will accept.
+fn converters_for(media_type: &str) -> &'static [&'static str] {
+    match media_type {
+        "audio/x-raw" => &["audioconvert", "audioresample"],
+        "video/x-raw" => &["videoconvert", "videoscale"],
+        _ => &[],
+    }
+}
+
+/// Create an element, turning a missing plugin into a [`LinkError`].
+pub fn make(factory: &str, name: &str) -> Result<Element, LinkError> {
+    ElementFactory::make(factory, Some(name))
+        .map_err(|_| LinkError::MissingElement(factory.to_string()))
+}
+
+/// Why a pad couldn't be linked.
+#[derive(Debug)]
+pub enum LinkError {
+    /// The pad doesn't have any caps yet, so there's no way to know what it
+    /// contains.
+    NoCaps(String),
+    /// None of the branches accept this kind of stream.
+    NoBranch {
+        pad: String,
+        caps: String,
+    },
+    /// An element couldn't be created, normally because the plugin providing
+    /// it isn't installed.
+    MissingElement(String),
+    /// The element built for a branch has no `sink` pad to link to.
+    NoSinkPad(String),
+    PadLink {
+        pad: String,
+        error: PadLinkError,
+    },
+    Gstreamer(glib::BoolError),
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkError::NoCaps(pad) => {
+                write!(f, "The \"{}\" pad doesn't have any caps", pad)
+            },
+            LinkError::NoBranch { pad, caps } => write!(
+                f,
+                "Nothing handles the \"{}\" pad's stream ({})",
+                pad, caps
+            ),
+            LinkError::MissingElement(name) => {
+                write!(f, "Unable to create a \"{}\" element", name)
+            },
+            LinkError::NoSinkPad(branch) => {
+                write!(f, "The \"{}\" branch doesn't have a sink pad", branch)
+            },
+            LinkError::PadLink { pad, error } => {
+                write!(f, "Unable to link the \"{}\" pad: {}", pad, error)
+            },
+            LinkError::Gstreamer(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for LinkError {}
+
+impl From<glib::BoolError> for LinkError {
+    fn from(other: glib::BoolError) -> LinkError {
+        LinkError::Gstreamer(other)
+    }
+}
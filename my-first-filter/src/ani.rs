@@ -0,0 +1,993 @@
+use crate::{decibels::to_decibels, goertzel::Goertzel};
+use byte_slice_cast::AsSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags, Element,
+    ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange, Message,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::sync::Mutex;
+use transmission::messages::{ANI, TRANSMISSION_END, TRANSMISSION_START};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(Some(plugin), "rsani", Rank::None, Ani::get_type())
+}
+
+/// The DTMF row (low group) frequencies, in Hz.
+pub const DTMF_ROWS: [f64; 4] = [697.0, 770.0, 852.0, 941.0];
+/// The DTMF column (high group) frequencies, in Hz.
+pub const DTMF_COLUMNS: [f64; 4] = [1209.0, 1336.0, 1477.0, 1633.0];
+const DTMF_KEYS: [[char; 4]; 4] = [
+    ['1', '2', '3', 'A'],
+    ['4', '5', '6', 'B'],
+    ['7', '8', '9', 'C'],
+    ['*', '0', '#', 'D'],
+];
+
+/// The ZVEI1 tone frequencies (in Hz) for the digits 0 to 9, followed by
+/// the repeat tone.
+pub const ZVEI: [f64; 11] = [
+    2400.0, 1060.0, 1160.0, 1270.0, 1400.0, 1530.0, 1670.0, 1830.0, 2000.0,
+    2200.0, 2600.0,
+];
+/// The CCIR tone frequencies (in Hz) for the digits 0 to 9, followed by the
+/// repeat tone.
+pub const CCIR: [f64; 11] = [
+    1981.0, 1124.0, 1197.0, 1275.0, 1358.0, 1446.0, 1540.0, 1640.0, 1747.0,
+    1860.0, 2110.0,
+];
+
+/// The length of each analysis block, in milliseconds.
+const BLOCK: u32 = 10;
+/// The fraction of a block's power which needs to be in the tones we're
+/// looking for before it counts as signalling rather than voice or noise.
+const PURITY: f64 = 0.6;
+
+/// A passthrough audio element which decodes the DTMF or 5-tone (ZVEI1 or
+/// CCIR) ANI sequence a radio sends to identify itself.
+///
+/// This needs to go after a `rssquelch`. Whenever the end of a transmission
+/// passes through, an `ani` element message with the transmission's
+/// `sequence`, the `unit` ID and its `format` (`"dtmf"`, `"zvei"` or
+/// `"ccir"`) is posted on the bus.
+pub struct Ani {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Ani {
+    fn on_transmission_start(&self, element: &BaseTransform, sequence: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(ref mut state) = *state {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Listening for an ANI in transmission {}",
+                sequence
+            );
+            state.sequence = Some(sequence);
+            state.detector.reset();
+        }
+    }
+
+    fn on_transmission_end(&self, element: &BaseTransform, sequence: u32) {
+        let decoded = {
+            let mut state = self.state.lock().unwrap();
+            match *state {
+                Some(ref mut state) if state.sequence == Some(sequence) => {
+                    state.sequence = None;
+                    state.detector.finish()
+                },
+                _ => None,
+            }
+        };
+
+        let ident = match decoded {
+            Some(ident) => ident,
+            None => {
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "No ANI in transmission {}",
+                    sequence
+                );
+                return;
+            },
+        };
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Transmission {} came from unit {} ({})",
+            sequence,
+            ident.unit,
+            ident.format.name()
+        );
+
+        let structure = Structure::new(
+            ANI,
+            &[
+                ("sequence", &sequence),
+                ("unit", &ident.unit),
+                ("format", &ident.format.name()),
+            ],
+        );
+        let msg = Message::new_element(structure).src(Some(element)).build();
+        let _ = element.post_message(&msg);
+    }
+}
+
+impl ObjectSubclass for Ani {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsAni";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsani",
+                DebugColorFlags::empty(),
+                Some("Rust DTMF and 5-tone ANI decoder"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "ANI Decoder",
+            "Filter/Analyzer/Audio",
+            "Decodes the DTMF or 5-tone sequence radios send to identify \
+             themselves",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        // the highest tone is 2600 Hz, so anything slower than telephone
+        // quality can't carry it
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(8000, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for Ani {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("threshold", ..) => {
+                let threshold = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing threshold from {} to {}",
+                    settings.threshold,
+                    threshold
+                );
+                settings.threshold = threshold;
+            },
+            Property("min-digits", ..) => {
+                let min_digits = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing min-digits from {} to {}",
+                    settings.min_digits,
+                    min_digits
+                );
+                settings.min_digits = min_digits;
+            },
+            _ => unimplemented!(),
+        }
+
+        // make sure the detector picks up the new settings
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.detector.settings = *settings;
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("threshold", ..) => Ok(settings.threshold.to_value()),
+            Property("min-digits", ..) => Ok(settings.min_digits.to_value()),
+            Property("unit", ..) => {
+                let unit = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .and_then(|s| s.detector.current())
+                    .map(|ident| ident.unit);
+                Ok(unit.to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Ani {}
+
+impl BaseTransformImpl for Ani {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            Some(ref mut state) => state.detector.reconfigure(info.rate()),
+            None => {
+                *state = Some(State {
+                    detector: Detector::new(settings, info.rate()),
+                    sequence: None,
+                })
+            },
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        match event.view() {
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+
+                    if s.get_name() == TRANSMISSION_START {
+                        self.on_transmission_start(element, sequence);
+                    } else if s.get_name() == TRANSMISSION_END {
+                        // post the unit ID before the end goes downstream,
+                        // so it arrives before the transmission is written out
+                        self.on_transmission_end(element, sequence);
+                    }
+                }
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.detector.reset();
+                    state.sequence = None;
+                }
+            },
+            _ => {},
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no state yet"]
+            );
+            FlowError::NotNegotiated
+        })?;
+
+        // radios only send their ID while transmitting
+        if state.sequence.is_none() {
+            return Ok(FlowSuccess::Ok);
+        }
+
+        let map = buf.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer readable"]
+            );
+            FlowError::Error
+        })?;
+        let samples = map.as_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        state.detector.process(samples);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+struct State {
+    detector: Detector,
+    /// The transmission we're currently listening to.
+    sequence: Option<u32>,
+}
+
+/// The signalling schemes a radio might use to send its ID.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Dtmf,
+    Zvei,
+    Ccir,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Dtmf => "dtmf",
+            Format::Zvei => "zvei",
+            Format::Ccir => "ccir",
+        }
+    }
+
+    /// The number of consecutive blocks a tone needs to last for before it
+    /// counts as a digit.
+    ///
+    /// ZVEI1 tones are 70 ms long so they can never satisfy the CCIR
+    /// minimum, which lets us tell the two apart when their frequencies
+    /// overlap.
+    fn min_blocks(self) -> u32 {
+        match self {
+            Format::Dtmf => 4,
+            Format::Zvei => 5,
+            Format::Ccir => 8,
+        }
+    }
+
+    /// How many blocks without a tone are allowed before the sequence is
+    /// considered finished.
+    fn max_gap(self) -> u32 {
+        match self {
+            // keypads leave a pause between each digit
+            Format::Dtmf => 20,
+            // 5-tone digits follow each other directly, this just covers the
+            // block(s) where one tone changes into the next
+            Format::Zvei | Format::Ccir => 3,
+        }
+    }
+}
+
+/// A unit ID which was decoded.
+#[derive(Debug, Clone, PartialEq)]
+struct Ident {
+    unit: String,
+    format: Format,
+}
+
+/// The part of the element which actually looks at samples.
+///
+/// Audio is chopped into 10 ms blocks and [Goertzel
+/// filters][crate::goertzel::Goertzel] measure how much of each block's
+/// power is in each of the DTMF, ZVEI1 and CCIR tones. A block is a DTMF key
+/// when a row and a column tone make up most of its power, or a 5-tone digit
+/// when a single tone does. Runs of the same symbol which last long enough
+/// become digits, and the longest sequence of digits in the transmission is
+/// the unit ID.
+#[derive(Debug)]
+struct Detector {
+    settings: Settings,
+    rate: u32,
+    dtmf_rows: Vec<Goertzel>,
+    dtmf_columns: Vec<Goertzel>,
+    zvei: Vec<Goertzel>,
+    ccir: Vec<Goertzel>,
+    /// The sum of the squared samples in this block.
+    energy: f64,
+    /// The number of samples seen so far in this block.
+    samples: usize,
+    sequences: [Sequence; 3],
+    /// The best unit ID found so far in this transmission.
+    best: Option<Ident>,
+}
+
+impl Detector {
+    fn new(settings: Settings, rate: u32) -> Detector {
+        let filters = |frequencies: &[f64]| -> Vec<Goertzel> {
+            frequencies
+                .iter()
+                .map(|&frequency| Goertzel::new(frequency, rate))
+                .collect()
+        };
+
+        Detector {
+            settings,
+            rate,
+            dtmf_rows: filters(&DTMF_ROWS),
+            dtmf_columns: filters(&DTMF_COLUMNS),
+            zvei: filters(&ZVEI),
+            ccir: filters(&CCIR),
+            energy: 0.0,
+            samples: 0,
+            // on a tie the earlier format wins, and a sequence which
+            // satisfies the stricter CCIR timing is more likely to be CCIR
+            sequences: [
+                Sequence::new(Format::Dtmf),
+                Sequence::new(Format::Ccir),
+                Sequence::new(Format::Zvei),
+            ],
+            best: None,
+        }
+    }
+
+    fn reconfigure(&mut self, rate: u32) {
+        *self = Detector::new(self.settings, rate);
+    }
+
+    fn reset(&mut self) {
+        self.filters_mut().for_each(Goertzel::reset);
+        self.energy = 0.0;
+        self.samples = 0;
+        self.sequences.iter_mut().for_each(Sequence::reset);
+        self.best = None;
+    }
+
+    fn filters_mut(&mut self) -> impl Iterator<Item = &mut Goertzel> {
+        self.dtmf_rows
+            .iter_mut()
+            .chain(self.dtmf_columns.iter_mut())
+            .chain(self.zvei.iter_mut())
+            .chain(self.ccir.iter_mut())
+    }
+
+    fn block_size(&self) -> usize {
+        std::cmp::max(1, self.rate as usize * BLOCK as usize / 1000)
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        let block_size = self.block_size();
+
+        for &sample in samples {
+            let sample = f64::from(sample);
+            self.filters_mut().for_each(|f| f.push(sample));
+            self.energy += sample * sample;
+            self.samples += 1;
+
+            if self.samples >= block_size {
+                self.end_of_block();
+            }
+        }
+    }
+
+    fn end_of_block(&mut self) {
+        let power = self.energy / self.samples as f64;
+
+        let symbols = if to_decibels(power.sqrt()) < self.settings.threshold {
+            [None; 3]
+        } else {
+            [
+                self.dtmf_key(power),
+                tone_digit(&self.ccir, self.samples, power),
+                tone_digit(&self.zvei, self.samples, power),
+            ]
+        };
+
+        for (sequence, &symbol) in self.sequences.iter_mut().zip(&symbols) {
+            if let Some(digits) = sequence.push(symbol) {
+                let candidate = Ident {
+                    unit: digits,
+                    format: sequence.format,
+                };
+                keep_longest(&mut self.best, candidate, &self.settings);
+            }
+        }
+
+        self.filters_mut().for_each(Goertzel::reset);
+        self.energy = 0.0;
+        self.samples = 0;
+    }
+
+    /// Check whether this block contains a DTMF key press.
+    fn dtmf_key(&self, power: f64) -> Option<char> {
+        let (row, row_share) = strongest(&self.dtmf_rows, self.samples, power)?;
+        let (column, column_share) =
+            strongest(&self.dtmf_columns, self.samples, power)?;
+
+        // both tones need to be there, but they're allowed to be different
+        // levels ("twist")
+        if row_share + column_share >= PURITY
+            && row_share >= PURITY / 6.0
+            && column_share >= PURITY / 6.0
+        {
+            Some(DTMF_KEYS[row][column])
+        } else {
+            None
+        }
+    }
+
+    /// The unit ID from the best sequence seen so far, including any which
+    /// are still in progress.
+    fn current(&self) -> Option<Ident> {
+        let mut best = self.best.clone();
+
+        for sequence in &self.sequences {
+            if let Some(unit) = sequence.peek() {
+                let candidate = Ident {
+                    unit,
+                    format: sequence.format,
+                };
+                keep_longest(&mut best, candidate, &self.settings);
+            }
+        }
+
+        best
+    }
+
+    /// The transmission has finished, decide which unit sent it.
+    fn finish(&mut self) -> Option<Ident> {
+        if self.samples >= self.block_size() / 2 {
+            self.end_of_block();
+        }
+
+        let ident = self.current();
+        self.reset();
+        ident
+    }
+}
+
+/// The digits decoded so far for one [`Format`].
+#[derive(Debug, Clone, PartialEq)]
+struct Sequence {
+    format: Format,
+    digits: String,
+    /// The symbol in the current run of blocks.
+    current: Option<char>,
+    /// How many blocks the current run has lasted.
+    run: u32,
+    /// How many blocks since the last tone.
+    gap: u32,
+    /// The symbol which produced the last digit.
+    last: Option<char>,
+}
+
+impl Sequence {
+    fn new(format: Format) -> Sequence {
+        Sequence {
+            format,
+            digits: String::new(),
+            current: None,
+            run: 0,
+            gap: 0,
+            last: None,
+        }
+    }
+
+    fn reset(&mut self) {
+        *self = Sequence::new(self.format);
+    }
+
+    /// Add the next block's symbol, returning the digits if that was the
+    /// end of a sequence.
+    fn push(&mut self, symbol: Option<char>) -> Option<String> {
+        if symbol.is_some() && symbol == self.current {
+            self.run += 1;
+            return None;
+        }
+
+        self.end_of_run();
+        self.current = symbol;
+
+        if symbol.is_some() {
+            self.run = 1;
+            self.gap = 0;
+            return None;
+        }
+
+        self.run = 0;
+        self.gap += 1;
+
+        if self.gap > self.format.max_gap() {
+            let digits = self.take();
+            self.reset();
+            digits
+        } else {
+            None
+        }
+    }
+
+    fn end_of_run(&mut self) {
+        let symbol = match self.current {
+            Some(symbol) if self.run >= self.format.min_blocks() => symbol,
+            _ => return,
+        };
+
+        match self.format {
+            Format::Dtmf => self.digits.push(symbol),
+            // 5-tone never sends the same tone twice in a row, so this is
+            // the same tone with a glitch in the middle
+            _ if self.last == Some(symbol) => return,
+            // the repeat tone stands in for the digit before it
+            _ if symbol == 'E' => match self.digits.chars().last() {
+                Some(previous) => self.digits.push(previous),
+                None => return,
+            },
+            _ => self.digits.push(symbol),
+        }
+
+        self.last = Some(symbol);
+    }
+
+    fn take(&mut self) -> Option<String> {
+        if self.digits.is_empty() {
+            None
+        } else {
+            Some(std::mem::replace(&mut self.digits, String::new()))
+        }
+    }
+
+    /// The digits decoded so far, including the run in progress.
+    fn peek(&self) -> Option<String> {
+        let mut sequence = self.clone();
+        sequence.end_of_run();
+        sequence.current = None;
+        sequence.take()
+    }
+}
+
+fn keep_longest(
+    best: &mut Option<Ident>,
+    candidate: Ident,
+    settings: &Settings,
+) {
+    if candidate.unit.len() < settings.min_digits as usize {
+        return;
+    }
+
+    let longer = match *best {
+        Some(ref best) => candidate.unit.len() > best.unit.len(),
+        None => true,
+    };
+    if longer {
+        *best = Some(candidate);
+    }
+}
+
+/// Find the filter with the most power, returning its index and the
+/// fraction of the block's `power` it accounts for.
+fn strongest(
+    filters: &[Goertzel],
+    samples: usize,
+    power: f64,
+) -> Option<(usize, f64)> {
+    filters
+        .iter()
+        .map(|f| f.rms(samples).powi(2) / power)
+        .enumerate()
+        // a silent block (or a NaN sample) gives 0/0, which matches nothing
+        .filter(|(_, share)| share.is_finite())
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+}
+
+/// Check whether this block is a single 5-tone digit.
+fn tone_digit(
+    filters: &[Goertzel],
+    samples: usize,
+    power: f64,
+) -> Option<char> {
+    match strongest(filters, samples, power)? {
+        // the repeat tone comes after the ten digits
+        (10, share) if share >= PURITY => Some('E'),
+        (ix, share) if share >= PURITY => std::char::from_digit(ix as u32, 10),
+        _ => None,
+    }
+}
+
+const DEFAULT_THRESHOLD: f64 = -40.0;
+const DEFAULT_MIN_DIGITS: u32 = 3;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// The level (in dBFS) the signalling tones must reach to be decoded.
+    threshold: f64,
+    /// The shortest sequence of digits which will be accepted as a unit ID.
+    min_digits: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            threshold: DEFAULT_THRESHOLD,
+            min_digits: DEFAULT_MIN_DIGITS,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 3] = [
+    Property("threshold", |name| {
+        ParamSpec::double(
+            name,
+            "Threshold",
+            "The level (in dBFS) the signalling tones must reach to be decoded",
+            -200.0,
+            0.0,
+            DEFAULT_THRESHOLD,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("min-digits", |name| {
+        ParamSpec::uint(
+            name,
+            "Minimum Digits",
+            "The shortest sequence of digits which will be accepted as a unit \
+             ID. Shorter sequences are usually voice which happened to look \
+             like a tone",
+            1,
+            32,
+            DEFAULT_MIN_DIGITS,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("unit", |name| {
+        ParamSpec::string(
+            name,
+            "Unit",
+            "The unit ID decoded from the current transmission, if there is \
+             one",
+            None,
+            ParamFlags::READABLE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 8_000;
+
+    fn tones(frequencies: &[f64], ms: u32) -> Vec<f32> {
+        let samples = RATE * ms / 1000;
+
+        (0..samples)
+            .map(|n| {
+                let t = f64::from(n) / f64::from(RATE);
+                frequencies
+                    .iter()
+                    .map(|&f| 0.25 * (2.0 * PI * f * t).sin())
+                    .sum::<f64>() as f32
+            })
+            .collect()
+    }
+
+    fn silence(ms: u32) -> Vec<f32> {
+        vec![0.0; (RATE * ms / 1000) as usize]
+    }
+
+    fn dtmf(keys: &str) -> Vec<f32> {
+        let mut audio = Vec::new();
+
+        for key in keys.chars() {
+            let (row, column) = (0..4)
+                .flat_map(|row| (0..4).map(move |column| (row, column)))
+                .find(|&(row, column)| DTMF_KEYS[row][column] == key)
+                .unwrap();
+            audio.extend(tones(&[DTMF_ROWS[row], DTMF_COLUMNS[column]], 60));
+            audio.extend(silence(60));
+        }
+
+        audio
+    }
+
+    /// Send a 5-tone sequence, where `E` is the repeat tone.
+    fn five_tone(table: &[f64; 11], digits: &str, ms: u32) -> Vec<f32> {
+        digits
+            .chars()
+            .flat_map(|digit| {
+                let index = digit.to_digit(10).unwrap_or(10) as usize;
+                tones(&[table[index]], ms)
+            })
+            .collect()
+    }
+
+    fn detect(audio: &[f32], settings: Settings) -> Option<Ident> {
+        let mut detector = Detector::new(settings, RATE);
+        detector.process(&silence(100));
+        detector.process(audio);
+        detector.process(&silence(100));
+        detector.finish()
+    }
+
+    fn ident(unit: &str, format: Format) -> Option<Ident> {
+        Some(Ident {
+            unit: unit.to_string(),
+            format,
+        })
+    }
+
+    #[test]
+    fn dtmf_keypad() {
+        assert_eq!(
+            detect(&dtmf("1234*#0D"), Settings::default()),
+            ident("1234*#0D", Format::Dtmf)
+        );
+    }
+
+    #[test]
+    fn zvei_sequence() {
+        assert_eq!(
+            detect(&five_tone(&ZVEI, "12345", 70), Settings::default()),
+            ident("12345", Format::Zvei)
+        );
+    }
+
+    #[test]
+    fn ccir_sequence() {
+        assert_eq!(
+            detect(&five_tone(&CCIR, "90817", 100), Settings::default()),
+            ident("90817", Format::Ccir)
+        );
+    }
+
+    #[test]
+    fn the_repeat_tone_stands_in_for_the_digit_before() {
+        assert_eq!(
+            detect(&five_tone(&ZVEI, "1E2E3", 70), Settings::default()),
+            ident("11223", Format::Zvei)
+        );
+        assert_eq!(
+            detect(&five_tone(&CCIR, "5E5E5", 100), Settings::default()),
+            ident("55555", Format::Ccir)
+        );
+    }
+
+    #[test]
+    fn short_sequences_are_ignored() {
+        assert_eq!(detect(&dtmf("12"), Settings::default()), None);
+
+        let settings = Settings {
+            min_digits: 2,
+            ..Settings::default()
+        };
+        assert_eq!(detect(&dtmf("12"), settings), ident("12", Format::Dtmf));
+
+        let settings = Settings {
+            min_digits: 6,
+            ..Settings::default()
+        };
+        let audio = five_tone(&ZVEI, "12345", 70);
+        assert_eq!(detect(&audio, settings), None);
+    }
+
+    #[test]
+    fn quiet_tones_are_ignored() {
+        let audio: Vec<f32> = dtmf("1234").iter().map(|x| x / 1000.0).collect();
+
+        assert_eq!(detect(&audio, Settings::default()), None);
+    }
+
+    #[test]
+    fn tones_need_to_last_long_enough() {
+        let mut sequence = Sequence::new(Format::Zvei);
+        let min_blocks = Format::Zvei.min_blocks() as usize;
+
+        // a tone which is too short is dropped
+        for symbol in vec![Some('1'); min_blocks - 1] {
+            assert_eq!(sequence.push(symbol), None);
+        }
+        for symbol in vec![Some('2'); min_blocks] {
+            assert_eq!(sequence.push(symbol), None);
+        }
+        assert_eq!(sequence.peek(), Some("2".to_string()));
+    }
+
+    #[test]
+    fn a_glitch_in_the_middle_of_a_tone_doesnt_repeat_it() {
+        let mut sequence = Sequence::new(Format::Zvei);
+        let blocks = Format::Zvei.min_blocks() as usize;
+
+        let mut symbols = vec![Some('1'); blocks];
+        symbols.push(None);
+        symbols.extend(vec![Some('1'); blocks]);
+        symbols.extend(vec![Some('2'); blocks]);
+        for symbol in symbols {
+            assert_eq!(sequence.push(symbol), None);
+        }
+
+        assert_eq!(sequence.peek(), Some("12".to_string()));
+    }
+
+    #[test]
+    fn a_repeat_tone_needs_a_digit_before_it() {
+        let mut sequence = Sequence::new(Format::Ccir);
+        let blocks = Format::Ccir.min_blocks() as usize;
+
+        let mut symbols = vec![Some('E'); blocks];
+        symbols.extend(vec![Some('3'); blocks]);
+        symbols.extend(vec![Some('E'); blocks]);
+        for symbol in symbols {
+            sequence.push(symbol);
+        }
+
+        assert_eq!(sequence.peek(), Some("33".to_string()));
+    }
+
+    #[test]
+    fn sequences_end_after_a_gap() {
+        let mut sequence = Sequence::new(Format::Dtmf);
+
+        for _ in 0..Format::Dtmf.min_blocks() {
+            sequence.push(Some('7'));
+        }
+        let gap = Format::Dtmf.max_gap();
+        for _ in 0..gap {
+            assert_eq!(sequence.push(None), None);
+        }
+
+        assert_eq!(sequence.push(None), Some("7".to_string()));
+        assert_eq!(sequence.peek(), None);
+    }
+}
@@ -0,0 +1,59 @@
+use std::f64::consts::{PI, SQRT_2};
+
+/// A 2nd-order Butterworth filter.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    pub fn low_pass(cutoff: f64, rate: f64) -> Biquad {
+        let k = (PI * cutoff / rate).tan();
+        let norm = 1.0 / (1.0 + SQRT_2 * k + k * k);
+        let b0 = k * k * norm;
+
+        Biquad::new(
+            [b0, 2.0 * b0, b0],
+            [
+                2.0 * (k * k - 1.0) * norm,
+                (1.0 - SQRT_2 * k + k * k) * norm,
+            ],
+        )
+    }
+
+    pub fn high_pass(cutoff: f64, rate: f64) -> Biquad {
+        let k = (PI * cutoff / rate).tan();
+        let norm = 1.0 / (1.0 + SQRT_2 * k + k * k);
+
+        Biquad::new(
+            [norm, -2.0 * norm, norm],
+            [
+                2.0 * (k * k - 1.0) * norm,
+                (1.0 - SQRT_2 * k + k * k) * norm,
+            ],
+        )
+    }
+
+    fn new(b: [f64; 3], a: [f64; 2]) -> Biquad {
+        Biquad {
+            b,
+            a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    pub fn push(&mut self, sample: f64) -> f64 {
+        let y =
+            self.b[0] * sample + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+                - self.a[0] * self.y[0]
+                - self.a[1] * self.y[1];
+
+        self.x = [sample, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
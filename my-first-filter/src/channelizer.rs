@@ -0,0 +1,884 @@
+use crate::{
+    demod::{Demodulator, Mode},
+    iq::{self, IQ_FORMAT, IQ_MEDIA_TYPE},
+    pfb::PolyphaseChannelizer,
+    timestamp::samples_to_nanoseconds,
+};
+use byte_slice_cast::{AsMutSliceOf, AsSliceOf};
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, Caps, ClockTime, CoreError, DebugCategory, DebugColorFlags,
+    Element, Event, EventType, EventView, FlowError, FlowSuccess, IntRange,
+    LibraryError, Pad, PadDirection, PadPresence, PadTemplate, Plugin, Rank,
+};
+use gstreamer_audio::AUDIO_FORMAT_F32;
+use std::sync::Mutex;
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rschannelizer",
+        Rank::None,
+        Channelizer::get_type(),
+    )
+}
+
+/// Demodulates several narrowband FM (or AM or SSB) channels from one
+/// wideband IQ stream (e.g. from `rsiqsrc`), using a polyphase filter bank.
+///
+/// Request a `src_<frequency>` pad for each channel, where the frequency is
+/// in Hz and the SDR was tuned to `center-frequency`. The `mode`,
+/// `bandwidth`, `deviation`, `squelch` and `audio-rate` properties apply to
+/// every channel, just like on `rsnbfmdemod`.
+///
+/// Channels need to sit comfortably inside the band the SDR is receiving,
+/// so asking for one too close to (or past) its edges is an error rather
+/// than picking up whatever aliases onto it.
+pub struct Channelizer {
+    cat: DebugCategory,
+    sinkpad: Pad,
+    /// Every src pad, along with the frequency (in Hz) it is tuned to.
+    srcpads: Mutex<Vec<(Pad, u32)>>,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Channelizer {
+    fn sink_chain(
+        &self,
+        _pad: &Pad,
+        element: &Element,
+        buffer: Buffer,
+    ) -> Result<FlowSuccess, FlowError> {
+        let outputs = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Negotiation,
+                    ["Have no caps yet"]
+                );
+                FlowError::NotNegotiated
+            })?;
+
+            let map = buffer.map_readable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer readable"]
+                );
+                FlowError::Error
+            })?;
+            let values = map.as_slice_of::<f32>().map_err(|_| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Buffer isn't a whole number of samples"]
+                );
+                FlowError::Error
+            })?;
+
+            if state.start.is_none() {
+                state.start = Some(buffer.get_pts().nseconds().unwrap_or(0));
+            }
+
+            state.process(values);
+            state.take_outputs()
+        };
+
+        let mut result = Ok(FlowSuccess::Ok);
+        let mut linked = outputs.is_empty();
+
+        for (pad, caps, audio, pts) in outputs {
+            if let Some(caps) = caps {
+                pad.push_event(Event::new_caps(&caps).build());
+            }
+
+            if audio.is_empty() {
+                linked |= pad.is_linked();
+                continue;
+            }
+
+            match pad.push(audio_buffer(element, &audio, pts)?) {
+                Ok(_) => linked = true,
+                Err(FlowError::NotLinked) => {},
+                Err(e) => {
+                    gst_debug!(
+                        self.cat,
+                        obj: element,
+                        "Pushing to {} failed: {:?}",
+                        pad.get_name(),
+                        e
+                    );
+                    result = Err(e);
+                },
+            }
+        }
+
+        // only give up once nobody is listening to any of the channels
+        match result {
+            Ok(_) if !linked => Err(FlowError::NotLinked),
+            other => other,
+        }
+    }
+
+    fn sink_event(&self, _pad: &Pad, element: &Element, event: Event) -> bool {
+        match event.view() {
+            EventView::Caps(c) => {
+                let rate = match c
+                    .get_caps()
+                    .get_structure(0)
+                    .and_then(|s| s.get_some::<i32>("rate").ok())
+                {
+                    Some(rate) if rate > 0 => rate as u32,
+                    _ => return false,
+                };
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Configured for caps {}",
+                    c.get_caps()
+                );
+
+                let settings = *self.settings.lock().unwrap();
+                let srcpads = self.srcpads.lock().unwrap().clone();
+                let state = match State::new(rate, settings, &srcpads) {
+                    Ok(state) => state,
+                    Err(e) => {
+                        gst_element_error!(
+                            element,
+                            LibraryError::Settings,
+                            ["{}", e]
+                        );
+                        return false;
+                    },
+                };
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Split into {} channels, {} Hz apart",
+                    state.filter_bank.channel_count(),
+                    state.filter_bank.spacing()
+                );
+                *self.state.lock().unwrap() = Some(state);
+
+                // we output audio, not IQ samples
+                let caps = audio_caps(settings.audio_rate);
+                return srcpads.iter().fold(true, |ok, (pad, _)| {
+                    pad.push_event(Event::new_caps(&caps).build()) && ok
+                });
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.reset();
+                }
+            },
+            _ => {},
+        }
+
+        let srcpads = self.srcpads.lock().unwrap().clone();
+        srcpads
+            .iter()
+            .fold(true, |ok, (pad, _)| pad.push_event(event.clone()) && ok)
+    }
+
+    fn src_event(&self, _pad: &Pad, _element: &Element, event: Event) -> bool {
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectSubclass for Channelizer {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = Element;
+
+    const NAME: &'static str = "RsChannelizer";
+
+    glib_object_subclass!();
+
+    fn new_with_class(klass: &ClassStruct<Self>) -> Self {
+        let templ = klass.get_pad_template("sink").unwrap();
+        let sinkpad = Pad::new_from_template(&templ, Some("sink"));
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Channelizer::catch_panic_pad_function(
+                parent,
+                || Err(FlowError::Error),
+                |channelizer, element| {
+                    channelizer.sink_chain(pad, element, buffer)
+                },
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Channelizer::catch_panic_pad_function(
+                parent,
+                || false,
+                |channelizer, element| {
+                    channelizer.sink_event(pad, element, event)
+                },
+            )
+        });
+
+        Self {
+            cat: DebugCategory::new(
+                "rschannelizer",
+                DebugColorFlags::empty(),
+                Some("Rust polyphase NBFM channelizer"),
+            ),
+            sinkpad,
+            srcpads: Mutex::new(Vec::new()),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "NBFM Channelizer",
+            "Filter/Converter/RF/Audio",
+            "Demodulates many narrowband FM (or AM or SSB) channels from one \
+             IQ stream",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src_%u",
+            PadDirection::Src,
+            PadPresence::Request,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let caps = Caps::new_simple(
+            IQ_MEDIA_TYPE,
+            &[
+                ("format", &IQ_FORMAT),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+            ],
+        );
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+    }
+}
+
+impl ObjectImpl for Channelizer {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+
+        let element = obj.downcast_ref::<Element>().unwrap();
+        element.add_pad(&self.sinkpad).unwrap();
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<Element>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("center-frequency", ..) => {
+                let center_frequency = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing center-frequency from {} to {}",
+                    settings.center_frequency,
+                    center_frequency
+                );
+                settings.center_frequency = center_frequency;
+            },
+            Property("mode", ..) => {
+                let name: String = value
+                    .get()
+                    .unwrap()
+                    .unwrap_or_else(|| DEFAULT_MODE.to_string());
+                match Mode::from_name(&name) {
+                    Some(mode) => {
+                        gst_info!(
+                            self.cat,
+                            obj: element,
+                            "Changing mode from {} to {}",
+                            settings.mode.name(),
+                            mode.name()
+                        );
+                        settings.mode = mode;
+                    },
+                    None => gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Ignoring unknown mode, \"{}\"",
+                        name
+                    ),
+                }
+            },
+            Property("bandwidth", ..) => {
+                let bandwidth = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing bandwidth from {} to {}",
+                    settings.bandwidth,
+                    bandwidth
+                );
+                settings.bandwidth = bandwidth;
+            },
+            Property("deviation", ..) => {
+                let deviation = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing deviation from {} to {}",
+                    settings.deviation,
+                    deviation
+                );
+                settings.deviation = deviation;
+            },
+            Property("squelch", ..) => {
+                let squelch = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing squelch from {} to {}",
+                    settings.squelch,
+                    squelch
+                );
+                settings.squelch = squelch;
+            },
+            Property("audio-rate", ..) => {
+                let audio_rate = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing audio-rate from {} to {}",
+                    settings.audio_rate,
+                    audio_rate
+                );
+                settings.audio_rate = audio_rate;
+            },
+            _ => unimplemented!(),
+        }
+
+        // retune straight away, sending new caps if the audio rate changed
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            if let Err(e) = state.reconfigure(*settings) {
+                gst_element_error!(element, LibraryError::Settings, ["{}", e]);
+            }
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("center-frequency", ..) => {
+                Ok(settings.center_frequency.to_value())
+            },
+            Property("mode", ..) => Ok(settings.mode.name().to_value()),
+            Property("bandwidth", ..) => Ok(settings.bandwidth.to_value()),
+            Property("deviation", ..) => Ok(settings.deviation.to_value()),
+            Property("squelch", ..) => Ok(settings.squelch.to_value()),
+            Property("audio-rate", ..) => Ok(settings.audio_rate.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Channelizer {
+    fn request_new_pad(
+        &self,
+        element: &Element,
+        templ: &PadTemplate,
+        name: Option<String>,
+        _caps: Option<&Caps>,
+    ) -> Option<Pad> {
+        let name = match name {
+            Some(name) => name,
+            None => {
+                gst_error!(
+                    self.cat,
+                    obj: element,
+                    "Pads must be requested by name, e.g. \"src_155200000\""
+                );
+                return None;
+            },
+        };
+        let frequency = match frequency_of(&name) {
+            Some(frequency) => frequency,
+            None => {
+                gst_error!(
+                    self.cat,
+                    obj: element,
+                    "Unable to get a frequency from \"{}\"",
+                    name
+                );
+                return None;
+            },
+        };
+
+        // refuse the pad up front rather than making a channel which would
+        // pick up some other part of the band
+        if let Some(ref state) = *self.state.lock().unwrap() {
+            if let Err(e) = state.tune(frequency) {
+                gst_element_error!(element, LibraryError::Settings, ["{}", e]);
+                return None;
+            }
+        }
+
+        let pad = Pad::new_from_template(templ, Some(&name));
+        pad.set_event_function(|pad, parent, event| {
+            Channelizer::catch_panic_pad_function(
+                parent,
+                || false,
+                |channelizer, element| {
+                    channelizer.src_event(pad, element, event)
+                },
+            )
+        });
+        element.add_pad(&pad).ok()?;
+
+        self.srcpads.lock().unwrap().push((pad.clone(), frequency));
+
+        // the new pad needs to catch up on anything the others have already
+        // been sent
+        let stream_start =
+            self.sinkpad.get_sticky_event(EventType::StreamStart, 0);
+        if let Some(event) = stream_start {
+            let _ = pad.store_sticky_event(&event);
+        }
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            let caps = audio_caps(state.settings.audio_rate);
+            let _ = pad.store_sticky_event(&Event::new_caps(&caps).build());
+            if let Some(event) =
+                self.sinkpad.get_sticky_event(EventType::Segment, 0)
+            {
+                let _ = pad.store_sticky_event(&event);
+            }
+
+            if let Err(e) = state.add_channel(pad.clone(), frequency) {
+                gst_element_error!(element, LibraryError::Settings, ["{}", e]);
+            }
+        }
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Added {} for {} Hz",
+            pad.get_name(),
+            frequency
+        );
+
+        Some(pad)
+    }
+
+    fn release_pad(&self, element: &Element, pad: &Pad) {
+        self.srcpads.lock().unwrap().retain(|(p, _)| p != pad);
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.channels.retain(|c| &c.pad != pad);
+        }
+
+        let _ = element.remove_pad(pad);
+    }
+}
+
+/// Get the frequency from a pad name like `src_155200000`.
+fn frequency_of(name: &str) -> Option<u32> {
+    let mut parts = name.splitn(2, '_');
+    match (parts.next(), parts.next()) {
+        (Some("src"), Some(frequency)) => frequency.parse().ok(),
+        _ => None,
+    }
+}
+
+fn audio_caps(rate: u32) -> Caps {
+    Caps::new_simple(
+        "audio/x-raw",
+        &[
+            ("format", &AUDIO_FORMAT_F32.to_string()),
+            ("rate", &(rate as i32)),
+            ("channels", &1),
+            ("layout", &"interleaved"),
+        ],
+    )
+}
+
+fn audio_buffer(
+    element: &Element,
+    audio: &[f32],
+    pts: u64,
+) -> Result<Buffer, FlowError> {
+    let mut buffer =
+        Buffer::with_size(audio.len() * std::mem::size_of::<f32>())
+            .ok_or(FlowError::Error)?;
+    {
+        let buffer = buffer.get_mut().unwrap();
+        buffer.set_pts(ClockTime::from_nseconds(pts));
+
+        let mut map = buffer.map_writable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer writable"]
+            );
+            FlowError::Error
+        })?;
+        map.as_mut_slice_of::<f32>()
+            .map_err(|_| FlowError::Error)?
+            .copy_from_slice(audio);
+    }
+
+    Ok(buffer)
+}
+
+struct State {
+    /// The rate of the incoming IQ samples.
+    input_rate: u32,
+    settings: Settings,
+    filter_bank: PolyphaseChannelizer,
+    channels: Vec<Channel>,
+    /// The timestamp of the first buffer.
+    start: Option<u64>,
+    /// The number of IQ samples seen so far.
+    samples: u64,
+}
+
+impl State {
+    fn new(
+        input_rate: u32,
+        settings: Settings,
+        srcpads: &[(Pad, u32)],
+    ) -> Result<State, String> {
+        let mut state = State {
+            input_rate,
+            settings,
+            filter_bank: PolyphaseChannelizer::new(
+                f64::from(input_rate),
+                settings.bandwidth(),
+            ),
+            channels: Vec::new(),
+            start: None,
+            samples: 0,
+        };
+
+        for (pad, frequency) in srcpads {
+            state.add_channel(pad.clone(), *frequency)?;
+        }
+
+        Ok(state)
+    }
+
+    /// Work out which filter bank channel `frequency` is in, and how far
+    /// from that channel's centre it is.
+    fn tune(&self, frequency: u32) -> Result<(usize, f64), String> {
+        let settings = self.settings;
+        let offset =
+            f64::from(frequency) - f64::from(settings.center_frequency);
+        // pick the filter bank channel which the whole signal (rather than
+        // an SSB signal's suppressed carrier) sits in
+        let centre = settings.mode.centre(settings.bandwidth());
+
+        // anything further out would alias onto a channel at the other end
+        // of the band instead
+        let (bin, residual) = self
+            .filter_bank
+            .nearest_channel(offset + centre)
+            .ok_or_else(|| {
+                let middle = f64::from(settings.center_frequency) - centre;
+                let max_offset = self.filter_bank.max_offset();
+                format!(
+                    "{} Hz is outside the {:.0} to {:.0} Hz which can be \
+                     received at {} samples per second",
+                    frequency,
+                    middle - max_offset,
+                    middle + max_offset,
+                    self.input_rate
+                )
+            })?;
+
+        Ok((bin, residual - centre))
+    }
+
+    fn add_channel(&mut self, pad: Pad, frequency: u32) -> Result<(), String> {
+        let settings = self.settings;
+        let (bin, residual) = self.tune(frequency)?;
+        let demodulator = Demodulator::new(
+            settings.mode,
+            self.filter_bank.output_rate(),
+            residual,
+            settings.bandwidth(),
+            settings.deviation,
+            settings.squelch,
+            f64::from(settings.audio_rate),
+        );
+
+        // a channel added part way through starts from wherever we're up to
+        let samples = self.samples;
+        let input_rate = u64::from(self.input_rate);
+        let start = self
+            .start
+            .map(|start| start + samples_to_nanoseconds(samples, input_rate));
+
+        self.channels.push(Channel {
+            pad,
+            frequency,
+            bin,
+            demodulator,
+            start,
+            samples: 0,
+            pending_caps: None,
+            audio: Vec::new(),
+        });
+
+        Ok(())
+    }
+
+    fn process(&mut self, values: &[f32]) {
+        self.samples += (values.len() / 2) as u64;
+
+        let State {
+            ref mut filter_bank,
+            ref mut channels,
+            ..
+        } = *self;
+
+        let mut sample = Vec::with_capacity(1);
+        for value in iq::to_complex(values) {
+            if !filter_bank.push(value) {
+                continue;
+            }
+
+            for channel in channels.iter_mut() {
+                sample.clear();
+                sample.push(filter_bank.output(channel.bin));
+                channel.demodulator.process(&sample, &mut channel.audio);
+            }
+        }
+    }
+
+    /// Take the audio (and any caps which need to go out first) for each
+    /// channel.
+    fn take_outputs(&mut self) -> Vec<(Pad, Option<Caps>, Vec<f32>, u64)> {
+        let audio_rate = u64::from(self.settings.audio_rate);
+        let start = self.start.unwrap_or(0);
+
+        self.channels
+            .iter_mut()
+            .map(|channel| {
+                // timestamps are worked out by counting samples so rounding
+                // errors don't build up
+                let channel_start = *channel.start.get_or_insert(start);
+                let pts = channel_start
+                    + samples_to_nanoseconds(channel.samples, audio_rate);
+                channel.samples += channel.audio.len() as u64;
+
+                (
+                    channel.pad.clone(),
+                    channel.pending_caps.take(),
+                    channel.audio.drain(..).collect(),
+                    pts,
+                )
+            })
+            .collect()
+    }
+
+    /// Switch to new settings, returning an error for any channels which
+    /// can no longer be received (and are dropped).
+    fn reconfigure(&mut self, settings: Settings) -> Result<(), String> {
+        let previous = std::mem::replace(&mut self.settings, settings);
+        self.filter_bank = PolyphaseChannelizer::new(
+            f64::from(self.input_rate),
+            settings.bandwidth(),
+        );
+
+        let mut result = Ok(());
+        let channels = std::mem::replace(&mut self.channels, Vec::new());
+        for old in channels {
+            if let Err(e) = self.add_channel(old.pad, old.frequency) {
+                result = result.and(Err(e));
+                continue;
+            }
+            let new = self.channels.last_mut().unwrap();
+            new.pending_caps = old.pending_caps;
+
+            if settings.audio_rate == previous.audio_rate {
+                new.start = old.start;
+                new.samples = old.samples;
+            } else {
+                // start counting again from the current position
+                new.start = old.start.map(|start| {
+                    start
+                        + samples_to_nanoseconds(
+                            old.samples,
+                            u64::from(previous.audio_rate),
+                        )
+                });
+                new.pending_caps = Some(audio_caps(settings.audio_rate));
+            }
+        }
+
+        result
+    }
+
+    fn reset(&mut self) {
+        self.filter_bank.reset();
+        self.start = None;
+        self.samples = 0;
+
+        for channel in self.channels.iter_mut() {
+            channel.demodulator.reset();
+            channel.start = None;
+            channel.samples = 0;
+            channel.audio.clear();
+        }
+    }
+}
+
+struct Channel {
+    pad: Pad,
+    frequency: u32,
+    /// Which of the filter bank's channels this one is in.
+    bin: usize,
+    demodulator: Demodulator,
+    /// The timestamp of the first audio sample.
+    start: Option<u64>,
+    /// The number of audio samples sent so far.
+    samples: u64,
+    /// Caps which need to be sent before the next buffer.
+    pending_caps: Option<Caps>,
+    /// Audio which is waiting to be sent.
+    audio: Vec<f32>,
+}
+
+const DEFAULT_CENTER_FREQUENCY: u32 = 0;
+const DEFAULT_MODE: &str = "nbfm";
+const DEFAULT_BANDWIDTH: f64 = 0.0;
+const DEFAULT_DEVIATION: f64 = 2_500.0;
+const DEFAULT_SQUELCH: f64 = -200.0;
+const DEFAULT_AUDIO_RATE: u32 = 16_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// The frequency (in Hz) the SDR was tuned to.
+    center_frequency: u32,
+    mode: Mode,
+    /// The width (in Hz) of each channel, or 0 to use the mode's default.
+    bandwidth: f64,
+    /// The frequency deviation (in Hz) which gives full-scale audio.
+    deviation: f64,
+    /// The carrier level (in dBFS) needed to let audio through.
+    squelch: f64,
+    audio_rate: u32,
+}
+
+impl Settings {
+    fn bandwidth(&self) -> f64 {
+        if self.bandwidth > 0.0 {
+            self.bandwidth
+        } else {
+            self.mode.default_bandwidth()
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            center_frequency: DEFAULT_CENTER_FREQUENCY,
+            mode: Mode::Nbfm,
+            bandwidth: DEFAULT_BANDWIDTH,
+            deviation: DEFAULT_DEVIATION,
+            squelch: DEFAULT_SQUELCH,
+            audio_rate: DEFAULT_AUDIO_RATE,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 6] = [
+    Property("center-frequency", |name| {
+        ParamSpec::uint(
+            name,
+            "Center Frequency",
+            "The frequency (in Hz) the SDR was tuned to",
+            0,
+            std::u32::MAX,
+            DEFAULT_CENTER_FREQUENCY,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("mode", |name| {
+        ParamSpec::string(
+            name,
+            "Mode",
+            "How the audio is carried (nbfm, am, usb or lsb)",
+            Some(DEFAULT_MODE),
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("bandwidth", |name| {
+        ParamSpec::double(
+            name,
+            "Bandwidth",
+            "The width (in Hz) of each channel, or 0 for the mode's usual \
+             bandwidth",
+            0.0,
+            std::f64::MAX,
+            DEFAULT_BANDWIDTH,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("deviation", |name| {
+        ParamSpec::double(
+            name,
+            "Deviation",
+            "The frequency deviation (in Hz) which gives full-scale audio",
+            1.0,
+            std::f64::MAX,
+            DEFAULT_DEVIATION,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("squelch", |name| {
+        ParamSpec::double(
+            name,
+            "Squelch",
+            "The carrier level (in dBFS) needed to let audio through",
+            -200.0,
+            0.0,
+            DEFAULT_SQUELCH,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("audio-rate", |name| {
+        ParamSpec::uint(
+            name,
+            "Audio Rate",
+            "The sample rate of the demodulated audio",
+            1,
+            std::i32::MAX as u32,
+            DEFAULT_AUDIO_RATE,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
@@ -0,0 +1,614 @@
+use crate::{decibels::to_decibels, goertzel::Goertzel};
+use byte_slice_cast::AsSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags, Element,
+    ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange, Message,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::sync::Mutex;
+use transmission::messages::{CTCSS, TRANSMISSION_END, TRANSMISSION_START};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(Some(plugin), "rsctcss", Rank::None, Ctcss::get_type())
+}
+
+/// The standard (EIA/TIA-603) CTCSS tone frequencies, in Hz.
+pub const TONES: [f64; 51] = [
+    67.0, 69.3, 71.9, 74.4, 77.0, 79.7, 82.5, 85.4, 88.5, 91.5, 94.8, 97.4,
+    100.0, 103.5, 107.2, 110.9, 114.8, 118.8, 123.0, 127.3, 131.8, 136.5,
+    141.3, 146.2, 150.0, 151.4, 156.7, 159.8, 162.2, 165.5, 167.9, 171.3,
+    173.8, 177.3, 179.9, 183.5, 186.2, 189.9, 192.8, 196.6, 199.5, 203.5,
+    206.5, 210.7, 218.1, 225.7, 229.1, 233.6, 241.8, 250.3, 254.1,
+];
+
+/// A passthrough audio element which figures out which CTCSS tone (if any)
+/// was used for each transmission.
+///
+/// This needs to go after a `rssquelch`. Whenever the end of a transmission
+/// passes through, a `ctcss` element message with the transmission's
+/// `sequence`, the tone's `frequency` (in Hz) and its `level` (in dBFS) is
+/// posted on the bus.
+pub struct Ctcss {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Ctcss {
+    fn on_transmission_start(&self, element: &BaseTransform, sequence: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(ref mut state) = *state {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Listening for a tone in transmission {}",
+                sequence
+            );
+            state.sequence = Some(sequence);
+            state.detector.reset();
+        }
+    }
+
+    fn on_transmission_end(&self, element: &BaseTransform, sequence: u32) {
+        let detected = {
+            let mut state = self.state.lock().unwrap();
+            match *state {
+                Some(ref mut state) if state.sequence == Some(sequence) => {
+                    state.sequence = None;
+                    state.detector.finish()
+                },
+                _ => None,
+            }
+        };
+
+        let tone = match detected {
+            Some(tone) => tone,
+            None => {
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "No tone detected in transmission {}",
+                    sequence
+                );
+                return;
+            },
+        };
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Transmission {} used a {} Hz tone ({:.1} dBFS)",
+            sequence,
+            tone.frequency,
+            tone.level
+        );
+
+        let structure = Structure::new(
+            CTCSS,
+            &[
+                ("sequence", &sequence),
+                ("frequency", &tone.frequency),
+                ("level", &tone.level),
+            ],
+        );
+        let msg = Message::new_element(structure).src(Some(element)).build();
+        let _ = element.post_message(&msg);
+    }
+}
+
+impl ObjectSubclass for Ctcss {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsCtcss";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsctcss",
+                DebugColorFlags::empty(),
+                Some("Rust CTCSS tone detector"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "CTCSS Detector",
+            "Filter/Analyzer/Audio",
+            "Detects the CTCSS tone used by each radio transmission",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for Ctcss {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("threshold", ..) => {
+                let threshold = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing threshold from {} to {}",
+                    settings.threshold,
+                    threshold
+                );
+                settings.threshold = threshold;
+            },
+            Property("window", ..) => {
+                let window = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing window from {} to {}",
+                    settings.window,
+                    window
+                );
+                settings.window = window;
+            },
+            _ => unimplemented!(),
+        }
+
+        // make sure the detector picks up the new settings
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.detector.settings = *settings;
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("threshold", ..) => Ok(settings.threshold.to_value()),
+            Property("window", ..) => Ok(settings.window.to_value()),
+            Property("tone", ..) => {
+                let tone = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .and_then(|s| s.detector.current())
+                    .map(|tone| tone.frequency)
+                    .unwrap_or(0.0);
+                Ok(tone.to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Ctcss {}
+
+impl BaseTransformImpl for Ctcss {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            Some(ref mut state) => state.detector.reconfigure(info.rate()),
+            None => {
+                *state = Some(State {
+                    detector: Detector::new(settings, info.rate()),
+                    sequence: None,
+                })
+            },
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        match event.view() {
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+
+                    if s.get_name() == TRANSMISSION_START {
+                        self.on_transmission_start(element, sequence);
+                    } else if s.get_name() == TRANSMISSION_END {
+                        // post the tone before the end goes downstream, so it
+                        // arrives before the transmission is written out
+                        self.on_transmission_end(element, sequence);
+                    }
+                }
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.detector.reset();
+                    state.sequence = None;
+                }
+            },
+            _ => {},
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no state yet"]
+            );
+            FlowError::NotNegotiated
+        })?;
+
+        // there's no point looking for tones between transmissions
+        if state.sequence.is_none() {
+            return Ok(FlowSuccess::Ok);
+        }
+
+        let map = buf.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer readable"]
+            );
+            FlowError::Error
+        })?;
+        let samples = map.as_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        state.detector.process(samples);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+struct State {
+    detector: Detector,
+    /// The transmission we're currently listening to.
+    sequence: Option<u32>,
+}
+
+/// A tone which was detected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Tone {
+    frequency: f64,
+    /// The tone's RMS level, in dBFS.
+    level: f64,
+}
+
+/// The part of the element which actually looks at samples.
+///
+/// A [Goertzel filter][goertzel] is run for every standard tone over
+/// fixed-size blocks of audio. The strongest tone in each block gets a vote
+/// (provided it's above the threshold) and whichever tone got a majority of
+/// the votes over the whole transmission wins. Voice has a lot of energy in
+/// the same range as CTCSS, but unlike a tone it moves around, so it rarely
+/// wins more than a couple of blocks.
+///
+/// [goertzel]: https://en.wikipedia.org/wiki/Goertzel_algorithm
+#[derive(Debug)]
+struct Detector {
+    settings: Settings,
+    rate: u32,
+    filters: Vec<Goertzel>,
+    /// The number of samples seen so far in this block.
+    samples: usize,
+    /// How many blocks each tone has won in this transmission.
+    votes: Vec<u32>,
+    /// The sum of each tone's level across the blocks it won, used to
+    /// report the average level.
+    levels: Vec<f64>,
+    /// The number of blocks seen so far in this transmission.
+    blocks: u32,
+}
+
+impl Detector {
+    fn new(settings: Settings, rate: u32) -> Detector {
+        Detector {
+            settings,
+            rate,
+            filters: TONES
+                .iter()
+                .map(|&frequency| Goertzel::new(frequency, rate))
+                .collect(),
+            samples: 0,
+            votes: vec![0; TONES.len()],
+            levels: vec![0.0; TONES.len()],
+            blocks: 0,
+        }
+    }
+
+    fn reconfigure(&mut self, rate: u32) {
+        *self = Detector::new(self.settings, rate);
+    }
+
+    fn reset(&mut self) {
+        self.filters.iter_mut().for_each(Goertzel::reset);
+        self.samples = 0;
+        self.votes.iter_mut().for_each(|v| *v = 0);
+        self.levels.iter_mut().for_each(|l| *l = 0.0);
+        self.blocks = 0;
+    }
+
+    fn block_size(&self) -> usize {
+        std::cmp::max(
+            1,
+            self.rate as usize * self.settings.window as usize / 1000,
+        )
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        let block_size = self.block_size();
+
+        for &sample in samples {
+            let sample = f64::from(sample);
+            self.filters.iter_mut().for_each(|f| f.push(sample));
+            self.samples += 1;
+
+            if self.samples >= block_size {
+                self.end_of_block();
+            }
+        }
+    }
+
+    fn end_of_block(&mut self) {
+        let samples = self.samples;
+        let strongest = self
+            .filters
+            .iter()
+            .map(|f| to_decibels(f.rms(samples)))
+            .enumerate()
+            // garbage in (e.g. a NaN sample) shouldn't take the pipeline down
+            .filter(|(_, level)| level.is_finite())
+            .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+        if let Some((ix, level)) = strongest {
+            if level >= self.settings.threshold {
+                self.votes[ix] += 1;
+                self.levels[ix] += level;
+            }
+        }
+
+        self.filters.iter_mut().for_each(Goertzel::reset);
+        self.samples = 0;
+        self.blocks += 1;
+    }
+
+    /// The tone which has a majority of the votes so far.
+    fn current(&self) -> Option<Tone> {
+        let (ix, &votes) =
+            self.votes.iter().enumerate().max_by_key(|(_, &v)| v)?;
+
+        if votes == 0 || votes * 2 <= self.blocks {
+            return None;
+        }
+
+        Some(Tone {
+            frequency: TONES[ix],
+            level: self.levels[ix] / f64::from(votes),
+        })
+    }
+
+    /// The transmission has finished, decide which tone it used.
+    fn finish(&mut self) -> Option<Tone> {
+        // a partial block still counts if it's long enough to tell tones
+        // apart
+        if self.samples >= self.block_size() / 2 {
+            self.end_of_block();
+        }
+
+        let tone = self.current();
+        self.reset();
+        tone
+    }
+}
+
+const DEFAULT_THRESHOLD: f64 = -45.0;
+const DEFAULT_WINDOW: u32 = 500;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// The level (in dBFS) a tone must reach to be detected.
+    threshold: f64,
+    /// The length of each analysis block (in milliseconds).
+    window: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            threshold: DEFAULT_THRESHOLD,
+            window: DEFAULT_WINDOW,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 3] = [
+    Property("threshold", |name| {
+        ParamSpec::double(
+            name,
+            "Threshold",
+            "The level (in dBFS) a tone must reach to be detected",
+            -200.0,
+            0.0,
+            DEFAULT_THRESHOLD,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("window", |name| {
+        ParamSpec::uint(
+            name,
+            "Window",
+            "The length (in ms) of each block of audio checked for a tone. \
+             Adjacent tones are only a couple of Hz apart, so this needs to \
+             be fairly long",
+            100,
+            5000,
+            DEFAULT_WINDOW,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("tone", |name| {
+        ParamSpec::double(
+            name,
+            "Tone",
+            "The CTCSS tone (in Hz) detected in the current transmission, or \
+             0 if there isn't one",
+            0.0,
+            300.0,
+            0.0,
+            ParamFlags::READABLE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 8_000;
+
+    /// Two seconds of someone talking, with an optional tone underneath.
+    fn transmission(tone: Option<f64>) -> Vec<f32> {
+        (0..2 * RATE)
+            .map(|n| {
+                let t = f64::from(n) / f64::from(RATE);
+                let voice = 0.3 * (2.0 * PI * 1_000.0 * t).sin()
+                    + 0.2 * (2.0 * PI * 450.0 * t).sin();
+                let tone =
+                    tone.map(|f| 0.1 * (2.0 * PI * f * t).sin()).unwrap_or(0.0);
+                (voice + tone) as f32
+            })
+            .collect()
+    }
+
+    fn detect(audio: &[f32]) -> Option<Tone> {
+        let mut detector = Detector::new(Settings::default(), RATE);
+        detector.process(audio);
+        detector.finish()
+    }
+
+    #[test]
+    fn tell_neighbouring_tones_apart() {
+        for &frequency in &[100.0, 103.5] {
+            let tone = detect(&transmission(Some(frequency))).unwrap();
+
+            assert_eq!(tone.frequency, frequency);
+            // a sine wave's RMS level is 3 dB below its peak
+            let expected = 20.0 * 0.1_f64.log10() - 3.0;
+            assert!((tone.level - expected).abs() < 0.5, "{:?}", tone);
+        }
+    }
+
+    #[test]
+    fn no_tone() {
+        assert_eq!(detect(&transmission(None)), None);
+        assert_eq!(detect(&vec![0.0; 2 * RATE as usize]), None);
+    }
+
+    #[test]
+    fn quiet_tones_are_ignored() {
+        let audio: Vec<f32> = transmission(Some(100.0))
+            .iter()
+            .map(|x| x / 1000.0)
+            .collect();
+
+        assert_eq!(detect(&audio), None);
+    }
+
+    #[test]
+    fn a_tone_needs_most_of_the_transmission() {
+        let mut audio = transmission(Some(100.0));
+        audio.truncate(RATE as usize / 2);
+        audio.extend(transmission(None));
+
+        assert_eq!(detect(&audio), None);
+    }
+}
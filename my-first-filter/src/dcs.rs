@@ -0,0 +1,746 @@
+use crate::biquad::Biquad;
+use byte_slice_cast::AsSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags, Element,
+    ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange, Message,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::{collections::HashMap, f64::consts::PI, sync::Mutex};
+use transmission::messages::{DCS, TRANSMISSION_END, TRANSMISSION_START};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(Some(plugin), "rsdcs", Rank::None, Dcs::get_type())
+}
+
+/// The standard DCS codes, written in octal.
+pub const CODES: [u16; 104] = [
+    0o023, 0o025, 0o026, 0o031, 0o032, 0o036, 0o043, 0o047, 0o051, 0o053,
+    0o054, 0o065, 0o071, 0o072, 0o073, 0o074, 0o114, 0o115, 0o116, 0o122,
+    0o125, 0o131, 0o132, 0o134, 0o143, 0o145, 0o152, 0o155, 0o156, 0o162,
+    0o165, 0o172, 0o174, 0o205, 0o212, 0o223, 0o225, 0o226, 0o243, 0o244,
+    0o245, 0o246, 0o251, 0o252, 0o255, 0o261, 0o263, 0o265, 0o266, 0o271,
+    0o274, 0o306, 0o311, 0o315, 0o325, 0o331, 0o332, 0o343, 0o346, 0o351,
+    0o356, 0o364, 0o365, 0o371, 0o411, 0o412, 0o413, 0o423, 0o431, 0o432,
+    0o445, 0o446, 0o452, 0o454, 0o455, 0o462, 0o464, 0o465, 0o466, 0o503,
+    0o506, 0o516, 0o523, 0o526, 0o532, 0o546, 0o565, 0o606, 0o612, 0o624,
+    0o627, 0o631, 0o632, 0o654, 0o662, 0o664, 0o703, 0o712, 0o723, 0o731,
+    0o732, 0o734, 0o743, 0o754,
+];
+
+/// The DCS bit rate, in bits per second.
+const BAUD: f64 = 134.4;
+/// The cut-off frequency (in Hz) used when separating DCS from voice.
+const CUTOFF: f64 = 250.0;
+
+/// A passthrough audio element which decodes the DCS (Digital-Coded
+/// Squelch) code used by each transmission.
+///
+/// This needs to go after a `rssquelch`. Whenever the end of a transmission
+/// passes through, a `dcs` element message with the transmission's
+/// `sequence`, the `code` (e.g. `"D023N"`) and whether it was `inverted` is
+/// posted on the bus.
+pub struct Dcs {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Dcs {
+    fn on_transmission_start(&self, element: &BaseTransform, sequence: u32) {
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Listening for a code in transmission {}",
+                sequence
+            );
+            state.sequence = Some(sequence);
+            state.decoder.reset();
+        }
+    }
+
+    fn on_transmission_end(&self, element: &BaseTransform, sequence: u32) {
+        let detected = {
+            let mut state = self.state.lock().unwrap();
+            match *state {
+                Some(ref mut state) if state.sequence == Some(sequence) => {
+                    state.sequence = None;
+                    state.decoder.finish()
+                },
+                _ => None,
+            }
+        };
+
+        let code = match detected {
+            Some(code) => code,
+            None => {
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "No code detected in transmission {}",
+                    sequence
+                );
+                return;
+            },
+        };
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Transmission {} used {}",
+            sequence,
+            code
+        );
+
+        let structure = Structure::new(
+            DCS,
+            &[
+                ("sequence", &sequence),
+                ("code", &code.to_string()),
+                ("inverted", &code.inverted),
+            ],
+        );
+        let msg = Message::new_element(structure).src(Some(element)).build();
+        let _ = element.post_message(&msg);
+    }
+}
+
+impl ObjectSubclass for Dcs {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsDcs";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsdcs",
+                DebugColorFlags::empty(),
+                Some("Rust DCS decoder"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "DCS Decoder",
+            "Filter/Analyzer/Audio",
+            "Decodes the DCS code used by each radio transmission",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for Dcs {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("min-matches", ..) => {
+                let min_matches = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing min-matches from {} to {}",
+                    settings.min_matches,
+                    min_matches
+                );
+                settings.min_matches = min_matches;
+            },
+            _ => unimplemented!(),
+        }
+
+        // make sure the decoder picks up the new settings
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.decoder.settings = *settings;
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("min-matches", ..) => Ok(settings.min_matches.to_value()),
+            Property("code", ..) => {
+                let code = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .and_then(|s| s.decoder.current())
+                    .map(|code| code.to_string())
+                    .unwrap_or_default();
+                Ok(code.to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Dcs {}
+
+impl BaseTransformImpl for Dcs {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            Some(ref mut state) => state.decoder.reconfigure(info.rate()),
+            None => {
+                *state = Some(State {
+                    decoder: Decoder::new(settings, info.rate()),
+                    sequence: None,
+                })
+            },
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        match event.view() {
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+
+                    if s.get_name() == TRANSMISSION_START {
+                        self.on_transmission_start(element, sequence);
+                    } else if s.get_name() == TRANSMISSION_END {
+                        // post the code before the end goes downstream, so it
+                        // arrives before the transmission is written out
+                        self.on_transmission_end(element, sequence);
+                    }
+                }
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.decoder.reset();
+                    state.sequence = None;
+                }
+            },
+            _ => {},
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no state yet"]
+            );
+            FlowError::NotNegotiated
+        })?;
+
+        // there's no point looking for codes between transmissions
+        if state.sequence.is_none() {
+            return Ok(FlowSuccess::Ok);
+        }
+
+        let map = buf.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer readable"]
+            );
+            FlowError::Error
+        })?;
+        let samples = map.as_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        state.decoder.process(samples);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+struct State {
+    decoder: Decoder,
+    /// The transmission we're currently listening to.
+    sequence: Option<u32>,
+}
+
+/// A decoded DCS code.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+struct Code {
+    /// The code's value (normally written in octal).
+    value: u16,
+    /// Was the code sent with inverted polarity?
+    inverted: bool,
+}
+
+impl std::fmt::Display for Code {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let polarity = if self.inverted { 'I' } else { 'N' };
+        write!(f, "D{:03o}{}", self.value, polarity)
+    }
+}
+
+/// Calculate the 23-bit code word that gets sent for a DCS code.
+///
+/// The word is a Golay (23, 12) code word. The 12 data bits are the 9-bit
+/// code followed by the fixed bits `100`, and are sent first (least
+/// significant bit first), followed by the 11 parity bits.
+fn code_word(code: u16) -> u32 {
+    const GENERATOR: u32 = 0xC75;
+
+    let data = 0x800 | u32::from(code & 0o777);
+    let mut remainder = data << 11;
+    for bit in (11..23).rev() {
+        if remainder & (1 << bit) != 0 {
+            remainder ^= GENERATOR << (bit - 11);
+        }
+    }
+
+    ((remainder & 0x7FF) << 12) | data
+}
+
+/// The part of the element which actually looks at samples.
+///
+/// DCS is sent as a continuously repeating 23-bit code word at 134.4 bits
+/// per second, well below the voice band. The audio is low-pass filtered,
+/// sliced into bits (using the zero crossings to keep the bit clock in
+/// sync), and every time the last 23 bits match the code word for a standard
+/// code (or its inverse) that code gets a vote.
+///
+/// Because the code word is cyclic, some codes are rotations of each other
+/// (e.g. D023N and D047I) and can't be told apart. When that happens normal
+/// polarity is preferred, then the lowest code. Every inverted code turns
+/// out to be a rotation of a normal one, so in practice codes are always
+/// reported with normal polarity.
+#[derive(Debug)]
+struct Decoder {
+    settings: Settings,
+    rate: u32,
+    /// Map from a 23-bit code word back to the code it came from.
+    words: HashMap<u32, Code>,
+    /// Codes which are really rotations of another, mapped to the one which
+    /// should be reported instead.
+    aliases: HashMap<Code, Code>,
+    /// Voice is normally high-pass filtered at 300 Hz before being
+    /// transmitted, so a steep low-pass filter gets rid of most of it.
+    filter: [Biquad; 2],
+    /// A slowly moving average used to remove any DC offset.
+    average: f64,
+    average_alpha: f64,
+    samples_per_bit: f64,
+    /// How far through the current bit we are, in samples.
+    phase: f64,
+    previous: bool,
+    /// The most recently received bits, with the newest in bit 22.
+    bits: u32,
+    bit_count: u32,
+    votes: HashMap<Code, u32>,
+}
+
+impl Decoder {
+    fn new(settings: Settings, rate: u32) -> Decoder {
+        let mut words = HashMap::new();
+        for &value in CODES.iter().rev() {
+            let word = code_word(value);
+            words.insert(
+                word,
+                Code {
+                    value,
+                    inverted: false,
+                },
+            );
+            words.insert(
+                !word & 0x7F_FFFF,
+                Code {
+                    value,
+                    inverted: true,
+                },
+            );
+        }
+
+        let mut aliases = HashMap::new();
+        for (&word, &code) in &words {
+            let preferred = (1..23)
+                .filter_map(|shift| {
+                    let rotated = (word >> shift) | (word << (23 - shift));
+                    words.get(&(rotated & 0x7F_FFFF))
+                })
+                .chain(std::iter::once(&code))
+                .min_by_key(|c| (c.inverted, c.value))
+                .copied()
+                .unwrap_or(code);
+            if preferred != code {
+                aliases.insert(code, preferred);
+            }
+        }
+
+        let rate_f = f64::from(rate);
+
+        Decoder {
+            settings,
+            rate,
+            words,
+            aliases,
+            filter: [Biquad::low_pass(CUTOFF, rate_f); 2],
+            average: 0.0,
+            average_alpha: 1.0 - (-2.0 * PI * 0.5 / rate_f).exp(),
+            samples_per_bit: rate_f / BAUD,
+            phase: 0.0,
+            previous: false,
+            bits: 0,
+            bit_count: 0,
+            votes: HashMap::new(),
+        }
+    }
+
+    fn reconfigure(&mut self, rate: u32) {
+        *self = Decoder::new(self.settings, rate);
+    }
+
+    fn reset(&mut self) {
+        self.filter = [Biquad::low_pass(CUTOFF, f64::from(self.rate)); 2];
+        self.average = 0.0;
+        self.phase = 0.0;
+        self.previous = false;
+        self.bits = 0;
+        self.bit_count = 0;
+        self.votes.clear();
+    }
+
+    fn process(&mut self, samples: &[f32]) {
+        for &sample in samples {
+            let filtered = self
+                .filter
+                .iter_mut()
+                .fold(f64::from(sample), |sample, f| f.push(sample));
+            self.average += self.average_alpha * (filtered - self.average);
+            let bit = filtered > self.average;
+
+            // a transition marks the start of a bit, nudge the clock
+            // towards it
+            if bit != self.previous {
+                let error = if self.phase < self.samples_per_bit / 2.0 {
+                    self.phase
+                } else {
+                    self.phase - self.samples_per_bit
+                };
+                self.phase -= error * 0.25;
+                self.previous = bit;
+            }
+
+            let middle = self.samples_per_bit / 2.0;
+            let before = self.phase;
+            self.phase += 1.0;
+
+            if before < middle && self.phase >= middle {
+                self.push_bit(bit);
+            }
+            if self.phase >= self.samples_per_bit {
+                self.phase -= self.samples_per_bit;
+            }
+        }
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        self.bits = (self.bits >> 1) | (u32::from(bit) << 22);
+        self.bit_count += 1;
+
+        if self.bit_count < 23 {
+            return;
+        }
+
+        if let Some(&code) = self.words.get(&self.bits) {
+            *self.votes.entry(code).or_insert(0) += 1;
+        }
+    }
+
+    /// The code with the most votes so far.
+    fn current(&self) -> Option<Code> {
+        // a code and its aliases are seen equally often (give or take one,
+        // depending on where the transmission stopped), so they're counted
+        // as one
+        let mut votes: HashMap<Code, u32> = HashMap::new();
+        for (code, &count) in &self.votes {
+            let code = self.aliases.get(code).unwrap_or(code);
+            let entry = votes.entry(*code).or_insert(0);
+            *entry = (*entry).max(count);
+        }
+
+        let mut candidates: Vec<_> = votes
+            .into_iter()
+            .filter(|&(_, votes)| votes >= self.settings.min_matches)
+            .collect();
+        // most votes first, then lowest code
+        candidates.sort_by_key(|&(code, votes)| {
+            (std::cmp::Reverse(votes), code.inverted, code.value)
+        });
+
+        candidates.first().map(|&(code, _)| code)
+    }
+
+    /// The transmission has finished, decide which code it used.
+    fn finish(&mut self) -> Option<Code> {
+        let code = self.current();
+        self.reset();
+        code
+    }
+}
+
+const DEFAULT_MIN_MATCHES: u32 = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// How many times a code word needs to be seen before we believe it.
+    min_matches: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            min_matches: DEFAULT_MIN_MATCHES,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 2] = [
+    Property("min-matches", |name| {
+        ParamSpec::uint(
+            name,
+            "Minimum Matches",
+            "How many times a code word must be received before the code is \
+             reported (each one takes about 171 ms)",
+            1,
+            1000,
+            DEFAULT_MIN_MATCHES,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("code", |name| {
+        ParamSpec::string(
+            name,
+            "Code",
+            "The DCS code (e.g. \"D023N\") detected in the current \
+             transmission, or an empty string if there isn't one",
+            None,
+            ParamFlags::READABLE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+
+    /// Send `word` over and over for `seconds`, with a 1 kHz tone on top to
+    /// stand in for someone talking.
+    fn dcs_audio(word: u32, seconds: f64) -> Vec<f32> {
+        let samples = (seconds * f64::from(RATE)) as usize;
+
+        (0..samples)
+            .map(|n| {
+                let t = n as f64 / f64::from(RATE);
+                let bit = (t * BAUD) as u32 % 23;
+                let level = if word & (1 << bit) != 0 { 0.1 } else { -0.1 };
+                let voice = 0.3 * (2.0 * PI * 1_000.0 * t).sin();
+                (level + voice) as f32
+            })
+            .collect()
+    }
+
+    fn decode(word: u32) -> Decoder {
+        let mut decoder = Decoder::new(Settings::default(), RATE);
+        decoder.process(&dcs_audio(word, 3.0));
+        decoder
+    }
+
+    fn code(value: u16, inverted: bool) -> Code {
+        Code { value, inverted }
+    }
+
+    #[test]
+    fn known_code_words() {
+        assert_eq!(code_word(0o023), 0x76_3813);
+
+        // the data always goes first, followed by the fixed bits
+        for &value in CODES.iter() {
+            assert_eq!(code_word(value) & 0xFFF, 0x800 | u32::from(value));
+        }
+    }
+
+    #[test]
+    fn codes_are_written_in_octal() {
+        assert_eq!(code(0o023, false).to_string(), "D023N");
+        assert_eq!(code(0o754, true).to_string(), "D754I");
+    }
+
+    #[test]
+    fn decode_a_code() {
+        let mut decoder = decode(code_word(0o023));
+
+        assert_eq!(decoder.current(), Some(code(0o023, false)));
+        assert_eq!(decoder.finish(), Some(code(0o023, false)));
+        // finishing starts again from scratch
+        assert_eq!(decoder.current(), None);
+    }
+
+    #[test]
+    fn nothing_is_reported_without_a_code() {
+        let mut decoder = Decoder::new(Settings::default(), RATE);
+        let voice: Vec<f32> = (0..3 * RATE)
+            .map(|n| {
+                let t = f64::from(n) / f64::from(RATE);
+                (0.3 * (2.0 * PI * 1_000.0 * t).sin()) as f32
+            })
+            .collect();
+
+        decoder.process(&voice);
+
+        assert_eq!(decoder.finish(), None);
+    }
+
+    #[test]
+    fn ties_go_to_the_lowest_code() {
+        let mut decoder = Decoder::new(Settings::default(), RATE);
+        decoder.votes.insert(code(0o031, false), 5);
+        decoder.votes.insert(code(0o026, false), 5);
+        assert_eq!(decoder.current(), Some(code(0o026, false)));
+
+        // ... but only if they've got as many votes as each other
+        decoder.votes.insert(code(0o031, false), 6);
+        assert_eq!(decoder.current(), Some(code(0o031, false)));
+    }
+
+    #[test]
+    fn aliases_are_found_from_the_code_words() {
+        let decoder = Decoder::new(Settings::default(), RATE);
+
+        assert_eq!(
+            decoder.aliases.get(&code(0o047, true)),
+            Some(&code(0o023, false))
+        );
+        assert_eq!(
+            decoder.aliases.get(&code(0o023, true)),
+            Some(&code(0o047, false))
+        );
+        assert_eq!(decoder.aliases.get(&code(0o023, false)), None);
+    }
+
+    #[test]
+    fn aliases_are_reported_as_the_normal_code() {
+        // D023N and D047I are rotations of each other, so both are seen
+        // whichever one is sent
+        let decoder = decode(code_word(0o023));
+        assert!(decoder.votes.contains_key(&code(0o047, true)));
+        assert_eq!(decoder.current(), Some(code(0o023, false)));
+
+        let decoder = decode(!code_word(0o047) & 0x7F_FFFF);
+        assert!(decoder.votes.contains_key(&code(0o047, true)));
+        assert_eq!(decoder.current(), Some(code(0o023, false)));
+
+        // ... even when the normal code is the higher one
+        let decoder = decode(!code_word(0o023) & 0x7F_FFFF);
+        assert!(decoder.votes.contains_key(&code(0o023, true)));
+        assert_eq!(decoder.current(), Some(code(0o047, false)));
+
+        let mut decoder = decode(!code_word(0o754) & 0x7F_FFFF);
+        assert_eq!(decoder.finish(), Some(code(0o116, false)));
+    }
+
+    #[test]
+    fn codes_need_enough_matches() {
+        let settings = Settings { min_matches: 1000 };
+        let mut decoder = Decoder::new(settings, RATE);
+
+        decoder.process(&dcs_audio(code_word(0o023), 3.0));
+
+        assert_eq!(decoder.current(), None);
+    }
+}
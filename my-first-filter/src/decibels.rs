@@ -0,0 +1,50 @@
+/// Convert an amplitude (as a fraction of full scale) to dBFS.
+///
+/// Silence comes out as negative infinity, which compares the way you'd
+/// expect against thresholds. Anything that gets reported (and so may end up
+/// as JSON) should be clamped with `.max(transmission::MIN_LEVEL)` first.
+pub(crate) fn to_decibels(amplitude: f64) -> f64 {
+    if amplitude > 0.0 {
+        20.0 * amplitude.log10()
+    } else {
+        f64::NEG_INFINITY
+    }
+}
+
+/// Convert a level in dBFS back to an amplitude.
+pub(crate) fn from_decibels(level: f64) -> f64 {
+    10_f64.powf(level / 20.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use transmission::MIN_LEVEL;
+
+    #[test]
+    fn amplitudes_to_decibels() {
+        assert_eq!(to_decibels(1.0), 0.0);
+        assert!((to_decibels(0.5) - -6.0206).abs() < 1e-4);
+        assert!((to_decibels(0.1) - -20.0).abs() < 1e-9);
+        assert!((to_decibels(2.0) - 6.0206).abs() < 1e-4);
+    }
+
+    #[test]
+    fn silence_is_negative_infinity_unless_clamped() {
+        assert_eq!(to_decibels(0.0), f64::NEG_INFINITY);
+        assert_eq!(to_decibels(-0.5), f64::NEG_INFINITY);
+
+        assert_eq!(to_decibels(0.0).max(MIN_LEVEL), MIN_LEVEL);
+        assert_eq!(to_decibels(1e-20).max(MIN_LEVEL), MIN_LEVEL);
+        assert_eq!(to_decibels(f64::NAN).max(MIN_LEVEL), MIN_LEVEL);
+    }
+
+    #[test]
+    fn round_trip() {
+        for &level in &[-120.0, -45.0, -20.0, -0.1, 0.0, 6.0] {
+            let amplitude = from_decibels(level);
+            assert!((to_decibels(amplitude) - level).abs() < 1e-9, "{}", level);
+        }
+        assert_eq!(from_decibels(f64::NEG_INFINITY), 0.0);
+    }
+}
@@ -0,0 +1,595 @@
+use crate::{biquad::Biquad, fft::Complex};
+use std::f64::consts::PI;
+use transmission::MIN_LEVEL;
+
+/// The lowest rate a channel is filtered down to before it's demodulated.
+/// This leaves plenty of room either side of a 12.5 kHz or 25 kHz channel.
+const CHANNEL_RATE: f64 = 48_000.0;
+/// How quickly (in seconds) the carrier level used by the squelch reacts.
+const SQUELCH_TIME: f64 = 0.01;
+/// How quickly (in seconds) the AM carrier level used to scale the audio
+/// reacts. This needs to be slow enough to ignore the speech itself.
+const CARRIER_TIME: f64 = 0.1;
+/// The RMS level (as a fraction of full scale) the SSB AGC aims for.
+const AGC_TARGET: f64 = 0.2;
+/// The most the SSB AGC will amplify the audio, so the noise between words
+/// isn't brought all the way up.
+const AGC_MAX_GAIN: f64 = 1000.0;
+/// How long (in seconds) the SSB AGC measures the audio level over.
+const AGC_WINDOW: f64 = 0.02;
+/// How quickly (in seconds) the SSB AGC backs off and comes back up.
+const AGC_ATTACK: f64 = 0.01;
+const AGC_RELEASE: f64 = 0.5;
+
+/// How the audio is carried by the signal.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum Mode {
+    /// Narrowband FM, used by most land mobile radio.
+    Nbfm,
+    /// Amplitude modulation, used by aviation.
+    Am,
+    /// Upper sideband, used on HF above 10 MHz.
+    Usb,
+    /// Lower sideband, used on HF below 10 MHz.
+    Lsb,
+}
+
+impl Mode {
+    pub fn from_name(name: &str) -> Option<Mode> {
+        match name {
+            "nbfm" => Some(Mode::Nbfm),
+            "am" => Some(Mode::Am),
+            "usb" => Some(Mode::Usb),
+            "lsb" => Some(Mode::Lsb),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Nbfm => "nbfm",
+            Mode::Am => "am",
+            Mode::Usb => "usb",
+            Mode::Lsb => "lsb",
+        }
+    }
+
+    /// The bandwidth (in Hz) normally used with this mode. For SSB this is
+    /// just the one sideband.
+    pub fn default_bandwidth(self) -> f64 {
+        match self {
+            Mode::Nbfm => 12_500.0,
+            Mode::Am => 8_000.0,
+            Mode::Usb | Mode::Lsb => 3_000.0,
+        }
+    }
+
+    /// Where (relative to the carrier) the middle of a signal `bandwidth` Hz
+    /// wide is.
+    pub fn centre(self, bandwidth: f64) -> f64 {
+        match self {
+            Mode::Nbfm | Mode::Am => 0.0,
+            Mode::Usb => bandwidth / 2.0,
+            Mode::Lsb => -bandwidth / 2.0,
+        }
+    }
+}
+
+/// Demodulates a single NBFM, AM or SSB channel from complex IQ samples.
+///
+/// The channel is shifted down to 0 Hz, decimated to about 48 kHz, filtered
+/// down to its bandwidth, demodulated and then resampled to the requested
+/// audio rate. An SSB channel's sideband is centred on 0 Hz while it is being
+/// filtered, so the other sideband gets removed.
+///
+/// NBFM audio is scaled so a signal at the full `deviation` comes out at
+/// ±1.0, and nothing is de-emphasised. AM audio is scaled by the carrier
+/// level, so 100% modulation comes out at ±1.0 however strong the signal is.
+/// SSB has no carrier to go by, so an AGC keeps the audio at a steady level.
+#[derive(Debug, Clone)]
+pub(crate) struct Demodulator {
+    mode: Mode,
+    oscillator: Oscillator,
+    /// Stops anything folding into the channel while decimating. This isn't
+    /// needed if the input is already at the channel rate.
+    decimator: Option<DecimatingFir>,
+    /// Removes everything outside the channel's bandwidth.
+    channel_filter: DecimatingFir,
+    /// The rate (in Hz) of the filtered channel.
+    channel_rate: f64,
+    /// Moves an SSB signal back to where it was before it was centred for
+    /// filtering.
+    sideband: Oscillator,
+    previous: Complex,
+    /// Converts the phase change between two samples into audio.
+    gain: f64,
+    /// The smoothed AM carrier level.
+    carrier: f64,
+    carrier_smoothing: f64,
+    agc: Agc,
+    /// The carrier power needed to let audio through.
+    squelch_power: f64,
+    /// The smoothed power of the filtered channel.
+    power: f64,
+    power_smoothing: f64,
+    audio_filters: [Biquad; 2],
+    resampler: Resampler,
+}
+
+impl Demodulator {
+    /// Create a demodulator for the channel `offset` Hz away from the centre
+    /// of an IQ stream sampled at `input_rate`. The `deviation` is only used
+    /// for NBFM.
+    pub fn new(
+        mode: Mode,
+        input_rate: f64,
+        offset: f64,
+        bandwidth: f64,
+        deviation: f64,
+        squelch: f64,
+        audio_rate: f64,
+    ) -> Demodulator {
+        let decimation = (input_rate / CHANNEL_RATE).floor().max(1.0);
+        let channel_rate = input_rate / decimation;
+        let cutoff = (bandwidth / 2.0).min(channel_rate * 0.45);
+        // anything closer than this to the next multiple of the channel rate
+        // gets folded back into the channel when decimating
+        let transition = (channel_rate - 2.0 * cutoff).max(cutoff * 0.2);
+        let audio_cutoff = (audio_rate * 0.45).min(channel_rate * 0.45);
+        let centre = mode.centre(bandwidth);
+
+        let decimator = if decimation > 1.0 {
+            Some(DecimatingFir::low_pass(
+                cutoff,
+                transition,
+                input_rate,
+                decimation as usize,
+            ))
+        } else {
+            None
+        };
+
+        Demodulator {
+            mode,
+            oscillator: Oscillator::new(-(offset + centre), input_rate),
+            decimator,
+            channel_filter: DecimatingFir::low_pass(
+                cutoff,
+                bandwidth / 5.0,
+                channel_rate,
+                1,
+            ),
+            channel_rate,
+            sideband: Oscillator::new(centre, channel_rate),
+            previous: Complex::default(),
+            gain: channel_rate / (2.0 * PI * deviation),
+            carrier: 0.0,
+            carrier_smoothing: 1.0 / (CARRIER_TIME * channel_rate),
+            agc: Agc::new(channel_rate),
+            squelch_power: 10_f64.powf(squelch / 10.0),
+            power: 0.0,
+            power_smoothing: 1.0 / (SQUELCH_TIME * channel_rate),
+            audio_filters: [
+                Biquad::low_pass(audio_cutoff, channel_rate),
+                Biquad::low_pass(audio_cutoff, channel_rate),
+            ],
+            resampler: Resampler::new(channel_rate / audio_rate),
+        }
+    }
+
+    /// The rate (in Hz) the channel is demodulated at.
+    pub fn channel_rate(&self) -> f64 {
+        self.channel_rate
+    }
+
+    /// The carrier level (in dBFS) of the channel.
+    pub fn level(&self) -> f64 {
+        if self.power > 0.0 {
+            (10.0 * self.power.log10()).max(MIN_LEVEL)
+        } else {
+            MIN_LEVEL
+        }
+    }
+
+    /// Is there a strong enough carrier to let audio through?
+    pub fn is_open(&self) -> bool {
+        self.power >= self.squelch_power
+    }
+
+    /// Demodulate some IQ samples, adding any audio to `audio`.
+    pub fn process(&mut self, iq: &[Complex], audio: &mut Vec<f32>) {
+        for &sample in iq {
+            let mixed = sample * self.oscillator.next();
+
+            let decimated = match self.decimator {
+                Some(ref mut decimator) => decimator.push(mixed),
+                None => Some(mixed),
+            };
+            if let Some(filtered) =
+                decimated.and_then(|s| self.channel_filter.push(s))
+            {
+                self.demodulate(filtered, audio);
+            }
+        }
+    }
+
+    /// Demodulate a sample which has already been shifted, filtered and
+    /// decimated to the channel rate.
+    fn demodulate(&mut self, sample: Complex, audio: &mut Vec<f32>) {
+        self.power += (sample.norm_sqr() - self.power) * self.power_smoothing;
+
+        let mut demodulated = match self.mode {
+            Mode::Nbfm => self.demodulate_fm(sample),
+            Mode::Am => self.demodulate_am(sample),
+            Mode::Usb | Mode::Lsb => {
+                let shifted = sample * self.sideband.next();
+                self.agc.push(shifted.re)
+            },
+        };
+        if !self.is_open() {
+            demodulated = 0.0;
+        }
+
+        let filtered = self
+            .audio_filters
+            .iter_mut()
+            .fold(demodulated, |sample, filter| filter.push(sample));
+        self.resampler.push(filtered, audio);
+    }
+
+    fn demodulate_fm(&mut self, sample: Complex) -> f64 {
+        // the phase change between this sample and the last one, i.e.
+        // arg(sample * conj(previous))
+        let previous = self.previous;
+        let re = sample.re * previous.re + sample.im * previous.im;
+        let im = sample.im * previous.re - sample.re * previous.im;
+        self.previous = sample;
+
+        if re == 0.0 && im == 0.0 {
+            0.0
+        } else {
+            im.atan2(re) * self.gain
+        }
+    }
+
+    fn demodulate_am(&mut self, sample: Complex) -> f64 {
+        let envelope = sample.norm_sqr().sqrt();
+        self.carrier += (envelope - self.carrier) * self.carrier_smoothing;
+
+        // the carrier itself is just a DC offset
+        if self.carrier > 0.0 {
+            (envelope / self.carrier - 1.0).max(-1.0).min(1.0)
+        } else {
+            0.0
+        }
+    }
+
+    /// Forget about any samples seen so far.
+    pub fn reset(&mut self) {
+        if let Some(ref mut decimator) = self.decimator {
+            decimator.reset();
+        }
+        self.channel_filter.reset();
+        self.previous = Complex::default();
+        self.carrier = 0.0;
+        self.agc.reset();
+        self.power = 0.0;
+        self.resampler.reset();
+    }
+}
+
+/// Keeps SSB audio at a steady level, backing off quickly when the signal
+/// gets louder and coming back up slowly so the gain doesn't pump between
+/// words.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Agc {
+    /// The smoothed power of the audio.
+    power: f64,
+    power_smoothing: f64,
+    gain: f64,
+    attack: f64,
+    release: f64,
+}
+
+impl Agc {
+    pub fn new(rate: f64) -> Agc {
+        Agc {
+            power: 0.0,
+            power_smoothing: 1.0 / (AGC_WINDOW * rate),
+            // start high and let the (much quicker) attack bring it down
+            gain: AGC_MAX_GAIN,
+            attack: 1.0 / (AGC_ATTACK * rate),
+            release: 1.0 / (AGC_RELEASE * rate),
+        }
+    }
+
+    pub fn push(&mut self, sample: f64) -> f64 {
+        self.power += (sample * sample - self.power) * self.power_smoothing;
+
+        let rms = self.power.sqrt();
+        let wanted = if rms * AGC_MAX_GAIN <= AGC_TARGET {
+            AGC_MAX_GAIN
+        } else {
+            AGC_TARGET / rms
+        };
+        let coefficient = if wanted < self.gain {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain += coefficient * (wanted - self.gain);
+
+        (sample * self.gain).max(-1.0).min(1.0)
+    }
+
+    pub fn reset(&mut self) {
+        self.power = 0.0;
+        self.gain = AGC_MAX_GAIN;
+    }
+}
+
+/// Generates `e^(j2πft)`, for shifting a signal by `f` Hz.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Oscillator {
+    phasor: Complex,
+    step: Complex,
+    count: u32,
+}
+
+impl Oscillator {
+    pub fn new(frequency: f64, rate: f64) -> Oscillator {
+        let omega = 2.0 * PI * frequency / rate;
+
+        Oscillator {
+            phasor: Complex::new(1.0, 0.0),
+            step: Complex::new(omega.cos(), omega.sin()),
+            count: 0,
+        }
+    }
+
+    pub fn next(&mut self) -> Complex {
+        let value = self.phasor;
+        self.phasor = self.phasor * self.step;
+
+        // rounding errors slowly change the amplitude
+        self.count += 1;
+        if self.count >= 1024 {
+            self.count = 0;
+            self.phasor =
+                self.phasor.scale(1.0 / self.phasor.norm_sqr().sqrt());
+        }
+
+        value
+    }
+}
+
+/// A windowed-sinc low-pass filter which only calculates every
+/// `decimation`'th output.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DecimatingFir {
+    taps: Vec<f64>,
+    /// The last `taps.len()` inputs, stored twice so they can always be read
+    /// as one contiguous slice.
+    history: Vec<Complex>,
+    position: usize,
+    decimation: usize,
+    phase: usize,
+}
+
+impl DecimatingFir {
+    pub fn low_pass(
+        cutoff: f64,
+        transition: f64,
+        rate: f64,
+        decimation: usize,
+    ) -> DecimatingFir {
+        DecimatingFir::new(low_pass_taps(cutoff, transition, rate), decimation)
+    }
+
+    pub fn new(taps: Vec<f64>, decimation: usize) -> DecimatingFir {
+        DecimatingFir {
+            history: vec![Complex::default(); taps.len() * 2],
+            taps,
+            position: 0,
+            decimation: decimation.max(1),
+            phase: 0,
+        }
+    }
+
+    pub fn push(&mut self, sample: Complex) -> Option<Complex> {
+        let len = self.taps.len();
+        self.history[self.position] = sample;
+        self.history[self.position + len] = sample;
+        self.position = (self.position + 1) % len;
+
+        self.phase += 1;
+        if self.phase < self.decimation {
+            return None;
+        }
+        self.phase = 0;
+
+        let window = &self.history[self.position..self.position + len];
+        let mut sum = Complex::default();
+        for (&tap, &value) in self.taps.iter().zip(window) {
+            sum.re += tap * value.re;
+            sum.im += tap * value.im;
+        }
+
+        Some(sum)
+    }
+
+    pub fn reset(&mut self) {
+        for value in self.history.iter_mut() {
+            *value = Complex::default();
+        }
+        self.position = 0;
+        self.phase = 0;
+    }
+}
+
+/// Design a Hamming-windowed sinc low-pass filter with unity gain at DC.
+pub(crate) fn low_pass_taps(
+    cutoff: f64,
+    transition: f64,
+    rate: f64,
+) -> Vec<f64> {
+    // the usual rule of thumb for a Hamming window
+    let len = ((3.3 * rate / transition).ceil() as usize) | 1;
+    let middle = (len / 2) as f64;
+    let fc = cutoff / rate;
+
+    let mut taps: Vec<f64> = (0..len)
+        .map(|i| {
+            let n = i as f64 - middle;
+            let sinc = if n == 0.0 {
+                2.0 * fc
+            } else {
+                (2.0 * PI * fc * n).sin() / (PI * n)
+            };
+            let window = 0.54
+                - 0.46 * (2.0 * PI * i as f64 / (len - 1).max(1) as f64).cos();
+            sinc * window
+        })
+        .collect();
+
+    let gain: f64 = taps.iter().sum();
+    for tap in taps.iter_mut() {
+        *tap /= gain;
+    }
+
+    taps
+}
+
+/// Converts between sample rates by linearly interpolating. The input should
+/// already be low-pass filtered.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Resampler {
+    /// How many input samples to move forward for each output sample.
+    step: f64,
+    /// Where the next output sample is, relative to the previous input.
+    position: f64,
+    previous: f64,
+}
+
+impl Resampler {
+    pub fn new(step: f64) -> Resampler {
+        Resampler {
+            step,
+            position: 0.0,
+            previous: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, sample: f64, output: &mut Vec<f32>) {
+        while self.position < 1.0 {
+            let value =
+                self.previous + (sample - self.previous) * self.position;
+            output.push(value as f32);
+            self.position += self.step;
+        }
+
+        self.position -= 1.0;
+        self.previous = sample;
+    }
+
+    pub fn reset(&mut self) {
+        self.position = 0.0;
+        self.previous = 0.0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT_RATE: f64 = 240_000.0;
+    const AUDIO_RATE: f64 = 16_000.0;
+    const OFFSET: f64 = 25_000.0;
+    const DEVIATION: f64 = 2_500.0;
+    const TONE: f64 = 1_000.0;
+
+    /// Half a second of a carrier `OFFSET` Hz from the centre, frequency
+    /// modulated by a `TONE` Hz tone at the full deviation.
+    fn fm_tone() -> Vec<Complex> {
+        (0..INPUT_RATE as usize / 2)
+            .map(|i| {
+                let t = i as f64 / INPUT_RATE;
+                let phase = 2.0 * PI * OFFSET * t
+                    + DEVIATION / TONE * (2.0 * PI * TONE * t).sin();
+                Complex::new(phase.cos(), phase.sin()).scale(0.5)
+            })
+            .collect()
+    }
+
+    fn demodulate(squelch: f64) -> Vec<f32> {
+        let mut demod = Demodulator::new(
+            Mode::Nbfm,
+            INPUT_RATE,
+            OFFSET,
+            Mode::Nbfm.default_bandwidth(),
+            DEVIATION,
+            squelch,
+            AUDIO_RATE,
+        );
+        let mut audio = Vec::new();
+        demod.process(&fm_tone(), &mut audio);
+
+        // give the filters time to settle
+        audio.split_off(AUDIO_RATE as usize / 10)
+    }
+
+    #[test]
+    fn recover_an_fm_tone() {
+        let audio = demodulate(MIN_LEVEL);
+
+        let seconds = audio.len() as f64 / AUDIO_RATE;
+        let crossings = audio
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.0) != (pair[1] < 0.0))
+            .count();
+        let frequency = crossings as f64 / 2.0 / seconds;
+        assert!((frequency - TONE).abs() < 20.0, "{} Hz", frequency);
+
+        let peak = audio.iter().fold(0.0_f32, |peak, s| peak.max(s.abs()));
+        assert!((0.8..1.2).contains(&peak), "peak of {}", peak);
+    }
+
+    #[test]
+    fn the_audio_rate_is_right() {
+        let audio = demodulate(MIN_LEVEL);
+
+        let expected = AUDIO_RATE * 0.4;
+        assert!(
+            (audio.len() as f64 - expected).abs() < 2.0,
+            "{}",
+            audio.len()
+        );
+    }
+
+    #[test]
+    fn weak_signals_are_squelched() {
+        let audio = demodulate(10.0);
+
+        assert!(audio.iter().all(|&s| s == 0.0));
+    }
+
+    #[test]
+    fn shift_by_a_quarter_of_the_sample_rate() {
+        let mut oscillator = Oscillator::new(1.0, 4.0);
+
+        let got: Vec<Complex> = (0..4).map(|_| oscillator.next()).collect();
+
+        let expected = [(1.0, 0.0), (0.0, 1.0), (-1.0, 0.0), (0.0, -1.0)];
+        for (got, &(re, im)) in got.iter().zip(&expected) {
+            assert!((got.re - re).abs() < 1e-9 && (got.im - im).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn low_pass_filters_have_unity_gain() {
+        let taps = low_pass_taps(5_000.0, 1_000.0, 48_000.0);
+
+        assert_eq!(taps.len() % 2, 1);
+        assert!((taps.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+    }
+}
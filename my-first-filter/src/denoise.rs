@@ -0,0 +1,719 @@
+use crate::fft::{self, Complex};
+use byte_slice_cast::AsMutSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, BufferRef, Caps, ClockTime, CoreError, DebugCategory,
+    DebugColorFlags, Element, ErrorMessage, Event, EventView, FlowError,
+    FlowSuccess, IntRange, Message, PadDirection, PadPresence, PadTemplate,
+    Plugin, QueryRef, QueryView, Rank,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::{collections::VecDeque, f64::consts::PI, sync::Mutex};
+use transmission::messages::{TRANSMISSION_END, TRANSMISSION_START};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rsdenoise",
+        Rank::None,
+        Denoise::get_type(),
+    )
+}
+
+/// Roughly how long each analysis frame is, in seconds. The actual length
+/// is rounded up to a power of two.
+const FRAME: f64 = 0.032;
+/// The time constant (in seconds) of the average used for the noise
+/// profile.
+const LEARNING_TIME: f64 = 1.0;
+/// The lowest gain applied to any frequency, so the background doesn't drop
+/// out completely.
+const FLOOR: f64 = 0.05;
+
+/// A spectral subtraction noise reducer.
+///
+/// This needs to go after a `rssquelch`. Between transmissions the element
+/// learns what the background noise looks like, then subtracts it from the
+/// audio while the squelch is open. The audio is delayed by one frame
+/// (about 32 ms), which is reported as latency, and the squelch's events are
+/// held back by the same amount so they still line up with the audio.
+pub struct Denoise {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Denoise {
+    fn set_learning(&self, element: &BaseTransform, learning: bool) {
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "{} the noise profile",
+                if learning { "Learning" } else { "Applying" }
+            );
+            state.reducer.learning = learning;
+        }
+    }
+
+    /// How far behind the input the output is running.
+    fn latency(&self) -> ClockTime {
+        match *self.state.lock().unwrap() {
+            Some(ref state) => ClockTime::from_nseconds(
+                state.reducer.size() as u64 * 1_000_000_000
+                    / u64::from(state.reducer.rate),
+            ),
+            None => ClockTime::from_nseconds(0),
+        }
+    }
+
+    /// Send out the audio still inside the reducer (and any events waiting
+    /// for it), so the end of the stream doesn't get cut off.
+    fn drain(&self, element: &BaseTransform) {
+        let (before, buffer, after) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = match state_guard.as_mut() {
+                Some(state) => state,
+                None => return,
+            };
+            let Settings { strength } = *self.settings.lock().unwrap();
+
+            let samples = state.reducer.drain(over_subtraction(strength));
+            let end = state.position + samples.len() as u64;
+            state.position = end;
+
+            let mut buffer = match Buffer::with_size(samples.len() * 4) {
+                Some(buffer) => buffer,
+                None => return,
+            };
+            {
+                let buffer = buffer.get_mut().unwrap();
+                if let Some(mut map) = buffer.map_writable() {
+                    if let Ok(out) = map.as_mut_slice_of::<f32>() {
+                        for (out, &sample) in out.iter_mut().zip(&samples) {
+                            *out = sample as f32;
+                        }
+                    }
+                }
+                let rate = u64::from(state.reducer.rate);
+                buffer.set_pts(state.next_timestamp);
+                buffer.set_duration(ClockTime::from_nseconds(
+                    samples.len() as u64 * 1_000_000_000 / rate,
+                ));
+            }
+
+            // anything which arrived after the last sample goes after the
+            // audio
+            let (before, after): (Vec<_>, Vec<_>) =
+                state.events.drain(..).partition(|&(at, _)| at < end);
+
+            (before, buffer, after)
+        };
+
+        let src = element.get_static_pad("src").unwrap();
+        for (_, event) in before {
+            src.push_event(event);
+        }
+        if let Err(e) = src.push(buffer) {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Unable to push the remaining audio: {:?}",
+                e
+            );
+        }
+        for (_, event) in after {
+            src.push_event(event);
+        }
+    }
+}
+
+impl ObjectSubclass for Denoise {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsDenoise";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsdenoise",
+                DebugColorFlags::empty(),
+                Some("Rust spectral subtraction noise reducer"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "Noise Reducer",
+            "Filter/Effect/Audio",
+            "Removes background noise using a profile learned while the \
+             squelch is closed",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for Denoise {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+
+        match *prop {
+            Property("strength", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let strength = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing strength from {} to {}",
+                    settings.strength,
+                    strength
+                );
+                settings.strength = strength;
+            },
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            Property("strength", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.strength.to_value())
+            },
+            Property("noise-level", ..) => {
+                let level = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|s| s.reducer.noise_level())
+                    .unwrap_or(std::f64::NEG_INFINITY);
+                Ok(level.max(-200.0).to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Denoise {}
+
+impl BaseTransformImpl for Denoise {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        *self.state.lock().unwrap() = Some(State {
+            reducer: Reducer::new(info.rate()),
+            position: 0,
+            next_timestamp: ClockTime::none(),
+            events: VecDeque::new(),
+        });
+
+        // the frame size depends on the sample rate
+        let _ = element
+            .post_message(&Message::new_latency().src(Some(element)).build());
+
+        true
+    }
+
+    fn query(
+        &self,
+        element: &BaseTransform,
+        direction: PadDirection,
+        query: &mut QueryRef,
+    ) -> bool {
+        if !self.parent_query(element, direction, query) {
+            return false;
+        }
+
+        if direction == PadDirection::Src {
+            if let QueryView::Latency(ref mut q) = query.view_mut() {
+                let (live, min, max) = q.get_result();
+                let latency = self.latency();
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Adding {} of latency",
+                    latency
+                );
+                q.set(live, min + latency, max + latency);
+            }
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        let mut delay = false;
+
+        match event.view() {
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    if s.get_name() == TRANSMISSION_START {
+                        self.set_learning(element, false);
+                    } else if s.get_name() == TRANSMISSION_END {
+                        self.set_learning(element, true);
+                    }
+                }
+                delay = true;
+            },
+            EventView::Eos(..) => self.drain(element),
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.reducer.flush();
+                    state.events.clear();
+                }
+            },
+            _ => {},
+        }
+
+        if delay {
+            // the audio this event goes with won't come out for another
+            // frame
+            if let Some(ref mut state) = *self.state.lock().unwrap() {
+                let release_at = state.position + state.reducer.size() as u64;
+                state.events.push_back((release_at, event));
+                return true;
+            }
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no state yet"]
+            );
+            FlowError::NotNegotiated
+        })?;
+        let Settings { strength } = *self.settings.lock().unwrap();
+        let pts = buf.get_pts();
+
+        let mut map = buf.map_writable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer writable"]
+            );
+            FlowError::Error
+        })?;
+        let samples = map.as_mut_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        let over_subtraction = over_subtraction(strength);
+        for sample in samples.iter_mut() {
+            *sample =
+                state.reducer.push(f64::from(*sample), over_subtraction) as f32;
+        }
+
+        let end = state.position + samples.len() as u64;
+        state.position = end;
+        if let Some(pts) = pts.nseconds() {
+            let duration = samples.len() as u64 * 1_000_000_000
+                / u64::from(state.reducer.rate);
+            state.next_timestamp = ClockTime::from_nseconds(pts + duration);
+        }
+
+        // events are rounded to the start of the buffer their audio comes
+        // out in
+        let mut events = Vec::new();
+        while let Some(&(at, _)) = state.events.front() {
+            if at >= end {
+                break;
+            }
+            events.extend(state.events.pop_front().map(|(_, event)| event));
+        }
+
+        drop(map);
+        drop(state_guard);
+        let src = element.get_static_pad("src").unwrap();
+        for event in events {
+            src.push_event(event);
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+/// 100% strength subtracts twice the noise estimate.
+fn over_subtraction(strength: u32) -> f64 {
+    f64::from(strength) / 50.0
+}
+
+struct State {
+    reducer: Reducer,
+    /// How many samples have gone through the reducer.
+    position: u64,
+    /// The timestamp just after the last buffer.
+    next_timestamp: ClockTime,
+    /// Events waiting for their audio to come out of the reducer, and the
+    /// sample they should be sent before.
+    events: VecDeque<(u64, Event)>,
+}
+
+/// The part of the element which actually does the noise reduction.
+///
+/// Audio is cut into half-overlapping frames with a square-root Hann
+/// window. Each frame's power spectrum either goes into the noise profile
+/// (while learning) or has the profile subtracted from it, and the frames
+/// are stitched back together with the same window.
+#[derive(Debug)]
+struct Reducer {
+    rate: u32,
+    window: Vec<f64>,
+    /// The most recent frame's worth of input.
+    input: VecDeque<f64>,
+    /// Samples received since the last frame was processed.
+    pending: usize,
+    /// Overlap-add accumulator for the processed frames.
+    overlap: Vec<f64>,
+    /// Processed samples waiting to go out.
+    output: VecDeque<f64>,
+    /// The average power in each frequency bin while the squelch is closed.
+    noise: Vec<f64>,
+    /// How many frames have gone into the noise profile.
+    frames_learned: u64,
+    learning_rate: f64,
+    learning: bool,
+    spectrum: Vec<Complex>,
+}
+
+impl Reducer {
+    fn new(rate: u32) -> Reducer {
+        let size = ((f64::from(rate) * FRAME) as usize).next_power_of_two();
+        let hop = size / 2;
+        let window = (0..size)
+            .map(|i| (PI * i as f64 / size as f64).sin())
+            .collect();
+
+        Reducer {
+            rate,
+            window,
+            input: std::iter::repeat(0.0).take(size).collect(),
+            pending: 0,
+            overlap: vec![0.0; size],
+            output: std::iter::repeat(0.0).take(hop).collect(),
+            noise: vec![0.0; size / 2 + 1],
+            frames_learned: 0,
+            learning_rate: hop as f64 / (f64::from(rate) * LEARNING_TIME),
+            // the squelch starts off closed
+            learning: true,
+            spectrum: vec![Complex::default(); size],
+        }
+    }
+
+    fn size(&self) -> usize {
+        self.window.len()
+    }
+
+    fn hop(&self) -> usize {
+        self.size() / 2
+    }
+
+    /// Forget any buffered audio, keeping the noise profile.
+    fn flush(&mut self) {
+        let mut fresh = Reducer::new(self.rate);
+        fresh.noise = std::mem::replace(&mut self.noise, Vec::new());
+        fresh.frames_learned = self.frames_learned;
+        fresh.learning = self.learning;
+        *self = fresh;
+    }
+
+    /// Push silence through until everything which went in has come out
+    /// again, without letting it into the noise profile.
+    fn drain(&mut self, over_subtraction: f64) -> Vec<f64> {
+        let learning = std::mem::replace(&mut self.learning, false);
+        let drained = (0..self.size())
+            .map(|_| self.push(0.0, over_subtraction))
+            .collect();
+        self.learning = learning;
+
+        drained
+    }
+
+    fn push(&mut self, sample: f64, over_subtraction: f64) -> f64 {
+        self.input.pop_front();
+        self.input.push_back(sample);
+        self.pending += 1;
+
+        if self.pending >= self.hop() {
+            self.pending = 0;
+            self.process_frame(over_subtraction);
+        }
+
+        self.output.pop_front().unwrap_or(0.0)
+    }
+
+    fn process_frame(&mut self, over_subtraction: f64) {
+        let size = self.size();
+        let hop = self.hop();
+
+        for (i, (value, &sample)) in
+            self.spectrum.iter_mut().zip(&self.input).enumerate()
+        {
+            *value = Complex::new(sample * self.window[i], 0.0);
+        }
+        fft::fft(&mut self.spectrum);
+
+        if self.learning {
+            self.learn();
+        }
+
+        if self.frames_learned > 0 && over_subtraction > 0.0 {
+            for bin in 0..=size / 2 {
+                let power = self.spectrum[bin].norm_sqr();
+                let gain = if power > 0.0 {
+                    (1.0 - over_subtraction * self.noise[bin] / power)
+                        .max(FLOOR * FLOOR)
+                        .sqrt()
+                } else {
+                    FLOOR
+                };
+
+                self.spectrum[bin] = self.spectrum[bin].scale(gain);
+                // keep the spectrum symmetric so the output stays real
+                if bin > 0 && bin < size / 2 {
+                    self.spectrum[size - bin] =
+                        self.spectrum[size - bin].scale(gain);
+                }
+            }
+        }
+
+        fft::ifft(&mut self.spectrum);
+
+        for (i, value) in self.spectrum.iter().enumerate() {
+            self.overlap[i] += value.re * self.window[i];
+        }
+
+        // the first half of the accumulator won't get any more overlap
+        self.output.extend(self.overlap.drain(..hop));
+        self.overlap.extend(std::iter::repeat(0.0).take(hop));
+    }
+
+    fn learn(&mut self) {
+        // start with a plain average so the first few frames aren't
+        // swamped by the initial zeroes
+        self.frames_learned += 1;
+        let rate = self.learning_rate.max(1.0 / self.frames_learned as f64);
+
+        for (noise, value) in self.noise.iter_mut().zip(&self.spectrum) {
+            *noise += rate * (value.norm_sqr() - *noise);
+        }
+    }
+
+    /// The RMS level of the noise profile, in dBFS.
+    fn noise_level(&self) -> f64 {
+        // Parseval's theorem, allowing for the window and both halves of
+        // the spectrum
+        let size = self.size() as f64;
+        let total: f64 = self
+            .noise
+            .iter()
+            .enumerate()
+            .map(|(bin, &power)| {
+                if bin == 0 || bin == self.noise.len() - 1 {
+                    power
+                } else {
+                    2.0 * power
+                }
+            })
+            .sum();
+        // the mean of a sin² window is 1/2
+        let power = total / (size * size * 0.5);
+
+        if power > 0.0 {
+            10.0 * power.log10()
+        } else {
+            std::f64::NEG_INFINITY
+        }
+    }
+}
+
+const DEFAULT_STRENGTH: u32 = 50;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    strength: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            strength: DEFAULT_STRENGTH,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 2] = [
+    Property("strength", |name| {
+        ParamSpec::uint(
+            name,
+            "Strength",
+            "How aggressively to remove noise, from 0 (not at all) to 100 \
+             (twice the learned noise profile)",
+            0,
+            100,
+            DEFAULT_STRENGTH,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("noise-level", |name| {
+        ParamSpec::double(
+            name,
+            "Noise Level",
+            "The RMS level (in dBFS) of the learned noise profile",
+            -200.0,
+            0.0,
+            -200.0,
+            ParamFlags::READABLE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reducer which only delays the audio.
+    fn passthrough() -> Reducer {
+        let mut reducer = Reducer::new(8000);
+        reducer.learning = false;
+        reducer
+    }
+
+    #[test]
+    fn audio_comes_out_one_frame_late() {
+        let mut reducer = passthrough();
+        let mut input = vec![0.0; 1000];
+        input[10] = 1.0;
+
+        let output: Vec<f64> =
+            input.iter().map(|&s| reducer.push(s, 0.0)).collect();
+
+        let loudest = (0..output.len())
+            .max_by(|&a, &b| output[a].abs().total_cmp(&output[b].abs()))
+            .unwrap();
+        assert_eq!(loudest, 10 + reducer.size());
+        assert!((output[loudest] - 1.0).abs() < 1e-9, "{}", output[loudest]);
+    }
+
+    #[test]
+    fn draining_gives_back_the_rest_of_the_audio() {
+        let mut reducer = passthrough();
+        let input: Vec<f64> =
+            (0..300).map(|i| (i as f64 * 0.1).sin()).collect();
+
+        let mut output: Vec<f64> =
+            input.iter().map(|&s| reducer.push(s, 0.0)).collect();
+        output.extend(reducer.drain(0.0));
+
+        let size = reducer.size();
+        assert_eq!(output.len(), input.len() + size);
+        assert!(output[..size].iter().all(|s| s.abs() < 1e-9));
+        for (got, expected) in output[size..].iter().zip(&input) {
+            assert!((got - expected).abs() < 1e-9, "{} != {}", got, expected);
+        }
+    }
+
+    #[test]
+    fn draining_doesnt_learn_the_silence() {
+        let mut reducer = Reducer::new(8000);
+        for i in 0..1000 {
+            reducer.push((i as f64 * 0.3).sin() * 0.1, 1.0);
+        }
+        let level = reducer.noise_level();
+
+        reducer.drain(1.0);
+
+        assert_eq!(reducer.noise_level(), level);
+        assert!(reducer.learning);
+    }
+}
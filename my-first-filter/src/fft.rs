@@ -0,0 +1,107 @@
+use std::{
+    f64::consts::PI,
+    ops::{Add, Mul, Sub},
+};
+
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub(crate) struct Complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl Complex {
+    pub fn new(re: f64, im: f64) -> Complex {
+        Complex { re, im }
+    }
+
+    pub fn norm_sqr(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn scale(self, factor: f64) -> Complex {
+        Complex::new(self.re * factor, self.im * factor)
+    }
+}
+
+impl Add for Complex {
+    type Output = Complex;
+
+    fn add(self, other: Complex) -> Complex {
+        Complex::new(self.re + other.re, self.im + other.im)
+    }
+}
+
+impl Sub for Complex {
+    type Output = Complex;
+
+    fn sub(self, other: Complex) -> Complex {
+        Complex::new(self.re - other.re, self.im - other.im)
+    }
+}
+
+impl Mul for Complex {
+    type Output = Complex;
+
+    fn mul(self, other: Complex) -> Complex {
+        Complex::new(
+            self.re * other.re - self.im * other.im,
+            self.re * other.im + self.im * other.re,
+        )
+    }
+}
+
+/// An in-place radix-2 FFT. The length must be a power of two.
+pub(crate) fn fft(data: &mut [Complex]) {
+    transform(data, false);
+}
+
+/// The inverse of [`fft()`], including the `1/n` scaling.
+pub(crate) fn ifft(data: &mut [Complex]) {
+    transform(data, true);
+
+    let scale = 1.0 / data.len() as f64;
+    for value in data.iter_mut() {
+        *value = value.scale(scale);
+    }
+}
+
+fn transform(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "the FFT length must be a power of two");
+
+    // put everything in bit-reversed order
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        let step = Complex::new(angle.cos(), angle.sin());
+
+        for chunk in data.chunks_mut(len) {
+            let (left, right) = chunk.split_at_mut(len / 2);
+            let mut twiddle = Complex::new(1.0, 0.0);
+
+            for (a, b) in left.iter_mut().zip(right.iter_mut()) {
+                let t = *b * twiddle;
+                *b = *a - t;
+                *a = *a + t;
+                twiddle = twiddle * step;
+            }
+        }
+
+        len <<= 1;
+    }
+}
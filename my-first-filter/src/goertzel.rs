@@ -0,0 +1,40 @@
+use std::f64::consts::PI;
+
+/// A single-frequency DFT, for picking tones out of a block of audio.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) struct Goertzel {
+    coefficient: f64,
+    s1: f64,
+    s2: f64,
+}
+
+impl Goertzel {
+    pub fn new(frequency: f64, rate: u32) -> Goertzel {
+        Goertzel {
+            coefficient: 2.0 * (2.0 * PI * frequency / f64::from(rate)).cos(),
+            s1: 0.0,
+            s2: 0.0,
+        }
+    }
+
+    pub fn push(&mut self, sample: f64) {
+        let s0 = sample + self.coefficient * self.s1 - self.s2;
+        self.s2 = self.s1;
+        self.s1 = s0;
+    }
+
+    /// The RMS level of the frequency we're looking for, given how many
+    /// samples have been pushed.
+    pub fn rms(&self, samples: usize) -> f64 {
+        let power = self.s1 * self.s1 + self.s2 * self.s2
+            - self.coefficient * self.s1 * self.s2;
+        let amplitude = 2.0 * power.max(0.0).sqrt() / samples as f64;
+
+        amplitude / std::f64::consts::SQRT_2
+    }
+
+    pub fn reset(&mut self) {
+        self.s1 = 0.0;
+        self.s2 = 0.0;
+    }
+}
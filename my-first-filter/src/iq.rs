@@ -0,0 +1,156 @@
+use crate::fft::Complex;
+
+/// The media type used for complex IQ samples. Buffers contain interleaved
+/// native-endian `f32` I and Q values, scaled so full scale is ±1.0, and the
+/// caps have a `rate` field with the number of complex samples per second.
+pub(crate) const IQ_MEDIA_TYPE: &str = "application/x-iq";
+/// The only `format` used with [`IQ_MEDIA_TYPE`].
+pub(crate) const IQ_FORMAT: &str = "cf32";
+
+/// The raw sample formats an IQ recording can be in.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub(crate) enum SampleFormat {
+    /// Unsigned 8-bit, as written by `rtl_sdr`.
+    Cu8,
+    /// Signed 8-bit, as written by `hackrf_transfer`.
+    Cs8,
+    /// Signed 16-bit little-endian.
+    Cs16,
+    /// 32-bit little-endian floats.
+    Cf32,
+}
+
+impl SampleFormat {
+    pub fn from_name(name: &str) -> Option<SampleFormat> {
+        match name {
+            "cu8" => Some(SampleFormat::Cu8),
+            "cs8" => Some(SampleFormat::Cs8),
+            "cs16" => Some(SampleFormat::Cs16),
+            "cf32" => Some(SampleFormat::Cf32),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SampleFormat::Cu8 => "cu8",
+            SampleFormat::Cs8 => "cs8",
+            SampleFormat::Cs16 => "cs16",
+            SampleFormat::Cf32 => "cf32",
+        }
+    }
+
+    /// The number of bytes used by one complex sample.
+    pub fn frame_size(self) -> usize {
+        match self {
+            SampleFormat::Cu8 | SampleFormat::Cs8 => 2,
+            SampleFormat::Cs16 => 4,
+            SampleFormat::Cf32 => 8,
+        }
+    }
+
+    /// Convert whole samples from `raw` to interleaved `f32` I/Q values.
+    pub fn convert(self, raw: &[u8], output: &mut Vec<f32>) {
+        match self {
+            SampleFormat::Cu8 => output
+                .extend(raw.iter().map(|&b| (f32::from(b) - 127.5) / 127.5)),
+            SampleFormat::Cs8 => {
+                output.extend(raw.iter().map(|&b| f32::from(b as i8) / 128.0))
+            },
+            SampleFormat::Cs16 => output.extend(raw.chunks_exact(2).map(|b| {
+                f32::from(i16::from_le_bytes([b[0], b[1]])) / 32768.0
+            })),
+            SampleFormat::Cf32 => output.extend(raw.chunks_exact(4).map(|b| {
+                f32::from_bits(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            })),
+        }
+    }
+}
+
+/// Pair up interleaved I/Q values.
+pub(crate) fn to_complex(values: &[f32]) -> impl Iterator<Item = Complex> + '_ {
+    values
+        .chunks_exact(2)
+        .map(|pair| Complex::new(f64::from(pair[0]), f64::from(pair[1])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(format: SampleFormat, raw: &[u8]) -> Vec<f32> {
+        let mut values = Vec::new();
+        format.convert(raw, &mut values);
+        values
+    }
+
+    fn assert_close(got: &[f32], expected: &[f32]) {
+        assert_eq!(got.len(), expected.len(), "{:?} != {:?}", got, expected);
+        for (g, e) in got.iter().zip(expected) {
+            assert!((g - e).abs() < 1e-2, "{:?} != {:?}", got, expected);
+        }
+    }
+
+    #[test]
+    fn convert_unsigned_bytes() {
+        let got = convert(SampleFormat::Cu8, &[0, 255, 127, 128]);
+
+        assert_close(&got, &[-1.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn convert_signed_shorts() {
+        let raw: Vec<u8> = [i16::MIN, 0, 16384, -16384]
+            .iter()
+            .flat_map(|v| v.to_le_bytes().to_vec())
+            .collect();
+
+        let got = convert(SampleFormat::Cs16, &raw);
+
+        assert_eq!(got, vec![-1.0, 0.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn convert_floats() {
+        let expected = [0.25_f32, -0.75, 1.0, -1.0];
+        let raw: Vec<u8> = expected
+            .iter()
+            .flat_map(|v| v.to_bits().to_le_bytes().to_vec())
+            .collect();
+
+        let got = convert(SampleFormat::Cf32, &raw);
+
+        assert_eq!(got, expected);
+    }
+
+    #[test]
+    fn converted_values_are_appended() {
+        let mut values = vec![0.5];
+
+        SampleFormat::Cu8.convert(&[255, 0], &mut values);
+
+        assert_eq!(values.len(), 3);
+        assert_eq!(values[0], 0.5);
+    }
+
+    #[test]
+    fn every_format_has_a_name() {
+        let formats = [
+            SampleFormat::Cu8,
+            SampleFormat::Cs8,
+            SampleFormat::Cs16,
+            SampleFormat::Cf32,
+        ];
+
+        for &format in &formats {
+            assert_eq!(SampleFormat::from_name(format.name()), Some(format));
+        }
+    }
+
+    #[test]
+    fn pair_up_iq_values() {
+        let got: Vec<Complex> = to_complex(&[1.0, -1.0, 0.5, 0.25]).collect();
+
+        assert_eq!(got, vec![Complex::new(1.0, -1.0), Complex::new(0.5, 0.25)]);
+    }
+}
@@ -0,0 +1,461 @@
+use crate::{
+    iq::{SampleFormat, IQ_FORMAT, IQ_MEDIA_TYPE},
+    timestamp::samples_to_nanoseconds,
+};
+use byte_slice_cast::AsMutSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, Caps, CapsIntersectMode, ClockTime, CoreError, DebugCategory,
+    DebugColorFlags, Element, ErrorMessage, FlowError, Format, IntRange,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, ResourceError,
+};
+use gstreamer_base::{subclass::prelude::*, BaseSrc, BaseSrcExt};
+use std::{
+    fs::File,
+    io::{self, Read},
+    sync::Mutex,
+};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(Some(plugin), "rsiqsrc", Rank::None, IqSrc::get_type())
+}
+
+/// Reads raw complex IQ samples (e.g. a recording made with `rtl_sdr`) from
+/// a file or stdin.
+///
+/// Samples are converted to `application/x-iq` buffers of interleaved `f32`
+/// I and Q values, timestamped using the `sample-rate`.
+pub struct IqSrc {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl ObjectSubclass for IqSrc {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseSrc;
+
+    const NAME: &'static str = "RsIqSrc";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsiqsrc",
+                DebugColorFlags::empty(),
+                Some("Rust IQ sample source"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "IQ Source",
+            "Source/RF",
+            "Reads raw IQ samples recorded from a software defined radio",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            IQ_MEDIA_TYPE,
+            &[
+                ("format", &IQ_FORMAT),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+    }
+}
+
+impl ObjectImpl for IqSrc {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+
+        let element = obj.downcast_ref::<BaseSrc>().unwrap();
+        element.set_format(Format::Time);
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseSrc>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("location", ..) => {
+                let location = value.get().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing location from {:?} to {:?}",
+                    settings.location,
+                    location
+                );
+                settings.location = location;
+            },
+            Property("format", ..) => {
+                let name: String = value
+                    .get()
+                    .unwrap()
+                    .unwrap_or_else(|| DEFAULT_FORMAT.to_string());
+                match SampleFormat::from_name(&name) {
+                    Some(format) => {
+                        gst_info!(
+                            self.cat,
+                            obj: element,
+                            "Changing format from {} to {}",
+                            settings.format.name(),
+                            format.name()
+                        );
+                        settings.format = format;
+                    },
+                    None => gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Ignoring unknown sample format, \"{}\"",
+                        name
+                    ),
+                }
+            },
+            Property("sample-rate", ..) => {
+                let sample_rate = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing sample-rate from {} to {}",
+                    settings.sample_rate,
+                    sample_rate
+                );
+                settings.sample_rate = sample_rate;
+            },
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("location", ..) => Ok(settings.location.to_value()),
+            Property("format", ..) => Ok(settings.format.name().to_value()),
+            Property("sample-rate", ..) => Ok(settings.sample_rate.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for IqSrc {}
+
+impl BaseSrcImpl for IqSrc {
+    fn start(&self, element: &BaseSrc) -> Result<(), ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+
+        let reader: Box<dyn Read + Send> = match settings.location {
+            Some(ref location) if location != "-" => {
+                let file = File::open(location).map_err(|e| {
+                    gst_error_msg!(
+                        ResourceError::OpenRead,
+                        ["Unable to open \"{}\": {}", location, e]
+                    )
+                })?;
+                Box::new(file)
+            },
+            _ => Box::new(io::stdin()),
+        };
+
+        *self.state.lock().unwrap() = Some(State {
+            reader,
+            format: settings.format,
+            rate: settings.sample_rate,
+            samples: 0,
+        });
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Reading {} samples from {}",
+            settings.format.name(),
+            settings.location.as_deref().unwrap_or("stdin")
+        );
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &BaseSrc) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn is_seekable(&self, _element: &BaseSrc) -> bool {
+        false
+    }
+
+    fn get_caps(
+        &self,
+        _element: &BaseSrc,
+        filter: Option<&Caps>,
+    ) -> Option<Caps> {
+        let rate = self.settings.lock().unwrap().sample_rate;
+        let caps = Caps::new_simple(
+            IQ_MEDIA_TYPE,
+            &[("format", &IQ_FORMAT), ("rate", &(rate as i32))],
+        );
+
+        match filter {
+            Some(filter) => Some(
+                filter.intersect_with_mode(&caps, CapsIntersectMode::First),
+            ),
+            None => Some(caps),
+        }
+    }
+
+    fn create(
+        &self,
+        element: &BaseSrc,
+        _offset: u64,
+        length: u32,
+    ) -> Result<Buffer, FlowError> {
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no state yet"]
+            );
+            FlowError::NotNegotiated
+        })?;
+
+        // only ever read whole samples
+        let frame_size = state.format.frame_size();
+        let wanted =
+            std::cmp::max(1, length as usize / frame_size) * frame_size;
+        let mut raw = vec![0; wanted];
+        let read = read_fully(&mut state.reader, &mut raw).map_err(|e| {
+            gst_element_error!(
+                element,
+                ResourceError::Read,
+                ["Unable to read the IQ samples: {}", e]
+            );
+            FlowError::Error
+        })?;
+        raw.truncate(read - read % frame_size);
+
+        if raw.is_empty() {
+            gst_debug!(self.cat, obj: element, "Reached the end of the input");
+            return Err(FlowError::Eos);
+        }
+
+        let mut values = Vec::with_capacity(raw.len());
+        state.format.convert(&raw, &mut values);
+
+        let rate = u64::from(state.rate);
+        let start = state.samples;
+        let end = start + (raw.len() / frame_size) as u64;
+        state.samples = end;
+
+        let mut buffer =
+            Buffer::with_size(values.len() * std::mem::size_of::<f32>())
+                .ok_or(FlowError::Error)?;
+        {
+            let buffer = buffer.get_mut().unwrap();
+            let pts = samples_to_nanoseconds(start, rate);
+            buffer.set_pts(ClockTime::from_nseconds(pts));
+            buffer.set_duration(ClockTime::from_nseconds(
+                samples_to_nanoseconds(end, rate) - pts,
+            ));
+            buffer.set_offset(start);
+            buffer.set_offset_end(end);
+
+            let mut map = buffer.map_writable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer writable"]
+                );
+                FlowError::Error
+            })?;
+            map.as_mut_slice_of::<f32>()
+                .map_err(|_| FlowError::Error)?
+                .copy_from_slice(&values);
+        }
+
+        Ok(buffer)
+    }
+}
+
+/// Keep reading until `buf` is full or we reach the end of the input,
+/// returning the number of bytes read.
+fn read_fully<R: Read + ?Sized>(
+    reader: &mut R,
+    buf: &mut [u8],
+) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {},
+            Err(e) => return Err(e),
+        }
+    }
+
+    Ok(filled)
+}
+
+struct State {
+    reader: Box<dyn Read + Send>,
+    format: SampleFormat,
+    rate: u32,
+    /// The number of complex samples read so far.
+    samples: u64,
+}
+
+const DEFAULT_FORMAT: &str = "cu8";
+const DEFAULT_SAMPLE_RATE: u32 = 2_048_000;
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// The file to read from, or stdin when not set (or `-`).
+    location: Option<String>,
+    format: SampleFormat,
+    /// The number of complex samples per second.
+    sample_rate: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: None,
+            format: SampleFormat::Cu8,
+            sample_rate: DEFAULT_SAMPLE_RATE,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 3] = [
+    Property("location", |name| {
+        ParamSpec::string(
+            name,
+            "Location",
+            "The file to read IQ samples from (stdin if not set or \"-\")",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("format", |name| {
+        ParamSpec::string(
+            name,
+            "Format",
+            "The sample format (cu8 for rtl_sdr, cs8, cs16 or cf32)",
+            Some(DEFAULT_FORMAT),
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("sample-rate", |name| {
+        ParamSpec::uint(
+            name,
+            "Sample Rate",
+            "The number of complex samples per second",
+            1,
+            std::i32::MAX as u32,
+            DEFAULT_SAMPLE_RATE,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reader which hands out its data a few bytes at a time, being
+    /// interrupted along the way.
+    struct Trickle {
+        data: Vec<u8>,
+        interrupted: bool,
+    }
+
+    impl Read for Trickle {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if !self.interrupted {
+                self.interrupted = true;
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+
+            let n = buf.len().min(self.data.len()).min(3);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data.drain(..n);
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn keep_reading_until_the_buffer_is_full() {
+        let mut reader = Trickle {
+            data: (0..20).collect(),
+            interrupted: false,
+        };
+        let mut buf = [0; 16];
+
+        let read = read_fully(&mut reader, &mut buf).unwrap();
+
+        assert_eq!(read, 16);
+        assert_eq!(buf.to_vec(), (0..16).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn the_last_frame_can_be_partial() {
+        let format = SampleFormat::Cs16;
+        // two whole frames and half of another
+        let mut reader = Trickle {
+            data: (0..10).collect(),
+            interrupted: false,
+        };
+        let mut buf = [0; 16];
+
+        let read = read_fully(&mut reader, &mut buf).unwrap();
+
+        assert_eq!(read, 10);
+        assert_eq!(read - read % format.frame_size(), 8);
+        assert_eq!(read_fully(&mut reader, &mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn errors_are_passed_on() {
+        let mut buf = [0; 4];
+        let mut reader = io::repeat(0).take(0).chain(Broken);
+
+        assert!(read_fully(&mut reader, &mut buf).is_err());
+    }
+
+    struct Broken;
+
+    impl Read for Broken {
+        fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+    }
+}
@@ -0,0 +1,579 @@
+use crate::decibels::{from_decibels, to_decibels};
+use byte_slice_cast::AsSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags, Element,
+    ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange, Message,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::sync::Mutex;
+use transmission::{messages::LEVEL, MIN_LEVEL};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rslevelmeter",
+        Rank::None,
+        LevelMeter::get_type(),
+    )
+}
+
+/// A passthrough audio element which periodically posts the signal's RMS
+/// level, peak level and how many samples were clipped.
+///
+/// Unlike the squelch this measures everything, so it can be used to notice
+/// when a receiver has gone quiet or is being overdriven.
+pub struct LevelMeter {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl LevelMeter {
+    fn post_readings(&self, element: &BaseTransform, readings: Vec<Reading>) {
+        for reading in readings {
+            let structure = reading.to_structure();
+
+            gst_log!(self.cat, obj: element, "Posting {}", structure);
+
+            let msg =
+                Message::new_element(structure).src(Some(element)).build();
+            let _ = element.post_message(&msg);
+        }
+    }
+}
+
+impl ObjectSubclass for LevelMeter {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsLevelMeter";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rslevelmeter",
+                DebugColorFlags::empty(),
+                Some("Rust audio level meter"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "Level Meter",
+            "Filter/Analyzer/Audio",
+            "Periodically reports the RMS and peak level of the audio",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for LevelMeter {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("interval", ..) => {
+                let interval = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing interval from {} to {}",
+                    settings.interval,
+                    interval
+                );
+                settings.interval = interval;
+            },
+            Property("clip-level", ..) => {
+                let clip_level = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing clip-level from {} to {}",
+                    settings.clip_level,
+                    clip_level
+                );
+                settings.clip_level = clip_level;
+            },
+            _ => unimplemented!(),
+        }
+
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.meter.settings = *settings;
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("interval", ..) => Ok(settings.interval.to_value()),
+            Property("clip-level", ..) => Ok(settings.clip_level.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for LevelMeter {}
+
+impl BaseTransformImpl for LevelMeter {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            Some(ref mut state) => {
+                state.meter.reconfigure(info.rate(), info.channels())
+            },
+            None => {
+                *state = Some(State {
+                    meter: Meter::new(settings, info.rate(), info.channels()),
+                })
+            },
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        match event.view() {
+            EventView::Eos(..) => {
+                // report whatever was left over from the last interval
+                let reading = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_mut()
+                    .and_then(|state| state.meter.finish());
+                self.post_readings(element, reading.into_iter().collect());
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.meter.reset();
+                }
+            },
+            _ => {},
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let readings = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Negotiation,
+                    ["Have no state yet"]
+                );
+                FlowError::NotNegotiated
+            })?;
+
+            let map = buf.map_readable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer readable"]
+                );
+                FlowError::Error
+            })?;
+            let samples = map.as_slice_of::<f32>().map_err(|_| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Buffer isn't a whole number of samples"]
+                );
+                FlowError::Error
+            })?;
+
+            let mut readings = Vec::new();
+            state.meter.process(
+                samples,
+                buf.get_pts().nseconds(),
+                &mut readings,
+            );
+            readings
+        };
+
+        self.post_readings(element, readings);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+/// The levels measured over a single interval.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Reading {
+    timestamp: u64,
+    duration: u64,
+    rms: f64,
+    peak: f64,
+    clipped: u32,
+}
+
+impl Reading {
+    fn to_structure(&self) -> Structure {
+        Structure::new(
+            LEVEL,
+            &[
+                ("timestamp", &self.timestamp),
+                ("duration", &self.duration),
+                ("rms", &self.rms),
+                ("peak", &self.peak),
+                ("clipped", &self.clipped),
+            ],
+        )
+    }
+}
+
+/// Keeps a running total of the audio seen in the current interval.
+#[derive(Debug)]
+struct Meter {
+    settings: Settings,
+    rate: u64,
+    channels: usize,
+    sum_of_squares: f64,
+    /// The largest absolute sample value seen in this interval.
+    peak: f64,
+    clipped: u32,
+    /// The number of frames seen so far in this interval.
+    frames: u64,
+    /// The timestamp of the first frame in this interval.
+    interval_start: u64,
+    /// The timestamp we expect the next frame to have.
+    next_timestamp: u64,
+}
+
+impl Meter {
+    fn new(settings: Settings, rate: u32, channels: u32) -> Meter {
+        Meter {
+            settings,
+            rate: u64::from(rate),
+            channels: channels as usize,
+            sum_of_squares: 0.0,
+            peak: 0.0,
+            clipped: 0,
+            frames: 0,
+            interval_start: 0,
+            next_timestamp: 0,
+        }
+    }
+
+    fn reconfigure(&mut self, rate: u32, channels: u32) {
+        self.rate = u64::from(rate);
+        self.channels = channels as usize;
+        self.reset();
+    }
+
+    fn reset(&mut self) {
+        self.sum_of_squares = 0.0;
+        self.peak = 0.0;
+        self.clipped = 0;
+        self.frames = 0;
+    }
+
+    fn frames_per_interval(&self) -> u64 {
+        std::cmp::max(1, self.rate * u64::from(self.settings.interval) / 1000)
+    }
+
+    fn frame_timestamp(&self, base: u64, frame: u64) -> u64 {
+        base + frame * 1_000_000_000 / self.rate
+    }
+
+    fn process(
+        &mut self,
+        samples: &[f32],
+        pts: Option<u64>,
+        readings: &mut Vec<Reading>,
+    ) {
+        // buffers without a timestamp are assumed to follow on from the
+        // previous one
+        let base = pts.unwrap_or(self.next_timestamp);
+        let frames_per_interval = self.frames_per_interval();
+        let clip_level = from_decibels(self.settings.clip_level);
+
+        for (i, frame) in samples.chunks(self.channels).enumerate() {
+            if self.frames == 0 {
+                self.interval_start = self.frame_timestamp(base, i as u64);
+            }
+
+            for &sample in frame {
+                let sample = f64::from(sample);
+                self.sum_of_squares += sample * sample;
+                self.peak = self.peak.max(sample.abs());
+                if sample.abs() >= clip_level {
+                    self.clipped = self.clipped.saturating_add(1);
+                }
+            }
+            self.frames += 1;
+
+            if self.frames >= frames_per_interval {
+                let interval_end = self.frame_timestamp(base, i as u64 + 1);
+                readings.push(self.take_reading(interval_end));
+            }
+        }
+
+        let frames = (samples.len() / self.channels) as u64;
+        self.next_timestamp = self.frame_timestamp(base, frames);
+    }
+
+    fn take_reading(&mut self, interval_end: u64) -> Reading {
+        let samples = self.frames as f64 * self.channels as f64;
+        let reading = Reading {
+            timestamp: self.interval_start,
+            duration: interval_end.saturating_sub(self.interval_start),
+            rms: to_decibels((self.sum_of_squares / samples).sqrt())
+                .max(MIN_LEVEL),
+            peak: to_decibels(self.peak).max(MIN_LEVEL),
+            clipped: self.clipped,
+        };
+
+        self.reset();
+        reading
+    }
+
+    /// The stream has finished, report the partial interval (if any).
+    fn finish(&mut self) -> Option<Reading> {
+        if self.frames == 0 {
+            return None;
+        }
+
+        let end = self.next_timestamp;
+        Some(self.take_reading(end))
+    }
+}
+
+struct State {
+    meter: Meter,
+}
+
+const DEFAULT_INTERVAL: u32 = 1000;
+const DEFAULT_CLIP_LEVEL: f64 = -0.1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// How often (in milliseconds) to post a reading.
+    interval: u32,
+    /// Samples at or above this level (in dBFS) count as clipped.
+    clip_level: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            interval: DEFAULT_INTERVAL,
+            clip_level: DEFAULT_CLIP_LEVEL,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 2] = [
+    Property("interval", |name| {
+        ParamSpec::uint(
+            name,
+            "Interval",
+            "How often (in ms) to post the signal level",
+            1,
+            std::u32::MAX,
+            DEFAULT_INTERVAL,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("clip-level", |name| {
+        ParamSpec::double(
+            name,
+            "Clip Level",
+            "Samples at or above this level (in dBFS) count as clipped",
+            -200.0,
+            0.0,
+            DEFAULT_CLIP_LEVEL,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 16_000;
+    const MS: u64 = 1_000_000;
+
+    fn meter(interval: u32) -> Meter {
+        let settings = Settings {
+            interval,
+            ..Settings::default()
+        };
+        Meter::new(settings, RATE, 1)
+    }
+
+    fn sine(amplitude: f64, frequency: f64, frames: usize) -> Vec<f32> {
+        (0..frames)
+            .map(|i| {
+                let t = i as f64 / f64::from(RATE);
+                (amplitude * (2.0 * PI * frequency * t).sin()) as f32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rms_and_peak_of_a_sine() {
+        let mut meter = meter(100);
+        let mut readings = Vec::new();
+
+        // exactly 100 cycles of a 1 kHz half-scale sine
+        meter.process(&sine(0.5, 1000.0, 1600), Some(0), &mut readings);
+
+        assert_eq!(readings.len(), 1);
+        let reading = readings[0];
+        // a sine's RMS is 3 dB below its peak
+        assert!((reading.peak - -6.0206).abs() < 0.01, "{:?}", reading);
+        assert!((reading.rms - -9.0309).abs() < 0.01, "{:?}", reading);
+        assert_eq!(reading.clipped, 0);
+    }
+
+    #[test]
+    fn clipped_samples_are_counted() {
+        let mut meter = meter(100);
+        let mut readings = Vec::new();
+
+        meter.process(&[0.5, 1.0, -1.0, 0.999], Some(0), &mut readings);
+        let reading = meter.finish().unwrap();
+
+        assert_eq!(reading.clipped, 3);
+        assert_eq!(reading.peak, 0.0);
+    }
+
+    #[test]
+    fn silence_is_reported_as_the_minimum_level() {
+        let mut meter = meter(100);
+        let mut readings = Vec::new();
+
+        meter.process(&[0.0; 1600], Some(0), &mut readings);
+
+        assert_eq!(readings[0].rms, MIN_LEVEL);
+        assert_eq!(readings[0].peak, MIN_LEVEL);
+    }
+
+    #[test]
+    fn a_reading_is_taken_every_interval() {
+        let mut meter = meter(250);
+        let mut readings = Vec::new();
+
+        // 1.1s split across uneven buffers, the second without a timestamp
+        let audio = sine(0.5, 1000.0, 17_600);
+        meter.process(&audio[..5000], Some(0), &mut readings);
+        meter.process(&audio[5000..], None, &mut readings);
+
+        let timestamps: Vec<_> =
+            readings.iter().map(|r| (r.timestamp, r.duration)).collect();
+        assert_eq!(
+            timestamps,
+            vec![
+                (0, 250 * MS),
+                (250 * MS, 250 * MS),
+                (500 * MS, 250 * MS),
+                (750 * MS, 250 * MS),
+            ]
+        );
+
+        // the last 100 ms are reported when the stream finishes
+        let last = meter.finish().unwrap();
+        assert_eq!((last.timestamp, last.duration), (1000 * MS, 100 * MS));
+        assert!(meter.finish().is_none());
+    }
+
+    #[test]
+    fn timestamps_follow_the_buffers() {
+        let mut meter = meter(100);
+        let mut readings = Vec::new();
+
+        meter.process(&[0.25; 1600], Some(5000 * MS), &mut readings);
+
+        assert_eq!(readings[0].timestamp, 5000 * MS);
+        assert_eq!(readings[0].duration, 100 * MS);
+    }
+}
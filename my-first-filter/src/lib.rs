@@ -3,9 +3,43 @@ extern crate glib;
 #[macro_use]
 extern crate gstreamer;
 
+mod ani;
+mod biquad;
+mod channelizer;
+mod ctcss;
+mod dcs;
+mod decibels;
+mod demod;
+mod denoise;
+mod fft;
+mod goertzel;
+mod iq;
+mod iq_src;
+mod level_meter;
+mod mdc;
+mod nbfm_demod;
+mod pfb;
 mod rgb_2_gray;
+mod squelch;
+mod timestamp;
+mod transcribe;
+mod transmission_sink;
+mod voice_filter;
 
+pub use ani::Ani;
+pub use channelizer::Channelizer;
+pub use ctcss::Ctcss;
+pub use dcs::Dcs;
+pub use denoise::Denoise;
+pub use iq_src::IqSrc;
+pub use level_meter::LevelMeter;
+pub use mdc::Mdc;
+pub use nbfm_demod::NbfmDemod;
 pub use rgb_2_gray::Rgb2Gray;
+pub use squelch::Squelch;
+pub use transcribe::Transcribe;
+pub use transmission_sink::TransmissionSink;
+pub use voice_filter::VoiceFilter;
 
 use glib::BoolError;
 use gstreamer::Plugin;
@@ -24,5 +58,18 @@ gstreamer::gst_plugin_define!(
 
 fn plugin_init(plugin: &Plugin) -> Result<(), BoolError> {
     rgb_2_gray::register(plugin)?;
+    squelch::register(plugin)?;
+    transmission_sink::register(plugin)?;
+    transcribe::register(plugin)?;
+    ctcss::register(plugin)?;
+    dcs::register(plugin)?;
+    ani::register(plugin)?;
+    mdc::register(plugin)?;
+    voice_filter::register(plugin)?;
+    denoise::register(plugin)?;
+    level_meter::register(plugin)?;
+    iq_src::register(plugin)?;
+    nbfm_demod::register(plugin)?;
+    channelizer::register(plugin)?;
     Ok(())
 }
@@ -0,0 +1,1034 @@
+use crate::biquad::Biquad;
+use byte_slice_cast::AsMutSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags, Element,
+    ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange, Message,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::{f64::consts::PI, sync::Mutex};
+use transmission::messages::{MDC, TRANSMISSION_END, TRANSMISSION_START};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(Some(plugin), "rsmdc", Rank::None, Mdc::get_type())
+}
+
+/// Both MDC-1200 and FleetSync send data at 1200 baud.
+const BAUD: f64 = 1200.0;
+/// The data is sent using fast FSK, switching between 1200 Hz and 1800 Hz,
+/// so mixing with the frequency in the middle turns the tones into ±300 Hz.
+const CENTRE: f64 = 1500.0;
+const DEVIATION: f64 = 300.0;
+/// Only the baseband signal (and not the image at twice the centre
+/// frequency) should make it through the low-pass filter after mixing.
+const CUTOFF: f64 = 800.0;
+
+/// The 40-bit MDC-1200 sync word, sent after the bit-sync preamble.
+const MDC_SYNC: u64 = 0x07_092A_446F;
+/// The MDC-1200 opcode for an emergency alarm.
+const MDC_EMERGENCY: u8 = 0x00;
+/// The 16-bit FleetSync sync word, sent after the bit-sync preamble.
+const FLEETSYNC_SYNC: u32 = 0x23EB;
+/// The FleetSync command for an emergency alarm.
+const FLEETSYNC_EMERGENCY: u8 = 0x00;
+
+/// How long to keep muting once a burst stops looking like data, in
+/// milliseconds.
+const STRIP_HANG: u32 = 30;
+
+/// A passthrough audio element which decodes Motorola MDC-1200 and Kenwood
+/// FleetSync data bursts.
+///
+/// This needs to go after a `rssquelch`. Whenever a burst is decoded, an
+/// `mdc` element message with the transmission's `sequence`, the `unit` ID,
+/// its `format` (`"mdc1200"` or `"fleetsync"`) and whether it was an
+/// `emergency` alarm is posted on the bus.
+///
+/// Setting `strip` mutes the bursts, so the audio passed downstream only
+/// contains voice.
+pub struct Mdc {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Mdc {
+    fn on_transmission_start(&self, element: &BaseTransform, sequence: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(ref mut state) = *state {
+            gst_debug!(
+                self.cat,
+                obj: element,
+                "Listening for data bursts in transmission {}",
+                sequence
+            );
+            state.sequence = Some(sequence);
+            state.burst = None;
+            state.decoder.reset();
+        }
+    }
+
+    fn on_transmission_end(&self, _element: &BaseTransform, sequence: u32) {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(ref mut state) = *state {
+            if state.sequence == Some(sequence) {
+                state.sequence = None;
+                state.decoder.reset();
+            }
+        }
+    }
+
+    fn post_burst(
+        &self,
+        element: &BaseTransform,
+        sequence: u32,
+        burst: &Burst,
+    ) {
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Transmission {} contained a {} burst from unit {}{}",
+            sequence,
+            burst.format.name(),
+            burst.unit,
+            if burst.emergency { " (emergency)" } else { "" }
+        );
+
+        let structure = Structure::new(
+            MDC,
+            &[
+                ("sequence", &sequence),
+                ("unit", &burst.unit),
+                ("format", &burst.format.name()),
+                ("emergency", &burst.emergency),
+            ],
+        );
+        let msg = Message::new_element(structure).src(Some(element)).build();
+        let _ = element.post_message(&msg);
+    }
+}
+
+impl ObjectSubclass for Mdc {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsMdc";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsmdc",
+                DebugColorFlags::empty(),
+                Some("Rust MDC-1200 and FleetSync decoder"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "MDC-1200 and FleetSync Decoder",
+            "Filter/Analyzer/Audio",
+            "Decodes the unit ID and emergency status from MDC-1200 and \
+             FleetSync data bursts",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        // the highest tone is 1800 Hz, so anything slower than telephone
+        // quality can't carry it
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(8000, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for Mdc {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("strip", ..) => {
+                let strip = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing strip from {} to {}",
+                    settings.strip,
+                    strip
+                );
+                settings.strip = strip;
+            },
+            Property("post-messages", ..) => {
+                let post_messages = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing post-messages from {} to {}",
+                    settings.post_messages,
+                    post_messages
+                );
+                settings.post_messages = post_messages;
+            },
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+        let state = self.state.lock().unwrap();
+        let burst = state.as_ref().and_then(|s| s.burst.as_ref());
+
+        match *prop {
+            Property("strip", ..) => Ok(settings.strip.to_value()),
+            Property("post-messages", ..) => {
+                Ok(settings.post_messages.to_value())
+            },
+            Property("unit", ..) => {
+                Ok(burst.map(|b| b.unit.clone()).to_value())
+            },
+            Property("emergency", ..) => {
+                Ok(burst.map(|b| b.emergency).unwrap_or(false).to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Mdc {}
+
+impl BaseTransformImpl for Mdc {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let mut state = self.state.lock().unwrap();
+
+        match *state {
+            Some(ref mut state) => state.decoder.reconfigure(info.rate()),
+            None => {
+                *state = Some(State {
+                    decoder: Decoder::new(info.rate()),
+                    sequence: None,
+                    burst: None,
+                })
+            },
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        match event.view() {
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+
+                    if s.get_name() == TRANSMISSION_START {
+                        self.on_transmission_start(element, sequence);
+                    } else if s.get_name() == TRANSMISSION_END {
+                        self.on_transmission_end(element, sequence);
+                    }
+                }
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.decoder.reset();
+                    state.sequence = None;
+                    state.burst = None;
+                }
+            },
+            _ => {},
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        let settings = *self.settings.lock().unwrap();
+
+        let (sequence, bursts) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Negotiation,
+                    ["Have no state yet"]
+                );
+                FlowError::NotNegotiated
+            })?;
+
+            // radios only send data while transmitting
+            let sequence = match state.sequence {
+                Some(sequence) => sequence,
+                None => return Ok(FlowSuccess::Ok),
+            };
+
+            let mut map = buf.map_writable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer writable"]
+                );
+                FlowError::Error
+            })?;
+            let samples = map.as_mut_slice_of::<f32>().map_err(|_| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Buffer isn't a whole number of samples"]
+                );
+                FlowError::Error
+            })?;
+
+            let mut bursts = Vec::new();
+            state.decoder.process(samples, settings.strip, &mut bursts);
+            if let Some(last) = bursts.last() {
+                state.burst = Some(last.clone());
+            }
+
+            (sequence, bursts)
+        };
+
+        if settings.post_messages {
+            for burst in &bursts {
+                self.post_burst(element, sequence, burst);
+            }
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+struct State {
+    decoder: Decoder,
+    /// The transmission we're currently listening to.
+    sequence: Option<u32>,
+    /// The last burst decoded in this transmission.
+    burst: Option<Burst>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Format {
+    Mdc1200,
+    FleetSync,
+}
+
+impl Format {
+    fn name(self) -> &'static str {
+        match self {
+            Format::Mdc1200 => "mdc1200",
+            Format::FleetSync => "fleetsync",
+        }
+    }
+}
+
+/// A decoded data burst.
+#[derive(Debug, Clone, PartialEq)]
+struct Burst {
+    format: Format,
+    /// The unit ID, written the way the radio displays it (4 hex digits for
+    /// MDC-1200, `fleet-unit` for FleetSync).
+    unit: String,
+    emergency: bool,
+}
+
+/// The part of the element which actually looks at samples.
+///
+/// The audio is mixed down so the two FSK tones sit either side of 0 Hz and
+/// the sign of the instantaneous frequency gives the tone. A bit clock
+/// which locks onto the tone changes then feeds bits to the MDC-1200 and
+/// FleetSync framers, which look for their sync words and check the CRC of
+/// whatever follows.
+#[derive(Debug)]
+struct Decoder {
+    rate: u32,
+    /// The local oscillator's phase, in radians.
+    oscillator: f64,
+    oscillator_step: f64,
+    filter_i: Biquad,
+    filter_q: Biquad,
+    previous: (f64, f64),
+    samples_per_bit: f64,
+    /// How far through the current bit we are, in samples.
+    phase: f64,
+    /// The instantaneous frequency summed over the current bit.
+    integral: f64,
+    previous_tone: bool,
+    mdc: MdcFramer,
+    fleetsync: FleetSyncFramer,
+    /// Smoothed power of the whole signal and of the band the tones are in,
+    /// used to decide whether we're in the middle of a burst.
+    power: f64,
+    band_power: f64,
+    /// Smoothed squared difference between the instantaneous frequency and
+    /// the FSK deviation.
+    jitter: f64,
+    smoothing: f64,
+    /// How many more samples to mute.
+    muting: usize,
+}
+
+impl Decoder {
+    fn new(rate: u32) -> Decoder {
+        let rate_f = f64::from(rate);
+
+        Decoder {
+            rate,
+            oscillator: 0.0,
+            oscillator_step: 2.0 * PI * CENTRE / rate_f,
+            filter_i: Biquad::low_pass(CUTOFF, rate_f),
+            filter_q: Biquad::low_pass(CUTOFF, rate_f),
+            previous: (0.0, 0.0),
+            samples_per_bit: rate_f / BAUD,
+            phase: 0.0,
+            integral: 0.0,
+            previous_tone: false,
+            mdc: MdcFramer::default(),
+            fleetsync: FleetSyncFramer::default(),
+            power: 0.0,
+            band_power: 0.0,
+            jitter: 0.0,
+            // a time constant of about 5 ms
+            smoothing: 1.0 - (-1.0 / (0.005 * rate_f)).exp(),
+            muting: 0,
+        }
+    }
+
+    fn reconfigure(&mut self, rate: u32) {
+        *self = Decoder::new(rate);
+    }
+
+    fn reset(&mut self) {
+        *self = Decoder::new(self.rate);
+    }
+
+    fn process(
+        &mut self,
+        samples: &mut [f32],
+        strip: bool,
+        bursts: &mut Vec<Burst>,
+    ) {
+        for sample in samples {
+            let x = f64::from(*sample);
+
+            let i = self.filter_i.push(x * self.oscillator.cos());
+            let q = self.filter_q.push(-x * self.oscillator.sin());
+            self.oscillator =
+                (self.oscillator + self.oscillator_step) % (2.0 * PI);
+
+            // the phase change between samples is the instantaneous frequency
+            let (pi, pq) = self.previous;
+            let angle = (q * pi - i * pq).atan2(i * pi + q * pq);
+            let frequency = angle * f64::from(self.rate) / (2.0 * PI);
+            self.previous = (i, q);
+
+            if let Some(burst) = self.clock(frequency) {
+                bursts.push(burst);
+            }
+
+            if strip && self.strip(x, i * i + q * q, frequency) {
+                *sample = 0.0;
+            }
+        }
+    }
+
+    /// Recover the bit clock, returning any burst which was completed.
+    fn clock(&mut self, frequency: f64) -> Option<Burst> {
+        let tone = frequency < 0.0;
+
+        // a tone change marks the start of a bit, nudge the clock towards it
+        if tone != self.previous_tone {
+            let error = if self.phase < self.samples_per_bit / 2.0 {
+                self.phase
+            } else {
+                self.phase - self.samples_per_bit
+            };
+            self.phase -= error * 0.25;
+            self.previous_tone = tone;
+        }
+
+        self.integral += frequency;
+        self.phase += 1.0;
+
+        if self.phase < self.samples_per_bit {
+            return None;
+        }
+
+        self.phase -= self.samples_per_bit;
+        let bit = self.integral < 0.0;
+        self.integral = 0.0;
+
+        let mdc = self.mdc.push(bit);
+        let fleetsync = self.fleetsync.push(bit);
+        mdc.or(fleetsync)
+    }
+
+    /// Decide whether this sample is part of a burst and should be muted.
+    ///
+    /// A burst is (nearly) all energy in the tone band, and the
+    /// instantaneous frequency sits at the FSK deviation instead of
+    /// wandering around like it does for voice.
+    fn strip(&mut self, sample: f64, band_power: f64, frequency: f64) -> bool {
+        let error = frequency.abs() - DEVIATION;
+
+        self.power += self.smoothing * (sample * sample - self.power);
+        // mixing halves the amplitude, so a pure tone has a quarter of the
+        // power in the band (and half of it overall)
+        self.band_power +=
+            self.smoothing * (2.0 * band_power - self.band_power);
+        self.jitter += self.smoothing * (error * error - self.jitter);
+
+        let in_band = self.band_power >= 0.8 * self.power && self.power > 0.0;
+        let steady = self.jitter.sqrt() < DEVIATION;
+
+        if in_band && steady {
+            self.muting = self.rate as usize * STRIP_HANG as usize / 1000;
+        }
+
+        if self.muting > 0 {
+            self.muting -= 1;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Picks MDC-1200 packets out of a stream of bits.
+///
+/// MDC-1200 is differentially encoded, so polarity doesn't matter. After
+/// the sync word come 112 bits, interleaved over 16 columns of 7, holding 4
+/// bytes of data (opcode, argument and unit ID), a CRC, a status byte and 7
+/// bytes of FEC. Packets with a bad CRC are dropped.
+#[derive(Debug, Default)]
+struct MdcFramer {
+    previous: bool,
+    /// The last 40 bits, newest in the lowest bit.
+    shift: u64,
+    packet: Option<Vec<bool>>,
+}
+
+impl MdcFramer {
+    const PACKET_BITS: usize = 112;
+
+    fn push(&mut self, bit: bool) -> Option<Burst> {
+        let data = bit != self.previous;
+        self.previous = bit;
+
+        if let Some(ref mut packet) = self.packet {
+            packet.push(data);
+
+            if packet.len() < Self::PACKET_BITS {
+                return None;
+            }
+            let packet = self.packet.take().unwrap();
+            return MdcFramer::parse(&packet);
+        }
+
+        self.shift = ((self.shift << 1) | u64::from(data)) & 0xFF_FFFF_FFFF;
+        if (self.shift ^ MDC_SYNC).count_ones() <= 2 {
+            self.packet = Some(Vec::with_capacity(Self::PACKET_BITS));
+        }
+
+        None
+    }
+
+    fn parse(packet: &[bool]) -> Option<Burst> {
+        let mut bytes = [0_u8; 14];
+
+        for i in 0..16 {
+            for j in 0..7 {
+                let bit = i * 7 + j;
+                // bytes are sent least significant bit first
+                if packet[j * 16 + i] {
+                    bytes[bit / 8] |= 1 << (bit % 8);
+                }
+            }
+        }
+
+        let crc = u16::from(bytes[4]) | u16::from(bytes[5]) << 8;
+        if crc16(&bytes[..4]) != crc {
+            return None;
+        }
+
+        let unit = u16::from(bytes[2]) << 8 | u16::from(bytes[3]);
+
+        Some(Burst {
+            format: Format::Mdc1200,
+            unit: format!("{:04X}", unit),
+            emergency: bytes[0] == MDC_EMERGENCY,
+        })
+    }
+}
+
+/// Picks FleetSync headers out of a stream of bits.
+///
+/// The sync word follows a bit-sync preamble of alternating ones and zeroes.
+/// The 48-bit header after it holds the command, the sender's fleet and unit
+/// number, 4 bits of flags and a CRC, most significant bit first. Packets
+/// with a bad CRC are dropped.
+#[derive(Debug, Default)]
+struct FleetSyncFramer {
+    /// The last 32 bits (preamble and sync), newest in the lowest bit.
+    shift: u32,
+    header: Option<(u64, usize, bool)>,
+}
+
+impl FleetSyncFramer {
+    const HEADER_BITS: usize = 48;
+
+    fn push(&mut self, bit: bool) -> Option<Burst> {
+        if let Some((ref mut header, ref mut count, inverted)) = self.header {
+            *header = (*header << 1) | u64::from(bit != inverted);
+            *count += 1;
+
+            if *count < Self::HEADER_BITS {
+                return None;
+            }
+            let header = *header;
+            self.header = None;
+            return FleetSyncFramer::parse(header);
+        }
+
+        self.shift = (self.shift << 1) | u32::from(bit);
+
+        let preamble = self.shift >> 16;
+        let sync = self.shift & 0xFFFF;
+        let alternating = (preamble ^ 0xAAAA).count_ones() <= 2
+            || (preamble ^ 0x5555).count_ones() <= 2;
+
+        if alternating {
+            if (sync ^ FLEETSYNC_SYNC).count_ones() <= 1 {
+                self.header = Some((0, 0, false));
+            } else if (sync ^ !FLEETSYNC_SYNC & 0xFFFF).count_ones() <= 1 {
+                self.header = Some((0, 0, true));
+            }
+        }
+
+        None
+    }
+
+    fn parse(header: u64) -> Option<Burst> {
+        let bytes = [
+            (header >> 40) as u8,
+            (header >> 32) as u8,
+            (header >> 24) as u8,
+            (header >> 16) as u8,
+        ];
+        let crc = header as u16;
+        if crc16(&bytes) != crc {
+            return None;
+        }
+
+        let command = bytes[0];
+        let fleet = u32::from(bytes[1]);
+        let unit = (u32::from(bytes[2]) << 4) | (u32::from(bytes[3]) >> 4);
+
+        Some(Burst {
+            format: Format::FleetSync,
+            // fleets are numbered from 100 and units from 1000
+            unit: format!("{}-{}", 100 + fleet, 1000 + unit),
+            emergency: command == FLEETSYNC_EMERGENCY,
+        })
+    }
+}
+
+/// The 16-bit CRC used by MDC-1200 (CRC-CCITT, bit-reversed and inverted).
+fn crc16(bytes: &[u8]) -> u16 {
+    let mut crc = 0_u16;
+
+    for &byte in bytes {
+        crc ^= u16::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x8408
+            } else {
+                crc >> 1
+            };
+        }
+    }
+
+    !crc
+}
+
+const DEFAULT_STRIP: bool = false;
+const DEFAULT_POST_MESSAGES: bool = true;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// Mute the data bursts.
+    strip: bool,
+    /// Post a message on the bus for every burst.
+    post_messages: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            strip: DEFAULT_STRIP,
+            post_messages: DEFAULT_POST_MESSAGES,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 4] = [
+    Property("strip", |name| {
+        ParamSpec::boolean(
+            name,
+            "Strip",
+            "Mute data bursts so only voice is passed downstream",
+            DEFAULT_STRIP,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("post-messages", |name| {
+        ParamSpec::boolean(
+            name,
+            "Post Messages",
+            "Post a message on the bus for every burst which is decoded",
+            DEFAULT_POST_MESSAGES,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("unit", |name| {
+        ParamSpec::string(
+            name,
+            "Unit",
+            "The unit ID from the last burst in the current transmission, if \
+             there was one",
+            None,
+            ParamFlags::READABLE,
+        )
+    }),
+    Property("emergency", |name| {
+        ParamSpec::boolean(
+            name,
+            "Emergency",
+            "Whether the last burst in the current transmission was an \
+             emergency alarm",
+            false,
+            ParamFlags::READABLE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATE: u32 = 16_000;
+
+    fn mdc_bytes(opcode: u8, argument: u8, unit: u16) -> [u8; 14] {
+        let mut bytes = [0; 14];
+        bytes[..4].copy_from_slice(&[
+            opcode,
+            argument,
+            (unit >> 8) as u8,
+            unit as u8,
+        ]);
+        let crc = crc16(&bytes[..4]);
+        bytes[4] = crc as u8;
+        bytes[5] = (crc >> 8) as u8;
+
+        bytes
+    }
+
+    /// Spread a packet over the 16 columns of 7 bits, the way
+    /// [`MdcFramer::parse()`] reads them back.
+    fn interleave(bytes: &[u8; 14]) -> Vec<bool> {
+        let mut packet = vec![false; MdcFramer::PACKET_BITS];
+
+        for i in 0..16 {
+            for j in 0..7 {
+                let bit = i * 7 + j;
+                packet[j * 16 + i] = bytes[bit / 8] & (1 << (bit % 8)) != 0;
+            }
+        }
+
+        packet
+    }
+
+    /// The bits sent over the air for a MDC-1200 burst.
+    fn mdc_burst(bytes: &[u8; 14]) -> Vec<bool> {
+        let mut data: Vec<bool> = (0..24).map(|i| i % 2 == 0).collect();
+        data.extend((0..40).rev().map(|i| MDC_SYNC & (1 << i) != 0));
+        data.extend(interleave(bytes));
+        data.extend(vec![false; 16]);
+
+        // each data bit is sent as a change (or not) from the last bit
+        let mut line = false;
+        data.into_iter()
+            .map(|bit| {
+                line ^= bit;
+                line
+            })
+            .collect()
+    }
+
+    fn fleetsync_header(command: u8, fleet: u8, unit: u16) -> u64 {
+        let bytes = [command, fleet, (unit >> 4) as u8, (unit << 4) as u8];
+        let crc = crc16(&bytes);
+
+        bytes
+            .iter()
+            .fold(0_u64, |header, &byte| (header << 8) | u64::from(byte))
+            << 16
+            | u64::from(crc)
+    }
+
+    /// The bits sent over the air for a FleetSync burst.
+    fn fleetsync_burst(header: u64) -> Vec<bool> {
+        let mut bits: Vec<bool> = (0..24).map(|i| i % 2 == 0).collect();
+        bits.extend((0..16).rev().map(|i| FLEETSYNC_SYNC & (1 << i) != 0));
+        bits.extend((0..48).rev().map(|i| header & (1 << i) != 0));
+        bits.extend(vec![false; 16]);
+
+        bits
+    }
+
+    /// Send bits using the 1200 Hz and 1800 Hz tones.
+    fn fsk(bits: &[bool]) -> Vec<f32> {
+        let samples = (bits.len() as f64 * f64::from(RATE) / BAUD) as usize;
+        let mut phase = 0.0;
+
+        (0..samples)
+            .map(|n| {
+                let bit = bits[(n as f64 * BAUD / f64::from(RATE)) as usize];
+                let frequency = if bit { 1200.0 } else { 1800.0 };
+                phase += 2.0 * PI * frequency / f64::from(RATE);
+                (0.5 * phase.sin()) as f32
+            })
+            .collect()
+    }
+
+    /// Something which sounds more like voice than data.
+    fn voice(seconds: f64) -> Vec<f32> {
+        let samples = (seconds * f64::from(RATE)) as usize;
+
+        (0..samples)
+            .map(|n| {
+                let t = n as f64 / f64::from(RATE);
+                let low = (2.0 * PI * 300.0 * t).sin();
+                let high = (2.0 * PI * 3_100.0 * t).sin();
+                (0.2 * low + 0.2 * high) as f32
+            })
+            .collect()
+    }
+
+    fn decode(audio: &mut [f32], strip: bool) -> Vec<Burst> {
+        let mut decoder = Decoder::new(RATE);
+        let mut bursts = Vec::new();
+        decoder.process(audio, strip, &mut bursts);
+        bursts
+    }
+
+    fn burst(format: Format, unit: &str, emergency: bool) -> Burst {
+        Burst {
+            format,
+            unit: unit.to_string(),
+            emergency,
+        }
+    }
+
+    #[test]
+    fn crc_check_value() {
+        // CRC-16/KERMIT's check value, inverted
+        assert_eq!(crc16(b"123456789"), !0x2189);
+        assert_eq!(crc16(&[]), 0xFFFF);
+    }
+
+    #[test]
+    fn parse_a_mdc1200_packet() {
+        let packet = interleave(&mdc_bytes(0x01, 0x80, 0x1234));
+
+        assert_eq!(
+            MdcFramer::parse(&packet),
+            Some(burst(Format::Mdc1200, "1234", false))
+        );
+    }
+
+    #[test]
+    fn parse_a_mdc1200_emergency() {
+        let packet = interleave(&mdc_bytes(MDC_EMERGENCY, 0x80, 0x0A0B));
+
+        assert_eq!(
+            MdcFramer::parse(&packet),
+            Some(burst(Format::Mdc1200, "0A0B", true))
+        );
+    }
+
+    #[test]
+    fn mdc1200_packets_with_a_bad_crc_are_dropped() {
+        let mut bytes = mdc_bytes(0x01, 0x80, 0x1234);
+        bytes[3] ^= 0x01;
+
+        assert_eq!(MdcFramer::parse(&interleave(&bytes)), None);
+    }
+
+    #[test]
+    fn parse_a_fleetsync_header() {
+        let header = fleetsync_header(0x02, 1, 234);
+
+        assert_eq!(
+            FleetSyncFramer::parse(header),
+            Some(burst(Format::FleetSync, "101-1234", false))
+        );
+        assert_eq!(
+            FleetSyncFramer::parse(fleetsync_header(FLEETSYNC_EMERGENCY, 0, 1)),
+            Some(burst(Format::FleetSync, "100-1001", true))
+        );
+    }
+
+    #[test]
+    fn fleetsync_headers_with_a_bad_crc_are_dropped() {
+        let header = fleetsync_header(0x02, 1, 234) ^ (1 << 30);
+
+        assert_eq!(FleetSyncFramer::parse(header), None);
+    }
+
+    #[test]
+    fn framers_find_the_sync_word() {
+        let mut mdc = MdcFramer::default();
+        let bursts: Vec<_> = mdc_burst(&mdc_bytes(0x01, 0x80, 0x1234))
+            .into_iter()
+            .filter_map(|bit| mdc.push(bit))
+            .collect();
+        assert_eq!(bursts, vec![burst(Format::Mdc1200, "1234", false)]);
+
+        // FleetSync works with either polarity
+        for &inverted in &[false, true] {
+            let mut fleetsync = FleetSyncFramer::default();
+            let bursts: Vec<_> = fleetsync_burst(fleetsync_header(2, 1, 234))
+                .into_iter()
+                .filter_map(|bit| fleetsync.push(bit != inverted))
+                .collect();
+            assert_eq!(
+                bursts,
+                vec![burst(Format::FleetSync, "101-1234", false)]
+            );
+        }
+    }
+
+    #[test]
+    fn decode_bursts_from_audio() {
+        let mut audio = voice(0.1);
+        audio.extend(fsk(&mdc_burst(&mdc_bytes(0x01, 0x80, 0x1234))));
+        audio.extend(voice(0.1));
+        audio.extend(fsk(&fleetsync_burst(fleetsync_header(
+            FLEETSYNC_EMERGENCY,
+            1,
+            234,
+        ))));
+
+        assert_eq!(
+            decode(&mut audio, false),
+            vec![
+                burst(Format::Mdc1200, "1234", false),
+                burst(Format::FleetSync, "101-1234", true),
+            ]
+        );
+    }
+
+    #[test]
+    fn voice_on_its_own_isnt_a_burst() {
+        assert!(decode(&mut voice(1.0), false).is_empty());
+    }
+
+    #[test]
+    fn strip_mutes_the_burst() {
+        let before = voice(0.2);
+        let data = fsk(&mdc_burst(&mdc_bytes(0x01, 0x80, 0x1234)));
+        let after = voice(0.2);
+
+        let mut audio = before.clone();
+        audio.extend(&data);
+        audio.extend(&after);
+        let bursts = decode(&mut audio, true);
+
+        // the burst is still decoded
+        assert_eq!(bursts, vec![burst(Format::Mdc1200, "1234", false)]);
+
+        let (start, end) = (before.len(), before.len() + data.len());
+        assert_eq!(&audio[..start], &before[..]);
+        // it takes about 20 ms to notice the burst has started
+        let settle = RATE as usize / 40;
+        assert!(audio[start + settle..end].iter().all(|&x| x == 0.0));
+        // ... and it keeps muting for a little while afterwards
+        let hang = RATE as usize * STRIP_HANG as usize / 1000;
+        assert_eq!(&audio[end + hang * 2..], &after[hang * 2..]);
+    }
+}
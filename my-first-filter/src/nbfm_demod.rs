@@ -0,0 +1,593 @@
+use crate::{
+    demod::{Demodulator, Mode},
+    iq::{self, IQ_FORMAT, IQ_MEDIA_TYPE},
+    timestamp::samples_to_nanoseconds,
+};
+use byte_slice_cast::{AsMutSliceOf, AsSliceOf};
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, Caps, ClockTime, CoreError, DebugCategory, DebugColorFlags,
+    Element, Event, EventView, FlowError, FlowSuccess, IntRange, Pad,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank,
+};
+use gstreamer_audio::AUDIO_FORMAT_F32;
+use std::sync::Mutex;
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rsnbfmdemod",
+        Rank::None,
+        NbfmDemod::get_type(),
+    )
+}
+
+/// Demodulates a narrowband FM channel from the complex IQ samples produced
+/// by `rsiqsrc`. AM and SSB channels can be demodulated too, by changing the
+/// `mode`.
+///
+/// The channel is `offset` Hz away from the centre of the IQ stream (for SSB
+/// this is where the suppressed carrier would be). An
+/// optional carrier squelch mutes the audio when nothing is transmitting, so
+/// it works with the level-based `rssquelch` further down the pipeline.
+pub struct NbfmDemod {
+    cat: DebugCategory,
+    sinkpad: Pad,
+    srcpad: Pad,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl NbfmDemod {
+    fn sink_chain(
+        &self,
+        _pad: &Pad,
+        element: &Element,
+        buffer: Buffer,
+    ) -> Result<FlowSuccess, FlowError> {
+        let (audio, pts, caps) = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Negotiation,
+                    ["Have no caps yet"]
+                );
+                FlowError::NotNegotiated
+            })?;
+
+            let map = buffer.map_readable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer readable"]
+                );
+                FlowError::Error
+            })?;
+            let values = map.as_slice_of::<f32>().map_err(|_| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Buffer isn't a whole number of samples"]
+                );
+                FlowError::Error
+            })?;
+
+            if state.start.is_none() {
+                state.start = Some(buffer.get_pts().nseconds().unwrap_or(0));
+            }
+
+            let samples: Vec<_> = iq::to_complex(values).collect();
+            let mut audio = Vec::new();
+            state.demodulator.process(&samples, &mut audio);
+
+            // timestamps are worked out by counting samples so rounding
+            // errors don't build up
+            let pts = state.start.unwrap_or(0)
+                + samples_to_nanoseconds(
+                    state.samples,
+                    u64::from(state.audio_rate),
+                );
+            state.samples += audio.len() as u64;
+
+            (audio, pts, state.pending_caps.take())
+        };
+
+        if let Some(caps) = caps {
+            self.srcpad.push_event(Event::new_caps(&caps).build());
+        }
+
+        if audio.is_empty() {
+            return Ok(FlowSuccess::Ok);
+        }
+
+        let mut output =
+            Buffer::with_size(audio.len() * std::mem::size_of::<f32>())
+                .ok_or(FlowError::Error)?;
+        {
+            let output = output.get_mut().unwrap();
+            output.set_pts(ClockTime::from_nseconds(pts));
+
+            let mut map = output.map_writable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer writable"]
+                );
+                FlowError::Error
+            })?;
+            map.as_mut_slice_of::<f32>()
+                .map_err(|_| FlowError::Error)?
+                .copy_from_slice(&audio);
+        }
+
+        self.srcpad.push(output)
+    }
+
+    fn sink_event(&self, _pad: &Pad, element: &Element, event: Event) -> bool {
+        match event.view() {
+            EventView::Caps(c) => {
+                let rate = match c
+                    .get_caps()
+                    .get_structure(0)
+                    .and_then(|s| s.get_some::<i32>("rate").ok())
+                {
+                    Some(rate) if rate > 0 => rate as u32,
+                    _ => return false,
+                };
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Configured for caps {}",
+                    c.get_caps()
+                );
+
+                let settings = *self.settings.lock().unwrap();
+                let state = State::new(rate, settings);
+                let caps = audio_caps(settings.audio_rate);
+                *self.state.lock().unwrap() = Some(state);
+
+                // we output audio, not IQ samples
+                return self.srcpad.push_event(Event::new_caps(&caps).build());
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.demodulator.reset();
+                    state.start = None;
+                    state.samples = 0;
+                }
+            },
+            _ => {},
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, _pad: &Pad, _element: &Element, event: Event) -> bool {
+        self.sinkpad.push_event(event)
+    }
+}
+
+impl ObjectSubclass for NbfmDemod {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = Element;
+
+    const NAME: &'static str = "RsNbfmDemod";
+
+    glib_object_subclass!();
+
+    fn new_with_class(klass: &ClassStruct<Self>) -> Self {
+        let templ = klass.get_pad_template("sink").unwrap();
+        let sinkpad = Pad::new_from_template(&templ, Some("sink"));
+        let templ = klass.get_pad_template("src").unwrap();
+        let srcpad = Pad::new_from_template(&templ, Some("src"));
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            NbfmDemod::catch_panic_pad_function(
+                parent,
+                || Err(FlowError::Error),
+                |demod, element| demod.sink_chain(pad, element, buffer),
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            NbfmDemod::catch_panic_pad_function(
+                parent,
+                || false,
+                |demod, element| demod.sink_event(pad, element, event),
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            NbfmDemod::catch_panic_pad_function(
+                parent,
+                || false,
+                |demod, element| demod.src_event(pad, element, event),
+            )
+        });
+
+        Self {
+            cat: DebugCategory::new(
+                "rsnbfmdemod",
+                DebugColorFlags::empty(),
+                Some("Rust narrowband FM demodulator"),
+            ),
+            sinkpad,
+            srcpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "NBFM Demodulator",
+            "Filter/Converter/RF/Audio",
+            "Demodulates a narrowband FM (or AM or SSB) channel from IQ \
+             samples",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let caps = Caps::new_simple(
+            IQ_MEDIA_TYPE,
+            &[
+                ("format", &IQ_FORMAT),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+            ],
+        );
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+    }
+}
+
+impl ObjectImpl for NbfmDemod {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+
+        let element = obj.downcast_ref::<Element>().unwrap();
+        element.add_pad(&self.sinkpad).unwrap();
+        element.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<Element>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("offset", ..) => {
+                let offset = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing offset from {} to {}",
+                    settings.offset,
+                    offset
+                );
+                settings.offset = offset;
+            },
+            Property("mode", ..) => {
+                let name: String = value
+                    .get()
+                    .unwrap()
+                    .unwrap_or_else(|| DEFAULT_MODE.to_string());
+                match Mode::from_name(&name) {
+                    Some(mode) => {
+                        gst_info!(
+                            self.cat,
+                            obj: element,
+                            "Changing mode from {} to {}",
+                            settings.mode.name(),
+                            mode.name()
+                        );
+                        settings.mode = mode;
+                    },
+                    None => gst_warning!(
+                        self.cat,
+                        obj: element,
+                        "Ignoring unknown mode, \"{}\"",
+                        name
+                    ),
+                }
+            },
+            Property("bandwidth", ..) => {
+                let bandwidth = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing bandwidth from {} to {}",
+                    settings.bandwidth,
+                    bandwidth
+                );
+                settings.bandwidth = bandwidth;
+            },
+            Property("deviation", ..) => {
+                let deviation = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing deviation from {} to {}",
+                    settings.deviation,
+                    deviation
+                );
+                settings.deviation = deviation;
+            },
+            Property("squelch", ..) => {
+                let squelch = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing squelch from {} to {}",
+                    settings.squelch,
+                    squelch
+                );
+                settings.squelch = squelch;
+            },
+            Property("audio-rate", ..) => {
+                let audio_rate = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing audio-rate from {} to {}",
+                    settings.audio_rate,
+                    audio_rate
+                );
+                settings.audio_rate = audio_rate;
+            },
+            _ => unimplemented!(),
+        }
+
+        // retune straight away, sending new caps if the audio rate changed
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.reconfigure(*settings);
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("offset", ..) => Ok(settings.offset.to_value()),
+            Property("mode", ..) => Ok(settings.mode.name().to_value()),
+            Property("bandwidth", ..) => Ok(settings.bandwidth.to_value()),
+            Property("deviation", ..) => Ok(settings.deviation.to_value()),
+            Property("squelch", ..) => Ok(settings.squelch.to_value()),
+            Property("audio-rate", ..) => Ok(settings.audio_rate.to_value()),
+            Property("level", ..) => {
+                let level = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|s| s.demodulator.level())
+                    .unwrap_or(-200.0);
+                Ok(level.to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for NbfmDemod {}
+
+fn audio_caps(rate: u32) -> Caps {
+    Caps::new_simple(
+        "audio/x-raw",
+        &[
+            ("format", &AUDIO_FORMAT_F32.to_string()),
+            ("rate", &(rate as i32)),
+            ("channels", &1),
+            ("layout", &"interleaved"),
+        ],
+    )
+}
+
+struct State {
+    /// The rate of the incoming IQ samples.
+    input_rate: u32,
+    audio_rate: u32,
+    demodulator: Demodulator,
+    /// The timestamp of the first buffer.
+    start: Option<u64>,
+    /// The number of audio samples sent so far.
+    samples: u64,
+    /// Caps which need to be sent before the next buffer.
+    pending_caps: Option<Caps>,
+}
+
+impl State {
+    fn new(input_rate: u32, settings: Settings) -> State {
+        State {
+            input_rate,
+            audio_rate: settings.audio_rate,
+            demodulator: settings.demodulator(input_rate),
+            start: None,
+            samples: 0,
+            pending_caps: None,
+        }
+    }
+
+    fn reconfigure(&mut self, settings: Settings) {
+        self.demodulator = settings.demodulator(self.input_rate);
+
+        if settings.audio_rate != self.audio_rate {
+            // start counting again from the current position
+            self.start = self.start.map(|start| {
+                start
+                    + samples_to_nanoseconds(
+                        self.samples,
+                        u64::from(self.audio_rate),
+                    )
+            });
+            self.samples = 0;
+            self.audio_rate = settings.audio_rate;
+            self.pending_caps = Some(audio_caps(settings.audio_rate));
+        }
+    }
+}
+
+const DEFAULT_OFFSET: f64 = 0.0;
+const DEFAULT_MODE: &str = "nbfm";
+const DEFAULT_BANDWIDTH: f64 = 0.0;
+const DEFAULT_DEVIATION: f64 = 2_500.0;
+const DEFAULT_SQUELCH: f64 = -200.0;
+const DEFAULT_AUDIO_RATE: u32 = 16_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// How far (in Hz) the channel is from the centre of the IQ stream.
+    offset: f64,
+    mode: Mode,
+    /// The width (in Hz) of the channel, or 0 to use the mode's default.
+    bandwidth: f64,
+    /// The frequency deviation (in Hz) which gives full-scale audio.
+    deviation: f64,
+    /// The carrier level (in dBFS) needed to let audio through.
+    squelch: f64,
+    audio_rate: u32,
+}
+
+impl Settings {
+    fn demodulator(&self, input_rate: u32) -> Demodulator {
+        Demodulator::new(
+            self.mode,
+            f64::from(input_rate),
+            self.offset,
+            self.bandwidth(),
+            self.deviation,
+            self.squelch,
+            f64::from(self.audio_rate),
+        )
+    }
+
+    fn bandwidth(&self) -> f64 {
+        if self.bandwidth > 0.0 {
+            self.bandwidth
+        } else {
+            self.mode.default_bandwidth()
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            offset: DEFAULT_OFFSET,
+            mode: Mode::Nbfm,
+            bandwidth: DEFAULT_BANDWIDTH,
+            deviation: DEFAULT_DEVIATION,
+            squelch: DEFAULT_SQUELCH,
+            audio_rate: DEFAULT_AUDIO_RATE,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 7] = [
+    Property("offset", |name| {
+        ParamSpec::double(
+            name,
+            "Offset",
+            "How far (in Hz) the channel is from the centre frequency",
+            std::f64::MIN,
+            std::f64::MAX,
+            DEFAULT_OFFSET,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("mode", |name| {
+        ParamSpec::string(
+            name,
+            "Mode",
+            "How the audio is carried (nbfm, am, usb or lsb)",
+            Some(DEFAULT_MODE),
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("bandwidth", |name| {
+        ParamSpec::double(
+            name,
+            "Bandwidth",
+            "The width (in Hz) of the channel, or 0 for the mode's usual \
+             bandwidth",
+            0.0,
+            std::f64::MAX,
+            DEFAULT_BANDWIDTH,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("deviation", |name| {
+        ParamSpec::double(
+            name,
+            "Deviation",
+            "The frequency deviation (in Hz) which gives full-scale audio",
+            1.0,
+            std::f64::MAX,
+            DEFAULT_DEVIATION,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("squelch", |name| {
+        ParamSpec::double(
+            name,
+            "Squelch",
+            "The carrier level (in dBFS) needed to let audio through",
+            -200.0,
+            0.0,
+            DEFAULT_SQUELCH,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("audio-rate", |name| {
+        ParamSpec::uint(
+            name,
+            "Audio Rate",
+            "The sample rate of the demodulated audio",
+            1,
+            std::i32::MAX as u32,
+            DEFAULT_AUDIO_RATE,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("level", |name| {
+        ParamSpec::double(
+            name,
+            "Level",
+            "The carrier level (in dBFS) of the channel",
+            -200.0,
+            100.0,
+            -200.0,
+            ParamFlags::READABLE,
+        )
+    }),
+];
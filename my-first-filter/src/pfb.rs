@@ -0,0 +1,226 @@
+use crate::{
+    demod,
+    fft::{self, Complex},
+};
+use std::f64::consts::PI;
+
+/// Channels are never packed closer together than this (in Hz), so even the
+/// filter bank's narrowest output has room for a channel and its neighbours.
+const MIN_SPACING: f64 = 24_000.0;
+
+/// Splits a wideband IQ stream into evenly spaced channels, using a 2x
+/// oversampled polyphase filter bank.
+///
+/// The stream is divided into `channel_count()` channels, `spacing()` Hz
+/// apart, with channel `k` centred on `k * spacing()` (so the top half are
+/// really the negative frequencies). Each channel comes out at
+/// `output_rate()`, which is twice the spacing, so a signal anywhere between
+/// two channel centres can still be tuned in from the nearest one without
+/// anything folding over it.
+///
+/// Every channel costs about the same as filtering one of them on its own
+/// and an FFT is shared between all of them, which makes this much cheaper
+/// than running a separate demodulator over the full stream for each channel.
+#[derive(Debug, Clone)]
+pub(crate) struct PolyphaseChannelizer {
+    input_rate: f64,
+    channels: usize,
+    /// The prototype low-pass filter, padded to a multiple of `channels`.
+    taps: Vec<f64>,
+    /// The last `taps.len()` inputs, stored twice so they can always be read
+    /// as one contiguous slice.
+    history: Vec<Complex>,
+    position: usize,
+    phase: usize,
+    /// How many samples have been seen, modulo `channels`.
+    index: usize,
+    /// `e^(-j2πn/channels)` for every `n`.
+    twiddles: Vec<Complex>,
+    /// The most recent output from each channel, before being rotated back
+    /// into place.
+    outputs: Vec<Complex>,
+    /// Where `index` was when `outputs` were calculated.
+    output_index: usize,
+}
+
+impl PolyphaseChannelizer {
+    /// Create a filter bank for an IQ stream sampled at `input_rate`, which
+    /// can cleanly tune in channels up to `bandwidth` Hz wide.
+    pub fn new(input_rate: f64, bandwidth: f64) -> PolyphaseChannelizer {
+        let min_spacing = MIN_SPACING.max(2.0 * bandwidth);
+        let mut channels = 2;
+        while input_rate / (channels * 2) as f64 >= min_spacing {
+            channels *= 2;
+        }
+
+        let spacing = input_rate / channels as f64;
+        // a channel which sits halfway between two centres needs to get
+        // through untouched, while anything which would fold onto it when
+        // decimating needs to be removed
+        let transition = (spacing - bandwidth).max(spacing * 0.2);
+        let mut taps = demod::low_pass_taps(spacing, transition, input_rate);
+        let len = (taps.len() + channels - 1) / channels * channels;
+        taps.resize(len, 0.0);
+        // the FFT scales everything down by the number of channels
+        for tap in taps.iter_mut() {
+            *tap *= channels as f64;
+        }
+
+        let twiddles = (0..channels)
+            .map(|n| {
+                let angle = -2.0 * PI * n as f64 / channels as f64;
+                Complex::new(angle.cos(), angle.sin())
+            })
+            .collect();
+
+        PolyphaseChannelizer {
+            input_rate,
+            channels,
+            history: vec![Complex::default(); len * 2],
+            taps,
+            position: 0,
+            phase: 0,
+            index: 0,
+            twiddles,
+            outputs: vec![Complex::default(); channels],
+            output_index: 0,
+        }
+    }
+
+    pub fn channel_count(&self) -> usize {
+        self.channels
+    }
+
+    /// The distance (in Hz) between channel centres.
+    pub fn spacing(&self) -> f64 {
+        self.input_rate / self.channels as f64
+    }
+
+    /// The rate (in Hz) each channel comes out at.
+    pub fn output_rate(&self) -> f64 {
+        self.input_rate / self.decimation() as f64
+    }
+
+    /// How far (in Hz) from the centre of the stream a channel can be before
+    /// it ends up in the channel which straddles the edge of the band.
+    pub fn max_offset(&self) -> f64 {
+        (self.input_rate - self.spacing()) / 2.0
+    }
+
+    /// Find the channel nearest to `offset` Hz from the centre of the
+    /// stream, returning it along with how far `offset` is from its centre.
+    ///
+    /// Returns `None` if `offset` is outside the stream, or so close to its
+    /// edge that the nearest channel is the one centred on `±input_rate/2`.
+    /// That channel is half made up of the SDR's anti-aliasing roll-off and
+    /// half of whatever folded over from the other end of the band.
+    pub fn nearest_channel(&self, offset: f64) -> Option<(usize, f64)> {
+        let spacing = self.spacing();
+        let nearest = (offset / spacing).round();
+        if !nearest.is_finite() || nearest.abs() >= (self.channels / 2) as f64 {
+            return None;
+        }
+        let channel = (nearest as i64).rem_euclid(self.channels as i64);
+
+        Some((channel as usize, offset - nearest * spacing))
+    }
+
+    fn decimation(&self) -> usize {
+        self.channels / 2
+    }
+
+    /// Add a sample, returning `true` when a new output is available from
+    /// every channel.
+    pub fn push(&mut self, sample: Complex) -> bool {
+        let len = self.taps.len();
+        self.history[self.position] = sample;
+        self.history[self.position + len] = sample;
+        self.position = (self.position + 1) % len;
+        self.index = (self.index + 1) % self.channels;
+
+        self.phase += 1;
+        if self.phase < self.decimation() {
+            return false;
+        }
+        self.phase = 0;
+
+        // split the filter into one branch per channel, so branch `q` only
+        // sees every `channels`'th input starting `q` samples ago
+        let window = &self.history[self.position..self.position + len];
+        for value in self.outputs.iter_mut() {
+            *value = Complex::default();
+        }
+        for (i, (&tap, &value)) in
+            self.taps.iter().zip(window.iter().rev()).enumerate()
+        {
+            let branch = &mut self.outputs[i % self.channels];
+            branch.re += tap * value.re;
+            branch.im += tap * value.im;
+        }
+
+        // ... then the FFT shifts each channel down to 0 Hz
+        fft::ifft(&mut self.outputs);
+        self.output_index = self.index;
+
+        true
+    }
+
+    /// The latest output from a channel.
+    pub fn output(&self, channel: usize) -> Complex {
+        // the branches were lined up with the most recent sample rather than
+        // the first, so each channel needs its phase corrected
+        let rotation = (channel * self.output_index) % self.channels;
+        self.outputs[channel] * self.twiddles[rotation]
+    }
+
+    /// Forget about any samples seen so far.
+    pub fn reset(&mut self) {
+        for value in self.history.iter_mut() {
+            *value = Complex::default();
+        }
+        self.position = 0;
+        self.phase = 0;
+        self.index = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channels_are_found_by_offset() {
+        let bank = PolyphaseChannelizer::new(1_024_000.0, 12_500.0);
+        assert_eq!(bank.channel_count(), 32);
+        assert_eq!(bank.spacing(), 32_000.0);
+
+        assert_eq!(bank.nearest_channel(0.0), Some((0, 0.0)));
+        assert_eq!(bank.nearest_channel(65_000.0), Some((2, 1_000.0)));
+        // negative frequencies are in the top half
+        assert_eq!(bank.nearest_channel(-65_000.0), Some((30, -1_000.0)));
+    }
+
+    #[test]
+    fn frequencies_outside_the_band_are_rejected() {
+        let bank = PolyphaseChannelizer::new(1_024_000.0, 12_500.0);
+
+        assert_eq!(bank.nearest_channel(600_000.0), None);
+        assert_eq!(bank.nearest_channel(-600_000.0), None);
+        assert_eq!(bank.nearest_channel(f64::NAN), None);
+        assert_eq!(bank.nearest_channel(f64::INFINITY), None);
+    }
+
+    #[test]
+    fn the_channel_on_the_edge_of_the_band_is_rejected() {
+        let bank = PolyphaseChannelizer::new(1_024_000.0, 12_500.0);
+        assert_eq!(bank.max_offset(), 496_000.0);
+
+        assert_eq!(bank.nearest_channel(512_000.0), None);
+        assert_eq!(bank.nearest_channel(-512_000.0), None);
+        assert_eq!(bank.nearest_channel(496_000.0), None);
+        assert_eq!(bank.nearest_channel(-496_000.0), None);
+
+        assert_eq!(bank.nearest_channel(495_000.0), Some((15, 15_000.0)));
+        assert_eq!(bank.nearest_channel(-495_000.0), Some((17, -15_000.0)));
+    }
+}
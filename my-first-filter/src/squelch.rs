@@ -0,0 +1,870 @@
+use crate::decibels::to_decibels;
+use byte_slice_cast::AsSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags, Element,
+    ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange, Message,
+    PadDirection, PadPresence, PadTemplate, Plugin, Rank, Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::sync::Mutex;
+use transmission::messages::{TRANSMISSION_END, TRANSMISSION_START};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rssquelch",
+        Rank::None,
+        Squelch::get_type(),
+    )
+}
+
+/// A passthrough audio element which watches the signal level and splits the
+/// stream up into individual transmissions.
+///
+/// Whenever a transmission starts or ends a custom downstream event is
+/// inserted into the stream (so elements further down the pipeline can react
+/// to it in-band) and a matching element message is posted on the bus.
+pub struct Squelch {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl Squelch {
+    fn push_transitions(
+        &self,
+        element: &BaseTransform,
+        transitions: Vec<Transition>,
+    ) {
+        if transitions.is_empty() {
+            return;
+        }
+
+        let src_pad = element.get_static_pad("src").unwrap();
+
+        for transition in transitions {
+            let structure = transition.to_structure();
+
+            gst_debug!(self.cat, obj: element, "Emitting {}", structure);
+
+            let msg = Message::new_element(structure.clone())
+                .src(Some(element))
+                .build();
+            let _ = element.post_message(&msg);
+
+            if !src_pad
+                .push_event(Event::new_custom_downstream(structure).build())
+            {
+                gst_warning!(
+                    self.cat,
+                    obj: element,
+                    "Downstream didn't handle the {} event",
+                    transition.name()
+                );
+            }
+        }
+    }
+
+    /// Take any transitions which were waiting for the current buffer to be
+    /// sent downstream.
+    fn take_pending(&self) -> Vec<Transition> {
+        match *self.state.lock().unwrap() {
+            Some(ref mut state) => {
+                std::mem::replace(&mut state.pending, Vec::new())
+            },
+            None => Vec::new(),
+        }
+    }
+}
+
+impl ObjectSubclass for Squelch {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsSquelch";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rssquelch",
+                DebugColorFlags::empty(),
+                Some("Rust audio squelch"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "Squelch",
+            "Filter/Analyzer/Audio",
+            "Detects the start and end of radio transmissions",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::AlwaysInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for Squelch {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("open-threshold", ..) => {
+                let open_threshold = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing open-threshold from {} to {}",
+                    settings.open_threshold,
+                    open_threshold
+                );
+                settings.open_threshold = open_threshold;
+            },
+            Property("close-threshold", ..) => {
+                let close_threshold = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing close-threshold from {} to {}",
+                    settings.close_threshold,
+                    close_threshold
+                );
+                settings.close_threshold = close_threshold;
+            },
+            Property("hang-time", ..) => {
+                let hang_time = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing hang-time from {} to {}",
+                    settings.hang_time,
+                    hang_time
+                );
+                settings.hang_time = hang_time;
+            },
+            Property("window", ..) => {
+                let window = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing window from {} to {}",
+                    settings.window,
+                    window
+                );
+                settings.window = window;
+            },
+            _ => unimplemented!(),
+        }
+
+        // make sure the detector picks up the new settings
+        if let Some(ref mut state) = *self.state.lock().unwrap() {
+            state.detector.settings = *settings;
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("open-threshold", ..) => {
+                Ok(settings.open_threshold.to_value())
+            },
+            Property("close-threshold", ..) => {
+                Ok(settings.close_threshold.to_value())
+            },
+            Property("hang-time", ..) => Ok(settings.hang_time.to_value()),
+            Property("window", ..) => Ok(settings.window.to_value()),
+            Property("open", ..) => {
+                let open = self
+                    .state
+                    .lock()
+                    .unwrap()
+                    .as_ref()
+                    .map(|s| s.detector.is_open())
+                    .unwrap_or(false);
+                Ok(open.to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Squelch {}
+
+impl BaseTransformImpl for Squelch {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let settings = *self.settings.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+
+        // a renegotiation shouldn't lose track of an in-progress transmission
+        match *state {
+            Some(ref mut state) => {
+                state.detector.reconfigure(info.rate(), info.channels())
+            },
+            None => {
+                *state = Some(State {
+                    detector: Detector::new(
+                        settings,
+                        info.rate(),
+                        info.channels(),
+                    ),
+                    pending: Vec::new(),
+                })
+            },
+        }
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &BaseTransform, event: Event) -> bool {
+        match event.view() {
+            EventView::Eos(..) => {
+                // close off whatever we were in the middle of so downstream
+                // sees a matching end for every start
+                let mut transitions = self.take_pending();
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    transitions.extend(state.detector.finish());
+                }
+                self.push_transitions(element, transitions);
+            },
+            EventView::FlushStop(..) => {
+                if let Some(ref mut state) = *self.state.lock().unwrap() {
+                    state.detector.reset();
+                    state.pending.clear();
+                }
+            },
+            _ => {},
+        }
+
+        self.parent_sink_event(element, event)
+    }
+
+    fn transform_ip(
+        &self,
+        element: &BaseTransform,
+        buf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        // an end which overlapped the previous buffer could only be sent
+        // once that buffer had gone downstream
+        let pending = self.take_pending();
+        self.push_transitions(element, pending);
+
+        let transitions = {
+            let mut state_guard = self.state.lock().unwrap();
+            let state = state_guard.as_mut().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Negotiation,
+                    ["Have no state yet"]
+                );
+                FlowError::NotNegotiated
+            })?;
+
+            let map = buf.map_readable().ok_or_else(|| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Failed to map buffer readable"]
+                );
+                FlowError::Error
+            })?;
+            let samples = map.as_slice_of::<f32>().map_err(|_| {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Buffer isn't a whole number of samples"]
+                );
+                FlowError::Error
+            })?;
+
+            let pts = buf.get_pts().nseconds();
+            let buffer_start = pts.unwrap_or(state.detector.next_timestamp);
+            let mut transitions = Vec::new();
+            state.detector.process(samples, pts, &mut transitions);
+
+            // everything goes out before the buffer, except an end which
+            // still needs some of this buffer's audio
+            let (before, after) = transitions
+                .into_iter()
+                .partition::<Vec<_>, _>(|t| t.timestamp() <= buffer_start);
+            let (starts, ends) = after
+                .into_iter()
+                .partition::<Vec<_>, _>(Transition::is_start);
+            state.pending.extend(ends);
+            before.into_iter().chain(starts).collect::<Vec<_>>()
+        };
+
+        self.push_transitions(element, transitions);
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+/// The squelch just opened or closed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Transition {
+    Start {
+        sequence: u32,
+        timestamp: u64,
+        level: f64,
+    },
+    End {
+        sequence: u32,
+        start: u64,
+        timestamp: u64,
+    },
+}
+
+impl Transition {
+    fn is_start(&self) -> bool {
+        match *self {
+            Transition::Start { .. } => true,
+            Transition::End { .. } => false,
+        }
+    }
+
+    /// When the transition happened. For an end, this is the last time the
+    /// squelch was still active.
+    fn timestamp(&self) -> u64 {
+        match *self {
+            Transition::Start { timestamp, .. } => timestamp,
+            Transition::End { timestamp, .. } => timestamp,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match *self {
+            Transition::Start { .. } => TRANSMISSION_START,
+            Transition::End { .. } => TRANSMISSION_END,
+        }
+    }
+
+    fn to_structure(&self) -> Structure {
+        match *self {
+            Transition::Start {
+                sequence,
+                timestamp,
+                level,
+            } => Structure::new(
+                TRANSMISSION_START,
+                &[
+                    ("sequence", &sequence),
+                    ("timestamp", &timestamp),
+                    ("level", &level),
+                ],
+            ),
+            Transition::End {
+                sequence,
+                start,
+                timestamp,
+            } => Structure::new(
+                TRANSMISSION_END,
+                &[
+                    ("sequence", &sequence),
+                    ("start", &start),
+                    ("timestamp", &timestamp),
+                    ("duration", &timestamp.saturating_sub(start)),
+                ],
+            ),
+        }
+    }
+}
+
+/// The part of the squelch which actually looks at samples.
+///
+/// Audio is broken up into fixed-size windows and the RMS level of each
+/// window (across all channels) is compared against the open and close
+/// thresholds. Once the level drops below the close threshold the squelch
+/// waits `hang-time` milliseconds before deciding the transmission is over,
+/// so short pauses between words don't split a transmission in two.
+#[derive(Debug)]
+struct Detector {
+    settings: Settings,
+    rate: u64,
+    channels: usize,
+    /// Sum of the squared samples seen so far in this window.
+    sum_of_squares: f64,
+    /// The number of frames seen so far in this window.
+    frames: u64,
+    /// The timestamp of the first frame in this window.
+    window_start: u64,
+    /// The timestamp we expect the next frame to have.
+    next_timestamp: u64,
+    open: Option<Open>,
+    sequence: u32,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct Open {
+    start: u64,
+    last_active: u64,
+}
+
+impl Detector {
+    fn new(settings: Settings, rate: u32, channels: u32) -> Detector {
+        Detector {
+            settings,
+            rate: u64::from(rate),
+            channels: channels as usize,
+            sum_of_squares: 0.0,
+            frames: 0,
+            window_start: 0,
+            next_timestamp: 0,
+            open: None,
+            sequence: 0,
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        self.open.is_some()
+    }
+
+    fn reconfigure(&mut self, rate: u32, channels: u32) {
+        self.rate = u64::from(rate);
+        self.channels = channels as usize;
+        self.sum_of_squares = 0.0;
+        self.frames = 0;
+    }
+
+    fn reset(&mut self) {
+        self.sum_of_squares = 0.0;
+        self.frames = 0;
+        self.open = None;
+    }
+
+    fn frames_per_window(&self) -> u64 {
+        std::cmp::max(1, self.rate * u64::from(self.settings.window) / 1000)
+    }
+
+    fn frame_timestamp(&self, base: u64, frame: u64) -> u64 {
+        base + frame * 1_000_000_000 / self.rate
+    }
+
+    fn process(
+        &mut self,
+        samples: &[f32],
+        pts: Option<u64>,
+        transitions: &mut Vec<Transition>,
+    ) {
+        // buffers without a timestamp are assumed to follow on from the
+        // previous one
+        let base = pts.unwrap_or(self.next_timestamp);
+        let frames_per_window = self.frames_per_window();
+
+        for (i, frame) in samples.chunks(self.channels).enumerate() {
+            if self.frames == 0 {
+                self.window_start = self.frame_timestamp(base, i as u64);
+            }
+
+            self.sum_of_squares += frame
+                .iter()
+                .map(|&s| f64::from(s) * f64::from(s))
+                .sum::<f64>();
+            self.frames += 1;
+
+            if self.frames >= frames_per_window {
+                let window_end = self.frame_timestamp(base, i as u64 + 1);
+                self.end_of_window(window_end, transitions);
+            }
+        }
+
+        let frames = (samples.len() / self.channels) as u64;
+        self.next_timestamp = self.frame_timestamp(base, frames);
+    }
+
+    fn end_of_window(
+        &mut self,
+        window_end: u64,
+        transitions: &mut Vec<Transition>,
+    ) {
+        let samples = self.frames as f64 * self.channels as f64;
+        let rms = (self.sum_of_squares / samples).sqrt();
+        let level = to_decibels(rms);
+        let hang_time = u64::from(self.settings.hang_time) * 1_000_000;
+
+        self.sum_of_squares = 0.0;
+        self.frames = 0;
+
+        match self.open {
+            None if level >= self.settings.open_threshold => {
+                self.sequence += 1;
+                self.open = Some(Open {
+                    start: self.window_start,
+                    last_active: window_end,
+                });
+                transitions.push(Transition::Start {
+                    sequence: self.sequence,
+                    timestamp: self.window_start,
+                    level,
+                });
+            },
+            Some(ref mut open) if level >= self.settings.close_threshold => {
+                open.last_active = window_end;
+            },
+            Some(open)
+                if window_end.saturating_sub(open.last_active) >= hang_time =>
+            {
+                self.open = None;
+                transitions.push(Transition::End {
+                    sequence: self.sequence,
+                    start: open.start,
+                    timestamp: open.last_active,
+                });
+            },
+            _ => {},
+        }
+    }
+
+    /// The stream has finished, close any open transmissions.
+    fn finish(&mut self) -> Option<Transition> {
+        self.sum_of_squares = 0.0;
+        self.frames = 0;
+
+        self.open.take().map(|open| Transition::End {
+            sequence: self.sequence,
+            start: open.start,
+            timestamp: open.last_active,
+        })
+    }
+}
+
+struct State {
+    detector: Detector,
+    /// Transitions which should be sent after the current buffer.
+    pending: Vec<Transition>,
+}
+
+const DEFAULT_OPEN_THRESHOLD: f64 = -40.0;
+const DEFAULT_CLOSE_THRESHOLD: f64 = -45.0;
+const DEFAULT_HANG_TIME: u32 = 500;
+const DEFAULT_WINDOW: u32 = 20;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Settings {
+    /// The level (in dBFS) a window must reach to open the squelch.
+    open_threshold: f64,
+    /// The level (in dBFS) a window must stay above to keep the squelch open.
+    close_threshold: f64,
+    /// How long (in milliseconds) to wait before closing the squelch.
+    hang_time: u32,
+    /// The length of each analysis window (in milliseconds).
+    window: u32,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            open_threshold: DEFAULT_OPEN_THRESHOLD,
+            close_threshold: DEFAULT_CLOSE_THRESHOLD,
+            hang_time: DEFAULT_HANG_TIME,
+            window: DEFAULT_WINDOW,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 5] = [
+    Property("open-threshold", |name| {
+        ParamSpec::double(
+            name,
+            "Open Threshold",
+            "The level (in dBFS) needed to start a transmission",
+            -200.0,
+            0.0,
+            DEFAULT_OPEN_THRESHOLD,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("close-threshold", |name| {
+        ParamSpec::double(
+            name,
+            "Close Threshold",
+            "The level (in dBFS) a transmission must stay above to continue",
+            -200.0,
+            0.0,
+            DEFAULT_CLOSE_THRESHOLD,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("hang-time", |name| {
+        ParamSpec::uint(
+            name,
+            "Hang Time",
+            "How long (in ms) to wait after the signal drops before ending a \
+             transmission",
+            0,
+            std::u32::MAX,
+            DEFAULT_HANG_TIME,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("window", |name| {
+        ParamSpec::uint(
+            name,
+            "Window",
+            "The length (in ms) of each window used when measuring the level",
+            1,
+            1000,
+            DEFAULT_WINDOW,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("open", |name| {
+        ParamSpec::boolean(
+            name,
+            "Open",
+            "Are we currently in the middle of a transmission?",
+            false,
+            ParamFlags::READABLE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: u64 = 1_000_000;
+
+    fn detector() -> Detector {
+        let settings = Settings {
+            hang_time: 100,
+            ..Settings::default()
+        };
+        // 20 frames per window keeps the numbers easy to follow
+        Detector::new(settings, 1000, 1)
+    }
+
+    /// `(amplitude, milliseconds)` pairs, at 1 kHz.
+    fn audio(parts: &[(f32, usize)]) -> Vec<f32> {
+        parts
+            .iter()
+            .flat_map(|&(amplitude, ms)| vec![amplitude; ms])
+            .collect()
+    }
+
+    #[test]
+    fn open_then_close_after_the_hang_time() {
+        let mut detector = detector();
+        let mut transitions = Vec::new();
+
+        detector.process(
+            &audio(&[(0.5, 100), (0.0, 300)]),
+            Some(0),
+            &mut transitions,
+        );
+
+        assert_eq!(transitions.len(), 2);
+        match transitions[0] {
+            Transition::Start {
+                sequence,
+                timestamp,
+                level,
+            } => {
+                assert_eq!(sequence, 1);
+                assert_eq!(timestamp, 0);
+                assert!((level - -6.0206).abs() < 1e-3);
+            },
+            other => panic!("Expected a start, got {:?}", other),
+        }
+        // the end is where the audio stopped, not where the hang expired
+        assert_eq!(
+            transitions[1],
+            Transition::End {
+                sequence: 1,
+                start: 0,
+                timestamp: 100 * MS,
+            }
+        );
+        assert!(!detector.is_open());
+    }
+
+    #[test]
+    fn short_pauses_dont_split_a_transmission() {
+        let mut detector = detector();
+        let mut transitions = Vec::new();
+
+        detector.process(
+            &audio(&[(0.5, 100), (0.0, 60), (0.5, 100), (0.0, 300)]),
+            Some(0),
+            &mut transitions,
+        );
+
+        assert_eq!(transitions.len(), 2);
+        assert!(transitions[0].is_start());
+        assert_eq!(
+            transitions[1],
+            Transition::End {
+                sequence: 1,
+                start: 0,
+                timestamp: 260 * MS,
+            }
+        );
+    }
+
+    #[test]
+    fn quiet_audio_never_opens_the_squelch() {
+        let mut detector = detector();
+        let mut transitions = Vec::new();
+
+        // -46 dBFS is below both thresholds
+        detector.process(&audio(&[(0.005, 500)]), Some(0), &mut transitions);
+
+        assert!(transitions.is_empty());
+        assert!(!detector.is_open());
+    }
+
+    #[test]
+    fn the_close_threshold_keeps_the_squelch_open() {
+        let mut detector = detector();
+        let mut transitions = Vec::new();
+
+        // -43 dBFS is between the close and open thresholds
+        detector.process(
+            &audio(&[(0.5, 100), (0.007, 500)]),
+            Some(0),
+            &mut transitions,
+        );
+
+        assert_eq!(transitions.len(), 1);
+        assert!(detector.is_open());
+    }
+
+    #[test]
+    fn each_transmission_gets_the_next_sequence_number() {
+        let mut detector = detector();
+        let mut transitions = Vec::new();
+        let transmission = audio(&[(0.5, 100), (0.0, 200)]);
+
+        detector.process(&transmission, Some(0), &mut transitions);
+        // no timestamp, so it follows on from the previous buffer
+        detector.process(&transmission, None, &mut transitions);
+
+        assert_eq!(
+            transitions,
+            vec![
+                Transition::Start {
+                    sequence: 1,
+                    timestamp: 0,
+                    level: transitions[0].level(),
+                },
+                Transition::End {
+                    sequence: 1,
+                    start: 0,
+                    timestamp: 100 * MS,
+                },
+                Transition::Start {
+                    sequence: 2,
+                    timestamp: 300 * MS,
+                    level: transitions[2].level(),
+                },
+                Transition::End {
+                    sequence: 2,
+                    start: 300 * MS,
+                    timestamp: 400 * MS,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn finishing_closes_an_open_transmission() {
+        let mut detector = detector();
+        let mut transitions = Vec::new();
+
+        detector.process(&audio(&[(0.5, 100)]), Some(0), &mut transitions);
+
+        assert_eq!(
+            detector.finish(),
+            Some(Transition::End {
+                sequence: 1,
+                start: 0,
+                timestamp: 100 * MS,
+            })
+        );
+        assert_eq!(detector.finish(), None);
+    }
+
+    impl Transition {
+        fn level(&self) -> f64 {
+            match *self {
+                Transition::Start { level, .. } => level,
+                Transition::End { .. } => panic!("Ends don't have a level"),
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use std::convert::TryFrom;
+
+const NANOSECONDS_PER_SECOND: u128 = 1_000_000_000;
+
+/// How long (in nanoseconds) `samples` samples at `rate` Hz last.
+///
+/// Timestamps are worked out from a running sample count, and multiplying
+/// that by a billion in a `u64` overflows after about 2 hours of IQ samples
+/// at 2.4 Msps (or 13 days of 16 kHz audio), so the maths is done in `u128`
+/// instead.
+pub(crate) fn samples_to_nanoseconds(samples: u64, rate: u64) -> u64 {
+    let nanoseconds =
+        u128::from(samples) * NANOSECONDS_PER_SECOND / u128::from(rate);
+
+    u64::try_from(nanoseconds).unwrap_or(u64::MAX)
+}
+
+/// How many samples at `rate` Hz fit in `nanoseconds`.
+pub(crate) fn nanoseconds_to_samples(nanoseconds: u64, rate: u64) -> u64 {
+    let samples =
+        u128::from(nanoseconds) * u128::from(rate) / NANOSECONDS_PER_SECOND;
+
+    u64::try_from(samples).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IQ_RATE: u64 = 2_400_000;
+    const AUDIO_RATE: u64 = 16_000;
+
+    #[test]
+    fn short_durations() {
+        assert_eq!(samples_to_nanoseconds(0, AUDIO_RATE), 0);
+        assert_eq!(samples_to_nanoseconds(16_000, AUDIO_RATE), 1_000_000_000);
+        assert_eq!(samples_to_nanoseconds(1, AUDIO_RATE), 62_500);
+        assert_eq!(samples_to_nanoseconds(1, IQ_RATE), 416);
+
+        assert_eq!(nanoseconds_to_samples(1_000_000_000, AUDIO_RATE), 16_000);
+        assert_eq!(nanoseconds_to_samples(62_499, AUDIO_RATE), 0);
+    }
+
+    #[test]
+    fn iq_sample_counts_past_a_u64_billion() {
+        // just past where `samples * 1_000_000_000` used to overflow
+        let samples = u64::MAX / 1_000_000_000 + 1;
+        let expected = samples / IQ_RATE * 1_000_000_000
+            + samples % IQ_RATE * 1_000_000_000 / IQ_RATE;
+        assert_eq!(samples_to_nanoseconds(samples, IQ_RATE), expected);
+
+        // a month of IQ samples
+        let month = 30 * 24 * 60 * 60;
+        assert_eq!(
+            samples_to_nanoseconds(IQ_RATE * month, IQ_RATE),
+            month * 1_000_000_000
+        );
+    }
+
+    #[test]
+    fn a_year_of_audio() {
+        let year = 365 * 24 * 60 * 60;
+        let nanoseconds = year * 1_000_000_000;
+
+        assert_eq!(
+            samples_to_nanoseconds(AUDIO_RATE * year, AUDIO_RATE),
+            nanoseconds
+        );
+        assert_eq!(
+            nanoseconds_to_samples(nanoseconds, AUDIO_RATE),
+            AUDIO_RATE * year
+        );
+    }
+
+    #[test]
+    fn huge_values_dont_overflow() {
+        assert_eq!(samples_to_nanoseconds(u64::MAX, 1), u64::MAX);
+        assert_eq!(
+            nanoseconds_to_samples(u64::MAX, IQ_RATE),
+            u64::MAX / 1_000_000_000 * IQ_RATE
+                + u64::MAX % 1_000_000_000 * IQ_RATE / 1_000_000_000
+        );
+    }
+}
@@ -0,0 +1,563 @@
+use byte_slice_cast::AsSliceOf;
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, Caps, ClockTime, CoreError, DebugCategory, DebugColorFlags,
+    Element, Event, EventView, FlowError, FlowSuccess, IntRange, LibraryError,
+    Message, Pad, PadDirection, PadPresence, PadTemplate, Plugin, Rank,
+    Structure,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use speech_to_text::{FakeTranscriber, Transcriber, WhisperCpp};
+use std::sync::Mutex;
+use transmission::messages::{
+    TRANSCRIPTION, TRANSMISSION_END, TRANSMISSION_START,
+};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rstranscribe",
+        Rank::None,
+        Transcribe::get_type(),
+    )
+}
+
+/// Converts each transmission (as delimited by the events from `rssquelch`)
+/// into text.
+///
+/// Audio is collected until the transmission ends, then handed to the
+/// speech-to-text engine and the result is pushed out as a `text/x-raw`
+/// buffer with the same timestamp and duration as the transmission. A
+/// `transcription` element message is also posted on the bus.
+///
+/// Transcription happens on the streaming thread, so there should normally be
+/// a `queue` in front of this element.
+pub struct Transcribe {
+    cat: DebugCategory,
+    sinkpad: Pad,
+    srcpad: Pad,
+    state: Mutex<State>,
+    settings: Mutex<Settings>,
+}
+
+impl Transcribe {
+    fn sink_chain(
+        &self,
+        _pad: &Pad,
+        element: &Element,
+        buffer: Buffer,
+    ) -> Result<FlowSuccess, FlowError> {
+        let mut state = self.state.lock().unwrap();
+
+        // audio between transmissions is thrown away
+        let current = match state.current {
+            Some(ref mut current) => current,
+            None => return Ok(FlowSuccess::Ok),
+        };
+
+        let map = buffer.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer readable"]
+            );
+            FlowError::Error
+        })?;
+        let samples = map.as_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        current.samples.extend_from_slice(samples);
+
+        Ok(FlowSuccess::Ok)
+    }
+
+    fn sink_event(&self, _pad: &Pad, element: &Element, event: Event) -> bool {
+        match event.view() {
+            EventView::Caps(c) => {
+                let info = match AudioInfo::from_caps(c.get_caps()) {
+                    Some(info) => info,
+                    None => return false,
+                };
+                gst_debug!(
+                    self.cat,
+                    obj: element,
+                    "Configured for caps {}",
+                    c.get_caps()
+                );
+                self.state.lock().unwrap().rate = info.rate();
+
+                // we don't output audio, so tell downstream what we *do*
+                // output
+                let caps =
+                    Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+                return self.srcpad.push_event(Event::new_caps(&caps).build());
+            },
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    if s.get_name() == TRANSMISSION_START {
+                        let mut state = self.state.lock().unwrap();
+                        state.current = Some(Recording {
+                            sequence: s.get_some("sequence").unwrap_or(0),
+                            timestamp: s.get_some("timestamp").unwrap_or(0),
+                            samples: Vec::new(),
+                        });
+                    } else if s.get_name() == TRANSMISSION_END {
+                        let end = s.get_some::<u64>("timestamp").ok();
+                        if self.finish_transmission(element, end).is_err() {
+                            return false;
+                        }
+                    }
+                }
+            },
+            EventView::Eos(..) => {
+                if self.finish_transmission(element, None).is_err() {
+                    return false;
+                }
+            },
+            EventView::FlushStop(..) => {
+                self.state.lock().unwrap().current = None;
+            },
+            _ => {},
+        }
+
+        self.srcpad.push_event(event)
+    }
+
+    fn src_event(&self, _pad: &Pad, _element: &Element, event: Event) -> bool {
+        self.sinkpad.push_event(event)
+    }
+
+    /// Transcribe the current transmission and send the text downstream.
+    fn finish_transmission(
+        &self,
+        element: &Element,
+        end: Option<u64>,
+    ) -> Result<(), FlowError> {
+        let (recording, rate) = {
+            let mut state = self.state.lock().unwrap();
+            match state.current.take() {
+                Some(recording) => (recording, state.rate),
+                None => return Ok(()),
+            }
+        };
+
+        if rate == 0 {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no caps yet"]
+            );
+            return Err(FlowError::NotNegotiated);
+        }
+
+        let duration = match end {
+            Some(end) => end.saturating_sub(recording.timestamp),
+            None => {
+                recording.samples.len() as u64 * 1_000_000_000 / u64::from(rate)
+            },
+        };
+
+        // transcription can take a while, so borrow the engine instead of
+        // holding the lock while it runs
+        let (mut transcriber, generation) = {
+            let mut state = self.state.lock().unwrap();
+            let transcriber = match state.transcriber.take() {
+                Some(transcriber) => transcriber,
+                None => {
+                    let settings = self.settings.lock().unwrap().clone();
+                    gst_info!(
+                        self.cat,
+                        obj: element,
+                        "Starting the \"{}\" engine",
+                        settings.engine
+                    );
+                    settings.transcriber().map_err(|msg| {
+                        gst_element_error!(
+                            element,
+                            LibraryError::Settings,
+                            ["{}", msg]
+                        );
+                        FlowError::Error
+                    })?
+                },
+            };
+            (transcriber, state.generation)
+        };
+
+        let transcript = transcriber.transcribe(&recording.samples, rate);
+
+        {
+            // hand the engine back, unless the settings changed in the
+            // meantime
+            let mut state = self.state.lock().unwrap();
+            if state.generation == generation {
+                state.transcriber = Some(transcriber);
+            }
+        }
+
+        let transcript = match transcript {
+            Ok(t) => t,
+            Err(e) => {
+                // one bad transmission shouldn't bring the whole pipeline
+                // down
+                gst_element_warning!(
+                    element,
+                    LibraryError::Failed,
+                    [
+                        "Unable to transcribe transmission {}: {}",
+                        recording.sequence,
+                        e
+                    ]
+                );
+
+                // let anyone waiting for the transcript know it isn't coming
+                let s = Structure::new(
+                    TRANSCRIPTION,
+                    &[
+                        ("sequence", &recording.sequence),
+                        ("timestamp", &recording.timestamp),
+                        ("duration", &duration),
+                        ("text", &""),
+                        ("error", &e.to_string()),
+                    ],
+                );
+                let msg = Message::new_element(s).src(Some(element)).build();
+                let _ = element.post_message(&msg);
+
+                return Ok(());
+            },
+        };
+
+        let text = transcript.text();
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Transmission {} said \"{}\"",
+            recording.sequence,
+            text
+        );
+
+        let s = Structure::new(
+            TRANSCRIPTION,
+            &[
+                ("sequence", &recording.sequence),
+                ("timestamp", &recording.timestamp),
+                ("duration", &duration),
+                ("text", &text),
+                (
+                    "transcript",
+                    &serde_json::to_string(&transcript).unwrap_or_default(),
+                ),
+            ],
+        );
+        let msg = Message::new_element(s).src(Some(element)).build();
+        let _ = element.post_message(&msg);
+
+        let mut buffer = Buffer::from_mut_slice(text.into_bytes());
+        {
+            let buffer = buffer.get_mut().unwrap();
+            buffer.set_pts(ClockTime::from_nseconds(recording.timestamp));
+            buffer.set_duration(ClockTime::from_nseconds(duration));
+        }
+
+        self.srcpad.push(buffer).map(|_| ())
+    }
+}
+
+impl ObjectSubclass for Transcribe {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = Element;
+
+    const NAME: &'static str = "RsTranscribe";
+
+    glib_object_subclass!();
+
+    fn new_with_class(klass: &ClassStruct<Self>) -> Self {
+        let templ = klass.get_pad_template("sink").unwrap();
+        let sinkpad = Pad::new_from_template(&templ, Some("sink"));
+        let templ = klass.get_pad_template("src").unwrap();
+        let srcpad = Pad::new_from_template(&templ, Some("src"));
+
+        sinkpad.set_chain_function(|pad, parent, buffer| {
+            Transcribe::catch_panic_pad_function(
+                parent,
+                || Err(FlowError::Error),
+                |transcribe, element| {
+                    transcribe.sink_chain(pad, element, buffer)
+                },
+            )
+        });
+        sinkpad.set_event_function(|pad, parent, event| {
+            Transcribe::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcribe, element| {
+                    transcribe.sink_event(pad, element, event)
+                },
+            )
+        });
+        srcpad.set_event_function(|pad, parent, event| {
+            Transcribe::catch_panic_pad_function(
+                parent,
+                || false,
+                |transcribe, element| transcribe.src_event(pad, element, event),
+            )
+        });
+
+        Self {
+            cat: DebugCategory::new(
+                "rstranscribe",
+                DebugColorFlags::empty(),
+                Some("Rust speech-to-text"),
+            ),
+            sinkpad,
+            srcpad,
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "Transcribe",
+            "Filter/Converter/Audio/Text",
+            "Converts radio transmissions to text",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple("text/x-raw", &[("format", &"utf8")]);
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+    }
+}
+
+impl ObjectImpl for Transcribe {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+
+        let element = obj.downcast_ref::<Element>().unwrap();
+        element.add_pad(&self.sinkpad).unwrap();
+        element.add_pad(&self.srcpad).unwrap();
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<Element>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("engine", ..) => {
+                let engine = value
+                    .get()
+                    .unwrap()
+                    .unwrap_or_else(|| DEFAULT_ENGINE.to_string());
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing engine from \"{}\" to \"{}\"",
+                    settings.engine,
+                    engine
+                );
+                settings.engine = engine;
+            },
+            Property("model", ..) => {
+                let model = value.get().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing model from {:?} to {:?}",
+                    settings.model,
+                    model
+                );
+                settings.model = model;
+            },
+            Property("executable", ..) => {
+                let executable = value.get().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing executable from {:?} to {:?}",
+                    settings.executable,
+                    executable
+                );
+                settings.executable = executable;
+            },
+            Property("language", ..) => {
+                let language = value.get().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing language from {:?} to {:?}",
+                    settings.language,
+                    language
+                );
+                settings.language = language;
+            },
+            _ => unimplemented!(),
+        }
+
+        // the engine gets recreated with the new settings next time it's
+        // needed
+        drop(settings);
+        let mut state = self.state.lock().unwrap();
+        state.transcriber = None;
+        state.generation = state.generation.wrapping_add(1);
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("engine", ..) => Ok(settings.engine.to_value()),
+            Property("model", ..) => Ok(settings.model.to_value()),
+            Property("executable", ..) => Ok(settings.executable.to_value()),
+            Property("language", ..) => Ok(settings.language.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for Transcribe {}
+
+/// The transmission currently being collected.
+struct Recording {
+    sequence: u32,
+    timestamp: u64,
+    samples: Vec<f32>,
+}
+
+#[derive(Default)]
+struct State {
+    rate: u32,
+    current: Option<Recording>,
+    transcriber: Option<Box<dyn Transcriber + Send>>,
+    /// Incremented whenever the settings change, so an engine which was busy
+    /// at the time isn't reused.
+    generation: u32,
+}
+
+const DEFAULT_ENGINE: &str = "whisper";
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    /// Which speech-to-text engine to use, `whisper` or `fake`.
+    engine: String,
+    /// The model file to load.
+    model: Option<String>,
+    /// The engine's executable, if it isn't on `$PATH`.
+    executable: Option<String>,
+    language: Option<String>,
+}
+
+impl Settings {
+    fn transcriber(&self) -> Result<Box<dyn Transcriber + Send>, String> {
+        match self.engine.as_str() {
+            "whisper" => {
+                let model = self.model.as_ref().ok_or_else(|| {
+                    String::from("The whisper engine requires a model")
+                })?;
+                let mut whisper = WhisperCpp::new(model);
+                if let Some(ref executable) = self.executable {
+                    whisper = whisper.with_executable(executable);
+                }
+                if let Some(ref language) = self.language {
+                    whisper = whisper.with_language(language.as_str());
+                }
+                Ok(Box::new(whisper))
+            },
+            "fake" => Ok(Box::new(FakeTranscriber::default())),
+            other => {
+                Err(format!("Unknown speech-to-text engine, \"{}\"", other))
+            },
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            engine: DEFAULT_ENGINE.to_string(),
+            model: None,
+            executable: None,
+            language: None,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 4] = [
+    Property("engine", |name| {
+        ParamSpec::string(
+            name,
+            "Engine",
+            "The speech-to-text engine to use (whisper or fake)",
+            Some(DEFAULT_ENGINE),
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("model", |name| {
+        ParamSpec::string(
+            name,
+            "Model",
+            "The speech-to-text model to load",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("executable", |name| {
+        ParamSpec::string(
+            name,
+            "Executable",
+            "The speech-to-text engine's executable",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("language", |name| {
+        ParamSpec::string(
+            name,
+            "Language",
+            "The spoken language (e.g. \"en\")",
+            None,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
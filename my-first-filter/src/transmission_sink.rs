@@ -0,0 +1,762 @@
+use crate::{
+    decibels::to_decibels,
+    timestamp::{nanoseconds_to_samples, samples_to_nanoseconds},
+};
+use byte_slice_cast::AsSliceOf;
+use chrono::{DateTime, Utc};
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, Caps, ClockTime, CoreError, DebugCategory, DebugColorFlags,
+    Element, ErrorMessage, Event, EventView, FlowError, FlowSuccess, IntRange,
+    Message, PadDirection, PadPresence, PadTemplate, Plugin, Rank,
+    ResourceError, Structure, StructureRef,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{prelude::*, subclass::prelude::*, BaseSink};
+use hound::{SampleFormat, WavReader, WavSpec, WavWriter};
+use std::{
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::Duration,
+};
+use transmission::{
+    messages::{TRANSMISSION_END, TRANSMISSION_START, TRANSMISSION_WRITTEN},
+    Transmission, MIN_LEVEL,
+};
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rstransmissionsink",
+        Rank::None,
+        TransmissionSink::get_type(),
+    )
+}
+
+/// A sink which writes each transmission (as delimited by the events from
+/// `rssquelch`) to its own WAV file.
+///
+/// The file name is generated from the `location` template, where the
+/// following placeholders are available:
+///
+/// - `{channel}` - the `channel` property
+/// - `{start}` - the wall-clock time the transmission started, in UTC
+/// - `{sequence}` - the transmission's sequence number
+pub struct TransmissionSink {
+    cat: DebugCategory,
+    state: Mutex<State>,
+    settings: Mutex<Settings>,
+}
+
+impl TransmissionSink {
+    fn start_transmission(
+        &self,
+        element: &BaseSink,
+        s: &StructureRef,
+    ) -> Result<(), ErrorMessage> {
+        let settings = self.settings.lock().unwrap().clone();
+        let mut state = self.state.lock().unwrap();
+
+        if state.current.is_some() {
+            gst_warning!(
+                self.cat,
+                obj: element,
+                "Received a new transmission before the last one ended"
+            );
+            self.finish_transmission(element, &mut state, None);
+        }
+
+        let info = state.info.clone().ok_or_else(|| {
+            gst_error_msg!(CoreError::Negotiation, ["Have no caps yet"])
+        })?;
+        let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+        let timestamp = s.get_some::<u64>("timestamp").unwrap_or(0);
+        let running_time = element
+            .get_segment()
+            .downcast_ref::<ClockTime>()
+            .and_then(|segment| {
+                segment
+                    .to_running_time(ClockTime::from_nseconds(timestamp))
+                    .nseconds()
+            })
+            .unwrap_or(timestamp);
+        let wall_clock = wall_clock_at(
+            running_time,
+            Utc::now(),
+            element.get_clock().and_then(|c| c.get_time().nseconds()),
+            element.get_base_time().nseconds(),
+        );
+        let path = PathBuf::from(expand_template(
+            &settings.location,
+            &settings.channel,
+            wall_clock,
+            sequence,
+        ));
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| {
+                gst_error_msg!(
+                    ResourceError::OpenWrite,
+                    [
+                        "Unable to create the \"{}\" directory: {}",
+                        parent.display(),
+                        e
+                    ]
+                )
+            })?;
+        }
+
+        let spec = WavSpec {
+            channels: info.channels() as u16,
+            sample_rate: info.rate(),
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let writer = WavWriter::create(&path, spec).map_err(|e| {
+            gst_error_msg!(
+                ResourceError::OpenWrite,
+                ["Unable to create \"{}\": {}", path.display(), e]
+            )
+        })?;
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Writing transmission {} to \"{}\"",
+            sequence,
+            path.display()
+        );
+
+        state.current = Some(Recording {
+            writer,
+            path,
+            sequence,
+            start: timestamp,
+            running_time,
+            wall_clock,
+            frames: 0,
+            sum_of_squares: 0.0,
+            peak: 0.0,
+        });
+
+        Ok(())
+    }
+
+    /// Close off the current recording. When the squelch said where the
+    /// transmission really ended (`end`, as a buffer timestamp), the hang
+    /// time after it is trimmed off.
+    fn finish_transmission(
+        &self,
+        element: &BaseSink,
+        state: &mut State,
+        end: Option<u64>,
+    ) {
+        let Recording {
+            writer,
+            path,
+            sequence,
+            start,
+            running_time,
+            wall_clock,
+            mut frames,
+            mut sum_of_squares,
+            mut peak,
+        } = match state.current.take() {
+            Some(t) => t,
+            None => return,
+        };
+
+        if let Err(e) = writer.finalize() {
+            gst_element_error!(
+                element,
+                ResourceError::Write,
+                ["Unable to finish writing \"{}\": {}", path.display(), e]
+            );
+            return;
+        }
+
+        let (rate, channels) = state
+            .info
+            .as_ref()
+            .map(|i| (u64::from(i.rate()), u64::from(i.channels())))
+            .unwrap_or((1, 1));
+
+        if let Some(end) = end {
+            let keep = nanoseconds_to_samples(end.saturating_sub(start), rate);
+
+            if keep < frames {
+                let kept = match truncate_wav(&path, keep * channels) {
+                    Ok(kept) => kept,
+                    Err(e) => {
+                        gst_element_error!(
+                            element,
+                            ResourceError::Write,
+                            ["Unable to trim \"{}\": {}", path.display(), e]
+                        );
+                        return;
+                    },
+                };
+
+                frames = keep;
+                sum_of_squares = 0.0;
+                peak = 0.0;
+                for &sample in &kept {
+                    let sample = f32::from(sample) / f32::from(i16::MAX);
+                    sum_of_squares += f64::from(sample) * f64::from(sample);
+                    peak = peak.max(sample.abs());
+                }
+            }
+        }
+
+        let duration = samples_to_nanoseconds(frames, rate);
+        let channel = self.settings.lock().unwrap().channel.clone();
+
+        let mut transmission = Transmission::new(
+            channel.clone(),
+            sequence,
+            wall_clock,
+            Duration::from_nanos(running_time),
+            Duration::from_nanos(duration),
+            path.clone(),
+        );
+        let samples = frames * channels;
+        if samples > 0 {
+            transmission.rms =
+                to_decibels((sum_of_squares / samples as f64).sqrt())
+                    .max(MIN_LEVEL);
+            transmission.peak = to_decibels(f64::from(peak)).max(MIN_LEVEL);
+        }
+        let json = match serde_json::to_string(&transmission) {
+            Ok(json) => json,
+            Err(e) => {
+                gst_element_error!(
+                    element,
+                    CoreError::Failed,
+                    ["Unable to serialize the transmission: {}", e]
+                );
+                return;
+            },
+        };
+
+        gst_info!(
+            self.cat,
+            obj: element,
+            "Finished writing \"{}\"",
+            path.display()
+        );
+
+        let s = Structure::new(
+            TRANSMISSION_WRITTEN,
+            &[
+                ("channel", &channel),
+                ("sequence", &sequence),
+                ("transmission", &json),
+            ],
+        );
+        let msg = Message::new_element(s).src(Some(element)).build();
+        let _ = element.post_message(&msg);
+    }
+}
+
+impl ObjectSubclass for TransmissionSink {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseSink;
+
+    const NAME: &'static str = "RsTransmissionSink";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rstransmissionsink",
+                DebugColorFlags::empty(),
+                Some("Rust per-transmission WAV writer"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "Transmission Sink",
+            "Sink/File/Audio",
+            "Writes each transmission to its own WAV file",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &IntRange::<i32>::new(1, 16)),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+    }
+}
+
+impl ObjectImpl for TransmissionSink {
+    glib_object_impl!();
+
+    fn constructed(&self, obj: &glib::Object) {
+        self.parent_constructed(obj);
+
+        // we're writing to disk, there's no point waiting for the clock
+        let element = obj.downcast_ref::<BaseSink>().unwrap();
+        element.set_sync(false);
+    }
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseSink>().unwrap();
+        let mut settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("location", ..) => {
+                let location = value
+                    .get()
+                    .unwrap()
+                    .unwrap_or_else(|| DEFAULT_LOCATION.to_string());
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing location from \"{}\" to \"{}\"",
+                    settings.location,
+                    location
+                );
+                settings.location = location;
+            },
+            Property("channel", ..) => {
+                let channel = value
+                    .get()
+                    .unwrap()
+                    .unwrap_or_else(|| DEFAULT_CHANNEL.to_string());
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing channel from \"{}\" to \"{}\"",
+                    settings.channel,
+                    channel
+                );
+                settings.channel = channel;
+            },
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+        let settings = self.settings.lock().unwrap();
+
+        match *prop {
+            Property("location", ..) => Ok(settings.location.to_value()),
+            Property("channel", ..) => Ok(settings.channel.to_value()),
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for TransmissionSink {}
+
+impl BaseSinkImpl for TransmissionSink {
+    fn set_caps(&self, element: &BaseSink, caps: &Caps) -> bool {
+        let info = match AudioInfo::from_caps(caps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(self.cat, obj: element, "Configured for caps {}", caps);
+
+        let mut state = self.state.lock().unwrap();
+
+        if state.current.is_some() {
+            // the WAV header is already written, so the easiest way to
+            // handle a format change is to start a new file
+            gst_warning!(
+                self.cat,
+                obj: element,
+                "Caps changed in the middle of a transmission"
+            );
+            self.finish_transmission(element, &mut state, None);
+        }
+
+        state.info = Some(info);
+
+        true
+    }
+
+    fn stop(&self, element: &BaseSink) -> Result<(), ErrorMessage> {
+        let mut state = self.state.lock().unwrap();
+        self.finish_transmission(element, &mut state, None);
+        *state = State::default();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn event(&self, element: &BaseSink, event: Event) -> bool {
+        match event.view() {
+            EventView::CustomDownstream(ev) => {
+                if let Some(s) = ev.get_structure() {
+                    if s.get_name() == TRANSMISSION_START {
+                        if let Err(e) = self.start_transmission(element, s) {
+                            element.post_error_message(&e);
+                            return false;
+                        }
+                    } else if s.get_name() == TRANSMISSION_END {
+                        let end = s.get_some::<u64>("timestamp").ok();
+                        let mut state = self.state.lock().unwrap();
+                        self.finish_transmission(element, &mut state, end);
+                    }
+                }
+            },
+            EventView::Eos(..) => {
+                let mut state = self.state.lock().unwrap();
+                self.finish_transmission(element, &mut state, None);
+            },
+            _ => {},
+        }
+
+        self.parent_event(element, event)
+    }
+
+    fn render(
+        &self,
+        element: &BaseSink,
+        buffer: &Buffer,
+    ) -> Result<FlowSuccess, FlowError> {
+        let mut state = self.state.lock().unwrap();
+        let (rate, channels) = match state.info {
+            Some(ref info) => {
+                (u64::from(info.rate()), u64::from(info.channels()))
+            },
+            None => {
+                gst_element_error!(
+                    element,
+                    CoreError::Negotiation,
+                    ["Have no caps yet"]
+                );
+                return Err(FlowError::NotNegotiated);
+            },
+        };
+
+        // audio between transmissions is thrown away
+        let current = match state.current {
+            Some(ref mut current) => current,
+            None => return Ok(FlowSuccess::Ok),
+        };
+
+        let map = buffer.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map buffer readable"]
+            );
+            FlowError::Error
+        })?;
+        let samples = map.as_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        // the squelch sends the start before the buffer it falls in, so the
+        // first buffer may begin a little early
+        let skip = match buffer.get_pts().nseconds() {
+            Some(pts) if current.frames == 0 => {
+                let early = current.start.saturating_sub(pts);
+                nanoseconds_to_samples(early, rate) * channels
+            },
+            _ => 0,
+        };
+        let samples = &samples[std::cmp::min(skip as usize, samples.len())..];
+
+        for &sample in samples {
+            current.sum_of_squares += f64::from(sample) * f64::from(sample);
+            current.peak = current.peak.max(sample.abs());
+            current.writer.write_sample(to_i16(sample)).map_err(|e| {
+                gst_element_error!(
+                    element,
+                    ResourceError::Write,
+                    [
+                        "Unable to write to \"{}\": {}",
+                        current.path.display(),
+                        e
+                    ]
+                );
+                FlowError::Error
+            })?;
+        }
+        current.frames += samples.len() as u64 / channels;
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    let clamped = sample.max(-1.0).min(1.0);
+    (clamped * f32::from(std::i16::MAX)) as i16
+}
+
+/// Work out the wall-clock time of a running time, using the pipeline's
+/// clock rather than when we happened to hear about it (which lags behind
+/// by however much is queued up, or races ahead when reading a file).
+///
+/// `clock_time` and `base_time` are the element's current clock and base
+/// times, and `now` is the wall-clock time they were read at.
+fn wall_clock_at(
+    running_time: u64,
+    now: DateTime<Utc>,
+    clock_time: Option<u64>,
+    base_time: Option<u64>,
+) -> DateTime<Utc> {
+    match (clock_time, base_time) {
+        (Some(clock_time), Some(base_time)) => {
+            // how long ago (in clock time) the running time was
+            let ago = clock_time.saturating_sub(base_time) as i64
+                - running_time as i64;
+            now - chrono::Duration::nanoseconds(ago)
+        },
+        // we aren't playing yet, so there's nothing better to go on
+        _ => now,
+    }
+}
+
+/// Throw away everything after the first `samples` samples of a WAV file,
+/// returning the samples which were kept.
+fn truncate_wav(path: &Path, samples: u64) -> Result<Vec<i16>, hound::Error> {
+    let mut reader = WavReader::open(path)?;
+    let spec = reader.spec();
+    let kept = reader
+        .samples::<i16>()
+        .take(samples as usize)
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(reader);
+
+    let mut writer = WavWriter::create(path, spec)?;
+    for &sample in &kept {
+        writer.write_sample(sample)?;
+    }
+    writer.finalize()?;
+
+    Ok(kept)
+}
+
+/// Generate a file name from the `location` template.
+fn expand_template(
+    template: &str,
+    channel: &str,
+    start: DateTime<Utc>,
+    sequence: u32,
+) -> String {
+    template
+        .replace("{channel}", channel)
+        .replace("{start}", &start.format("%Y%m%dT%H%M%S%.3fZ").to_string())
+        .replace("{sequence}", &format!("{:05}", sequence))
+}
+
+/// The transmission currently being written to disk.
+struct Recording {
+    writer: WavWriter<BufWriter<File>>,
+    path: PathBuf,
+    sequence: u32,
+    /// Where the transmission started, as a buffer timestamp. Anything
+    /// before this is left out of the file.
+    start: u64,
+    /// When the `transmission-start` event arrived, in running time.
+    running_time: u64,
+    wall_clock: DateTime<Utc>,
+    /// The number of frames written so far.
+    frames: u64,
+    sum_of_squares: f64,
+    /// The largest absolute sample value seen so far.
+    peak: f32,
+}
+
+#[derive(Default)]
+struct State {
+    info: Option<AudioInfo>,
+    current: Option<Recording>,
+}
+
+const DEFAULT_LOCATION: &str = "{channel}-{start}-{sequence}.wav";
+const DEFAULT_CHANNEL: &str = "default";
+
+#[derive(Debug, Clone)]
+pub struct Settings {
+    location: String,
+    channel: String,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            location: DEFAULT_LOCATION.to_string(),
+            channel: DEFAULT_CHANNEL.to_string(),
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 2] = [
+    Property("location", |name| {
+        ParamSpec::string(
+            name,
+            "Location",
+            "Template for the file each transmission is written to",
+            Some(DEFAULT_LOCATION),
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("channel", |name| {
+        ParamSpec::string(
+            name,
+            "Channel",
+            "The name of the radio channel being recorded",
+            Some(DEFAULT_CHANNEL),
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECOND: u64 = 1_000_000_000;
+
+    fn noon() -> DateTime<Utc> {
+        "2020-01-01T12:00:00Z".parse().unwrap()
+    }
+
+    #[test]
+    fn expand_the_default_template() {
+        let start = noon() + chrono::Duration::milliseconds(1234);
+
+        let got = expand_template(DEFAULT_LOCATION, "fire", start, 42);
+
+        assert_eq!(got, "fire-20200101T120001.234Z-00042.wav");
+    }
+
+    #[test]
+    fn placeholders_can_be_repeated_or_left_out() {
+        let got = expand_template(
+            "/recordings/{channel}/{channel}-{sequence}.wav",
+            "fire",
+            noon(),
+            123_456,
+        );
+        assert_eq!(got, "/recordings/fire/fire-123456.wav");
+
+        let got = expand_template("static.wav", "fire", noon(), 1);
+        assert_eq!(got, "static.wav");
+    }
+
+    #[test]
+    fn truncate_a_wav_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transmission.wav");
+        let spec = WavSpec {
+            channels: 2,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for sample in 0..1000 {
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        let kept = truncate_wav(&path, 600).unwrap();
+
+        let expected: Vec<i16> = (0..600).collect();
+        assert_eq!(kept, expected);
+        let mut reader = WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec(), spec);
+        assert_eq!(reader.duration(), 300);
+        let on_disk: Vec<i16> =
+            reader.samples().collect::<Result<_, _>>().unwrap();
+        assert_eq!(on_disk, expected);
+    }
+
+    #[test]
+    fn truncating_past_the_end_keeps_everything() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("transmission.wav");
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let mut writer = WavWriter::create(&path, spec).unwrap();
+        for &sample in &[1, -2, 3] {
+            writer.write_sample(sample as i16).unwrap();
+        }
+        writer.finalize().unwrap();
+
+        assert_eq!(truncate_wav(&path, 10).unwrap(), vec![1, -2, 3]);
+        assert_eq!(WavReader::open(&path).unwrap().duration(), 3);
+    }
+
+    #[test]
+    fn missing_wav_files_are_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+
+        assert!(truncate_wav(&dir.path().join("missing.wav"), 10).is_err());
+    }
+
+    #[test]
+    fn wall_clock_of_an_earlier_running_time() {
+        // we've been playing for 60s, and the transmission started 15s in
+        let clock_time = 1000 * SECOND + 60 * SECOND;
+        let base_time = 1000 * SECOND;
+
+        let got = wall_clock_at(
+            15 * SECOND,
+            noon(),
+            Some(clock_time),
+            Some(base_time),
+        );
+
+        assert_eq!(got, noon() - chrono::Duration::seconds(45));
+    }
+
+    #[test]
+    fn wall_clock_of_a_running_time_ahead_of_the_clock() {
+        // reading from a file, so the audio is well ahead of the clock
+        let got =
+            wall_clock_at(90 * SECOND, noon(), Some(30 * SECOND), Some(0));
+
+        assert_eq!(got, noon() + chrono::Duration::seconds(60));
+    }
+
+    #[test]
+    fn wall_clock_falls_back_to_now_before_playing() {
+        assert_eq!(wall_clock_at(15 * SECOND, noon(), None, Some(0)), noon());
+        assert_eq!(wall_clock_at(15 * SECOND, noon(), Some(0), None), noon());
+    }
+}
@@ -0,0 +1,622 @@
+use crate::{biquad::Biquad, decibels::from_decibels};
+use byte_slice_cast::{AsMutSliceOf, AsSliceOf};
+use glib::{
+    subclass::{object::ObjectImpl, prelude::*, simple::ClassStruct, Property},
+    BoolError, Cast, Object, ParamFlags, ParamSpec, ToValue, Value,
+};
+use gstreamer::{
+    subclass::{prelude::*, ElementInstanceStruct},
+    Buffer, BufferRef, Caps, CoreError, DebugCategory, DebugColorFlags,
+    Element, ErrorMessage, FlowError, FlowSuccess, IntRange, PadDirection,
+    PadPresence, PadTemplate, Plugin, Rank,
+};
+use gstreamer_audio::{AudioInfo, AUDIO_FORMAT_F32};
+use gstreamer_base::{
+    subclass::{prelude::*, BaseTransformMode},
+    BaseTransform,
+};
+use std::sync::Mutex;
+
+pub fn register(plugin: &Plugin) -> Result<(), BoolError> {
+    Element::register(
+        Some(plugin),
+        "rsvoicefilter",
+        Rank::None,
+        VoiceFilter::get_type(),
+    )
+}
+
+/// The window the AGC measures the level over, in milliseconds.
+const LEVEL_WINDOW: f64 = 20.0;
+/// How quickly the AGC turns the gain down when the level goes up, in
+/// milliseconds.
+const ATTACK: f64 = 10.0;
+/// How quickly the AGC turns the gain back up when the level drops, in
+/// milliseconds.
+const RELEASE: f64 = 500.0;
+
+/// Cleans up radio speech for listening and transcription.
+///
+/// Audio goes through a 300–3400 Hz band-pass filter (the range voice
+/// radios actually carry), optional de-emphasis, then automatic gain control
+/// which brings every transmission to roughly the same level.
+pub struct VoiceFilter {
+    cat: DebugCategory,
+    state: Mutex<Option<State>>,
+    settings: Mutex<Settings>,
+}
+
+impl ObjectSubclass for VoiceFilter {
+    type Class = ClassStruct<Self>;
+    type Instance = ElementInstanceStruct<Self>;
+    type ParentType = BaseTransform;
+
+    const NAME: &'static str = "RsVoiceFilter";
+
+    glib_object_subclass!();
+
+    fn new() -> Self {
+        Self {
+            cat: DebugCategory::new(
+                "rsvoicefilter",
+                DebugColorFlags::empty(),
+                Some("Rust radio voice filter"),
+            ),
+            settings: Mutex::new(Settings::default()),
+            state: Mutex::new(None),
+        }
+    }
+
+    fn class_init(klass: &mut ClassStruct<Self>) {
+        klass.set_metadata(
+            "Radio Voice Filter",
+            "Filter/Effect/Audio",
+            "Band-pass filters, de-emphasises and levels radio speech",
+            env!("CARGO_PKG_AUTHORS"),
+        );
+        klass.install_properties(&PROPERTIES);
+
+        let caps = Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &AUDIO_FORMAT_F32.to_string()),
+                ("rate", &IntRange::<i32>::new(1, std::i32::MAX)),
+                ("channels", &1),
+                ("layout", &"interleaved"),
+            ],
+        );
+        let src_pad_template = PadTemplate::new(
+            "src",
+            PadDirection::Src,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(src_pad_template);
+
+        let sink_pad_template = PadTemplate::new(
+            "sink",
+            PadDirection::Sink,
+            PadPresence::Always,
+            &caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+
+        klass.configure(BaseTransformMode::NeverInPlace, false, false);
+    }
+}
+
+impl ObjectImpl for VoiceFilter {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<BaseTransform>().unwrap();
+
+        match *prop {
+            Property("de-emphasis", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let de_emphasis = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing de-emphasis from {} to {}",
+                    settings.de_emphasis,
+                    de_emphasis
+                );
+                settings.de_emphasis = de_emphasis;
+            },
+            Property("agc", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let agc = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing agc from {} to {}",
+                    settings.agc,
+                    agc
+                );
+                settings.agc = agc;
+            },
+            Property("target-level", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let target_level = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing target-level from {} to {}",
+                    settings.target_level,
+                    target_level
+                );
+                settings.target_level = target_level;
+            },
+            Property("max-gain", ..) => {
+                let mut settings = self.settings.lock().unwrap();
+                let max_gain = value.get().unwrap().unwrap();
+                gst_info!(
+                    self.cat,
+                    obj: element,
+                    "Changing max-gain from {} to {}",
+                    settings.max_gain,
+                    max_gain
+                );
+                settings.max_gain = max_gain;
+            },
+            _ => unimplemented!(),
+        }
+    }
+
+    fn get_property(&self, _obj: &Object, id: usize) -> Result<Value, ()> {
+        let prop = &PROPERTIES[id];
+
+        match *prop {
+            Property("de-emphasis", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.de_emphasis.to_value())
+            },
+            Property("agc", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.agc.to_value())
+            },
+            Property("target-level", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.target_level.to_value())
+            },
+            Property("max-gain", ..) => {
+                let settings = self.settings.lock().unwrap();
+                Ok(settings.max_gain.to_value())
+            },
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for VoiceFilter {}
+
+impl BaseTransformImpl for VoiceFilter {
+    fn set_caps(
+        &self,
+        element: &BaseTransform,
+        incaps: &Caps,
+        outcaps: &Caps,
+    ) -> bool {
+        let info = match AudioInfo::from_caps(incaps) {
+            None => return false,
+            Some(info) => info,
+        };
+
+        gst_debug!(
+            self.cat,
+            obj: element,
+            "Configured for caps {} to {}",
+            incaps,
+            outcaps
+        );
+
+        let settings = *self.settings.lock().unwrap();
+        *self.state.lock().unwrap() = Some(State {
+            processor: Processor::new(settings, info.rate()),
+        });
+
+        true
+    }
+
+    fn stop(&self, element: &BaseTransform) -> Result<(), ErrorMessage> {
+        // Drop state
+        let _ = self.state.lock().unwrap().take();
+
+        gst_info!(self.cat, obj: element, "Stopped");
+
+        Ok(())
+    }
+
+    fn get_unit_size(
+        &self,
+        _element: &BaseTransform,
+        caps: &Caps,
+    ) -> Option<usize> {
+        AudioInfo::from_caps(caps).map(|info| info.bpf() as usize)
+    }
+
+    fn transform(
+        &self,
+        element: &BaseTransform,
+        inbuf: &Buffer,
+        outbuf: &mut BufferRef,
+    ) -> Result<FlowSuccess, FlowError> {
+        // lock the state and make sure we've started
+        let mut state_guard = self.state.lock().unwrap();
+        let state = state_guard.as_mut().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Negotiation,
+                ["Have no state yet"]
+            );
+            FlowError::NotNegotiated
+        })?;
+        let settings = *self.settings.lock().unwrap();
+        state.processor.update(settings);
+
+        // make sure the incoming buffer is readable
+        let in_map = inbuf.map_readable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map input buffer readable"]
+            );
+            FlowError::Error
+        })?;
+        let in_samples = in_map.as_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Input buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        // make sure the outgoing buffer is writeable
+        let mut out_map = outbuf.map_writable().ok_or_else(|| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Failed to map output buffer writable"]
+            );
+            FlowError::Error
+        })?;
+        let out_samples = out_map.as_mut_slice_of::<f32>().map_err(|_| {
+            gst_element_error!(
+                element,
+                CoreError::Failed,
+                ["Output buffer isn't a whole number of samples"]
+            );
+            FlowError::Error
+        })?;
+
+        // sanity checks
+        assert_eq!(in_samples.len(), out_samples.len());
+
+        for (input, output) in in_samples.iter().zip(out_samples.iter_mut()) {
+            *output = state.processor.push(f64::from(*input)) as f32;
+        }
+
+        Ok(FlowSuccess::Ok)
+    }
+}
+
+struct State {
+    processor: Processor,
+}
+
+/// The part of the element which actually does the filtering.
+#[derive(Debug)]
+struct Processor {
+    settings: Settings,
+    rate: u32,
+    /// A 4th-order high-pass and low-pass, making up the band-pass.
+    band_pass: Vec<Biquad>,
+    /// The output of the 1st-order de-emphasis filter.
+    de_emphasised: f64,
+    de_emphasis_alpha: f64,
+    /// The smoothed signal power the AGC is working from.
+    power: f64,
+    power_alpha: f64,
+    gain: f64,
+    attack: f64,
+    release: f64,
+}
+
+impl Processor {
+    fn new(settings: Settings, rate: u32) -> Processor {
+        let rate_f = f64::from(rate);
+        let nyquist = rate_f / 2.0;
+
+        // a filter can't do anything for frequencies the stream can't carry
+        let mut band_pass = Vec::new();
+        if LOW_CUTOFF < nyquist * 0.9 {
+            band_pass.push(Biquad::high_pass(LOW_CUTOFF, rate_f));
+            band_pass.push(Biquad::high_pass(LOW_CUTOFF, rate_f));
+        }
+        if HIGH_CUTOFF < nyquist * 0.9 {
+            band_pass.push(Biquad::low_pass(HIGH_CUTOFF, rate_f));
+            band_pass.push(Biquad::low_pass(HIGH_CUTOFF, rate_f));
+        }
+
+        let de_emphasis_alpha = if settings.de_emphasis > 0 {
+            let tau = f64::from(settings.de_emphasis) / 1_000_000.0;
+            1.0 - (-1.0 / (tau * rate_f)).exp()
+        } else {
+            1.0
+        };
+
+        Processor {
+            settings,
+            rate,
+            band_pass,
+            de_emphasised: 0.0,
+            de_emphasis_alpha,
+            power: 0.0,
+            power_alpha: smoothing(LEVEL_WINDOW, rate_f),
+            gain: 1.0,
+            attack: smoothing(ATTACK, rate_f),
+            release: smoothing(RELEASE, rate_f),
+        }
+    }
+
+    /// Pick up any settings which were changed since last time.
+    fn update(&mut self, settings: Settings) {
+        if settings != self.settings {
+            *self = Processor::new(settings, self.rate);
+        }
+    }
+
+    fn push(&mut self, sample: f64) -> f64 {
+        let filtered = self.band_pass.iter_mut().fold(sample, |s, f| f.push(s));
+
+        self.de_emphasised +=
+            self.de_emphasis_alpha * (filtered - self.de_emphasised);
+        let sample = self.de_emphasised;
+
+        if !self.settings.agc {
+            return sample;
+        }
+
+        self.power += self.power_alpha * (sample * sample - self.power);
+
+        // back off quickly so loud transmissions don't clip, but come back
+        // up slowly so the gain doesn't pump between words
+        let wanted = self.wanted_gain();
+        let coefficient = if wanted < self.gain {
+            self.attack
+        } else {
+            self.release
+        };
+        self.gain += coefficient * (wanted - self.gain);
+
+        (sample * self.gain).max(-1.0).min(1.0)
+    }
+
+    /// The gain needed to bring the current level up (or down) to the
+    /// target.
+    fn wanted_gain(&self) -> f64 {
+        let target = from_decibels(self.settings.target_level);
+        let max_gain = from_decibels(self.settings.max_gain);
+        let rms = self.power.sqrt();
+
+        if rms * max_gain <= target {
+            max_gain
+        } else {
+            target / rms
+        }
+    }
+}
+
+/// The coefficient for a one-pole smoothing filter with the given time
+/// constant (in milliseconds).
+fn smoothing(time_constant: f64, rate: f64) -> f64 {
+    1.0 - (-1000.0 / (time_constant * rate)).exp()
+}
+
+/// The band voice radios carry, in Hz.
+const LOW_CUTOFF: f64 = 300.0;
+const HIGH_CUTOFF: f64 = 3400.0;
+
+const DEFAULT_DE_EMPHASIS: u32 = 0;
+const DEFAULT_AGC: bool = true;
+const DEFAULT_TARGET_LEVEL: f64 = -20.0;
+const DEFAULT_MAX_GAIN: f64 = 30.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /// The de-emphasis time constant in microseconds, or 0 to turn it off.
+    de_emphasis: u32,
+    agc: bool,
+    /// The RMS level (in dBFS) the AGC aims for.
+    target_level: f64,
+    /// The most the AGC will amplify by (in dB).
+    max_gain: f64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            de_emphasis: DEFAULT_DE_EMPHASIS,
+            agc: DEFAULT_AGC,
+            target_level: DEFAULT_TARGET_LEVEL,
+            max_gain: DEFAULT_MAX_GAIN,
+        }
+    }
+}
+
+pub static PROPERTIES: [Property; 4] = [
+    Property("de-emphasis", |name| {
+        ParamSpec::uint(
+            name,
+            "De-emphasis",
+            "The de-emphasis time constant (in µs), or 0 to disable it. Use \
+             750 for narrowband FM taken straight from a discriminator",
+            0,
+            10_000,
+            DEFAULT_DE_EMPHASIS,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("agc", |name| {
+        ParamSpec::boolean(
+            name,
+            "AGC",
+            "Automatically adjust the gain to reach the target level",
+            DEFAULT_AGC,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("target-level", |name| {
+        ParamSpec::double(
+            name,
+            "Target Level",
+            "The RMS level (in dBFS) the AGC aims for",
+            -60.0,
+            0.0,
+            DEFAULT_TARGET_LEVEL,
+            ParamFlags::READWRITE,
+        )
+    }),
+    Property("max-gain", |name| {
+        ParamSpec::double(
+            name,
+            "Maximum Gain",
+            "The most (in dB) the AGC will amplify quiet audio by",
+            0.0,
+            60.0,
+            DEFAULT_MAX_GAIN,
+            ParamFlags::READWRITE,
+        )
+    }),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const RATE: u32 = 16_000;
+
+    fn sine(frequency: f64, level: f64, seconds: f64) -> Vec<f64> {
+        let amplitude = from_decibels(level) * std::f64::consts::SQRT_2;
+
+        (0..(seconds * f64::from(RATE)) as usize)
+            .map(|n| {
+                let t = n as f64 / f64::from(RATE);
+                amplitude * (2.0 * PI * frequency * t).sin()
+            })
+            .collect()
+    }
+
+    /// The RMS level (in dBFS) of the last half of some audio, once the
+    /// filters have settled.
+    fn level(samples: &[f64]) -> f64 {
+        let tail = &samples[samples.len() / 2..];
+        let power = tail.iter().map(|x| x * x).sum::<f64>() / tail.len() as f64;
+
+        10.0 * power.log10()
+    }
+
+    fn process(settings: Settings, input: &[f64]) -> Vec<f64> {
+        let mut processor = Processor::new(settings, RATE);
+        input.iter().map(|&x| processor.push(x)).collect()
+    }
+
+    fn without_agc() -> Settings {
+        Settings {
+            agc: false,
+            ..Settings::default()
+        }
+    }
+
+    #[test]
+    fn voice_passes_through_the_band_pass() {
+        let output = process(without_agc(), &sine(1_000.0, -20.0, 0.5));
+
+        assert!((level(&output) + 20.0).abs() < 1.0, "{}", level(&output));
+    }
+
+    #[test]
+    fn hum_and_hiss_are_removed() {
+        for &frequency in &[100.0, 6_000.0] {
+            let output = process(without_agc(), &sine(frequency, -20.0, 0.5));
+
+            assert!(level(&output) < -35.0, "{} Hz", frequency);
+        }
+    }
+
+    #[test]
+    fn de_emphasis_rolls_off_the_treble() {
+        let settings = Settings {
+            de_emphasis: 750,
+            ..without_agc()
+        };
+
+        let low = level(&process(settings, &sine(400.0, -20.0, 0.5)));
+        let high = level(&process(settings, &sine(3_000.0, -20.0, 0.5)));
+
+        // 750 µs is -6 dB/octave from about 212 Hz
+        assert!(low - high > 15.0, "{} vs {}", low, high);
+    }
+
+    #[test]
+    fn agc_brings_everything_to_the_target_level() {
+        for &input in &[-45.0, -20.0, -3.0] {
+            let output =
+                process(Settings::default(), &sine(1_000.0, input, 3.0));
+
+            let target = DEFAULT_TARGET_LEVEL;
+            assert!(
+                (level(&output) - target).abs() < 1.0,
+                "{} dB came out at {} dB",
+                input,
+                level(&output)
+            );
+        }
+    }
+
+    #[test]
+    fn agc_never_goes_past_the_max_gain() {
+        let settings = Settings {
+            max_gain: 20.0,
+            ..Settings::default()
+        };
+        let mut processor = Processor::new(settings, RATE);
+        let max_gain = from_decibels(settings.max_gain);
+
+        let mut output = Vec::new();
+        for x in sine(1_000.0, -70.0, 3.0) {
+            output.push(processor.push(x));
+            assert!(processor.gain <= max_gain * (1.0 + 1e-9));
+        }
+
+        assert!((level(&output) + 50.0).abs() < 1.0, "{}", level(&output));
+    }
+
+    #[test]
+    fn update_only_rebuilds_when_the_settings_change() {
+        let mut processor = Processor::new(Settings::default(), RATE);
+        for x in sine(1_000.0, -40.0, 0.5) {
+            processor.push(x);
+        }
+        let gain = processor.gain;
+        assert!(gain > 1.0);
+
+        processor.update(Settings::default());
+        assert_eq!(processor.gain, gain);
+
+        let settings = Settings {
+            target_level: -10.0,
+            ..Settings::default()
+        };
+        processor.update(settings);
+        assert_eq!(processor.gain, 1.0);
+        assert_eq!(processor.power, 0.0);
+        assert_eq!(processor.settings, settings);
+    }
+}
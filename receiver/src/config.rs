@@ -0,0 +1,681 @@
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    fmt::{self, Display, Formatter},
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// The receiver's configuration, normally loaded from a TOML file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Config {
+    /// The `my-first-filter` plugin to load. If not provided the plugin must
+    /// be somewhere on `$GST_PLUGIN_PATH`.
+    pub plugin: Option<PathBuf>,
+    /// The directory recordings and transmission metadata are saved to.
+    pub recordings: PathBuf,
+    /// The radio channels to listen to. Each one gets its own branch in the
+    /// pipeline.
+    pub channels: Vec<Channel>,
+    /// The squelch settings used by any channel which doesn't provide its
+    /// own.
+    #[serde(default)]
+    pub squelch: Squelch,
+    /// How each channel's signal level is monitored.
+    #[serde(default)]
+    pub levels: Levels,
+    /// When to raise alerts about channels which look broken.
+    #[serde(default)]
+    pub alerts: Alerts,
+    /// Speech-to-text settings. Transmissions are only recorded when this is
+    /// missing.
+    pub transcriber: Option<Transcriber>,
+    #[serde(default)]
+    pub restart: Restart,
+    /// Run the HTTP server in the same process, so clients get notified
+    /// about transmissions as they happen.
+    pub server: Option<Server>,
+}
+
+impl Config {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, crate::Error> {
+        let src = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&src)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<(), crate::Error> {
+        if self.channels.is_empty() {
+            return Err(crate::Error::InvalidConfig(
+                "At least one channel is required".to_string(),
+            ));
+        }
+
+        let mut names = HashSet::new();
+        for channel in &self.channels {
+            if channel.name.is_empty() {
+                return Err(crate::Error::InvalidConfig(
+                    "Every channel needs a name".to_string(),
+                ));
+            }
+            if is_reserved(&channel.name) {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "\"{}\" is used by the receiver itself, pick a different channel name",
+                    channel.name
+                )));
+            }
+            if !names.insert(&channel.name) {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "The \"{}\" channel is defined more than once",
+                    channel.name
+                )));
+            }
+        }
+
+        for channel in &self.channels {
+            if channel.sdr.is_some()
+                && (channel.uri.is_some()
+                    || channel.rtp.is_some()
+                    || channel.audio_channel.is_some())
+            {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "The \"{}\" channel can't use \"sdr\" with \"uri\", \"rtp\" or \"audio-channel\"",
+                    channel.name
+                )));
+            }
+            if channel.rtp.is_some() && channel.uri.is_some() {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "The \"{}\" channel can't use both \"uri\" and \"rtp\"",
+                    channel.name
+                )));
+            }
+        }
+
+        let mut ports = HashSet::new();
+        for (source, _) in self.sources() {
+            let rtp = match source {
+                Source::Rtp(rtp) => rtp,
+                _ => continue,
+            };
+            if !ports.insert(rtp.port) {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "{} is used more than once with different settings",
+                    source
+                )));
+            }
+            if rtp.clock_rate().is_none() {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "{} must set \"clock-rate\" for {} streams",
+                    source, rtp.encoding
+                )));
+            }
+        }
+
+        for channel in &self.channels {
+            let sdr = match channel.sdr {
+                Some(ref sdr) => sdr,
+                None => continue,
+            };
+            if sdr.frequency.is_some() && sdr.center_frequency.is_none() {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "The \"{}\" channel must set \"center-frequency\" to use \"frequency\"",
+                    channel.name
+                )));
+            }
+        }
+
+        for (location, channels) in self.captures() {
+            if channels.len() < 2 {
+                continue;
+            }
+            let source = location
+                .map(|l| format!("\"{}\"", l.display()))
+                .unwrap_or_else(|| "stdin".to_string());
+            let (_, first) = channels[0];
+
+            let mut frequencies = HashSet::new();
+            for (_, sdr) in &channels {
+                let frequency = match sdr.frequency {
+                    Some(frequency) => frequency,
+                    None => {
+                        return Err(crate::Error::InvalidConfig(format!(
+                            "Every channel using the IQ samples from {} must set \"frequency\"",
+                            source
+                        )))
+                    },
+                };
+                if !frequencies.insert(frequency) {
+                    return Err(crate::Error::InvalidConfig(format!(
+                        "{} Hz in the IQ samples from {} is used more than once",
+                        frequency, source
+                    )));
+                }
+
+                // they all go through the same channelizer
+                let shared = Sdr {
+                    location: sdr.location.clone(),
+                    frequency: sdr.frequency,
+                    offset: sdr.offset,
+                    ..first.clone()
+                };
+                if shared != **sdr {
+                    return Err(crate::Error::InvalidConfig(format!(
+                        "Every channel using the IQ samples from {} must have the same \"sdr\" settings, apart from \"frequency\"",
+                        source
+                    )));
+                }
+            }
+        }
+
+        for (source, channels) in self.sources() {
+            if channels.len() > 1
+                && channels.iter().any(|c| c.audio_channel.is_none())
+            {
+                return Err(crate::Error::InvalidConfig(format!(
+                    "Every channel using {} must set \"audio-channel\"",
+                    source
+                )));
+            }
+
+            let mut audio_channels = HashSet::new();
+            for channel in &channels {
+                if !audio_channels.insert(channel.audio_channel) {
+                    return Err(crate::Error::InvalidConfig(format!(
+                        "Audio channel {} of {} is used more than once",
+                        channel.audio_channel.unwrap_or(0),
+                        source
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Group the channels by where their audio comes from, in the order they
+    /// were defined. Channels received from an SDR are left out.
+    pub fn sources(&self) -> Vec<(Source<'_>, Vec<&Channel>)> {
+        let mut sources: Vec<(Source<'_>, Vec<&Channel>)> = Vec::new();
+
+        for channel in self.channels.iter().filter(|c| c.sdr.is_none()) {
+            let source = match (&channel.uri, &channel.rtp) {
+                (_, Some(rtp)) => Source::Rtp(rtp),
+                (Some(uri), None) => Source::Uri(uri),
+                (None, None) => Source::Default,
+            };
+
+            match sources.iter_mut().find(|(s, _)| *s == source) {
+                Some((_, channels)) => channels.push(channel),
+                None => sources.push((source, vec![channel])),
+            }
+        }
+
+        sources
+    }
+
+    /// Group the channels received from an SDR by the IQ samples they are
+    /// demodulated from (`None` meaning stdin), in the order they were
+    /// defined.
+    pub fn captures(&self) -> Vec<(Option<&Path>, Vec<(&Channel, &Sdr)>)> {
+        let mut captures: Vec<(Option<&Path>, Vec<(&Channel, &Sdr)>)> =
+            Vec::new();
+
+        for channel in &self.channels {
+            let sdr = match channel.sdr {
+                Some(ref sdr) => sdr,
+                None => continue,
+            };
+            let location = sdr.location();
+
+            match captures.iter_mut().find(|(l, _)| *l == location) {
+                Some((_, channels)) => channels.push((channel, sdr)),
+                None => captures.push((location, vec![(channel, sdr)])),
+            }
+        }
+
+        captures
+    }
+
+    /// The squelch settings to use for a particular channel.
+    pub fn squelch_for(&self, channel: &Channel) -> Squelch {
+        channel.squelch.unwrap_or(self.squelch)
+    }
+
+    /// Where recordings for a particular channel are saved.
+    pub fn recordings_for(&self, channel: &Channel) -> PathBuf {
+        match channel.recordings {
+            Some(ref dir) => dir.clone(),
+            None => self.recordings.join(&channel.name),
+        }
+    }
+}
+
+/// Could `name` clash with the elements the receiver adds to the pipeline?
+/// Each channel's bin is named after the channel, and elements in a pipeline
+/// need unique names, so channels can't be called `receiver` (the pipeline
+/// itself), or `source0`, `remote1`, `sdr2`, etc. (the sources and anything
+/// named after them).
+fn is_reserved(name: &str) -> bool {
+    if name == "receiver" {
+        return true;
+    }
+
+    ["source", "remote", "sdr"].iter().any(|prefix| {
+        name.starts_with(prefix)
+            && name[prefix.len()..].starts_with(|c: char| c.is_ascii_digit())
+    })
+}
+
+/// A single radio channel and where its audio comes from.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Channel {
+    /// The name of the radio channel. Every [`Transmission`] received on
+    /// this channel gets tagged with it.
+    ///
+    /// [`Transmission`]: transmission::Transmission
+    pub name: String,
+    /// Any URI `uridecodebin` understands. The default audio input is used
+    /// when this isn't provided.
+    pub uri: Option<String>,
+    /// Receive the audio as an RTP stream instead of using `uri`.
+    pub rtp: Option<Rtp>,
+    /// Only use one channel (starting from 0) of an interleaved source.
+    ///
+    /// This lets several radio channels share a source, for example two
+    /// scanners plugged into the left and right side of one sound card.
+    pub audio_channel: Option<u32>,
+    /// Demodulate the channel from a software defined radio's IQ samples
+    /// instead of using `uri`.
+    pub sdr: Option<Sdr>,
+    /// Override the top-level squelch settings.
+    pub squelch: Option<Squelch>,
+    /// Remove background noise with the given strength (0-100), using a
+    /// noise profile learned while the squelch is closed.
+    pub noise_reduction: Option<u32>,
+    /// Clean up the audio (band-pass, de-emphasis and AGC) before it is
+    /// recorded and transcribed.
+    pub filter: Option<VoiceFilter>,
+    /// Detect the CTCSS tone used by each transmission.
+    #[serde(default)]
+    pub ctcss: bool,
+    /// Decode the DCS code used by each transmission.
+    #[serde(default)]
+    pub dcs: bool,
+    /// Decode the DTMF or 5-tone (ZVEI1/CCIR) ANI sequence radios send to
+    /// identify themselves.
+    #[serde(default)]
+    pub ani: bool,
+    /// Decode the unit ID and emergency alarms from MDC-1200 and FleetSync
+    /// data bursts.
+    #[serde(default)]
+    pub mdc: bool,
+    /// Mute MDC-1200 and FleetSync bursts in the audio sent to the
+    /// transcriber, so they don't turn into garbage text.
+    #[serde(default)]
+    pub strip_mdc: bool,
+    /// The directory this channel's recordings are saved to. Defaults to
+    /// `<recordings>/<name>`.
+    pub recordings: Option<PathBuf>,
+}
+
+/// Where a channel's audio comes from.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Source<'a> {
+    /// The default audio input (e.g. a sound card).
+    Default,
+    Uri(&'a str),
+    Rtp(&'a Rtp),
+}
+
+impl<'a> Source<'a> {
+    /// Is this a stream received over the network (e.g. from an Icecast
+    /// server or a remote receiver's RTP stream)? These get reconnected
+    /// on their own when they drop out, instead of restarting the whole
+    /// pipeline.
+    pub fn is_remote(&self) -> bool {
+        match self {
+            Source::Default => false,
+            Source::Uri(uri) => {
+                uri.starts_with("http://") || uri.starts_with("https://")
+            },
+            Source::Rtp(_) => true,
+        }
+    }
+}
+
+impl<'a> Display for Source<'a> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "the default audio input"),
+            Source::Uri(uri) => write!(f, "{}", uri),
+            Source::Rtp(rtp) => write!(f, "RTP port {}", rtp.port),
+        }
+    }
+}
+
+/// Where to listen for an RTP stream and what it contains, used to set up
+/// the `udpsrc` and `rtpjitterbuffer` elements.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Rtp {
+    /// The address to listen on. Multicast groups are joined automatically.
+    pub address: String,
+    pub port: u16,
+    /// The payload's encoding name (e.g. `OPUS`, `PCMU`, `PCMA` or `L16`).
+    pub encoding: String,
+    /// The RTP clock rate (in Hz). Only needed for encodings which don't
+    /// have a standard one, like `L16`.
+    pub clock_rate: Option<u32>,
+    /// How many audio channels the stream contains.
+    pub channels: u32,
+    /// How long (in milliseconds) to wait for late or out-of-order packets.
+    pub latency: u32,
+}
+
+impl Rtp {
+    /// The RTP clock rate, falling back to the encoding's standard one.
+    pub fn clock_rate(&self) -> Option<u32> {
+        if self.clock_rate.is_some() {
+            return self.clock_rate;
+        }
+
+        match self.encoding.to_uppercase().as_str() {
+            "OPUS" => Some(48_000),
+            "PCMU" | "PCMA" | "G722" => Some(8_000),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Rtp {
+    fn default() -> Rtp {
+        Rtp {
+            address: String::from("0.0.0.0"),
+            port: 5004,
+            encoding: String::from("OPUS"),
+            clock_rate: None,
+            channels: 1,
+            latency: 200,
+        }
+    }
+}
+
+/// Where to read IQ samples from and how to demodulate them, passed through to
+/// the `rsiqsrc` and `rsnbfmdemod` (or `rschannelizer`) elements.
+///
+/// Channels which read the same IQ samples share a single source and are
+/// demodulated together, so one SDR can cover a whole band.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Sdr {
+    /// A recording made with `rtl_sdr` (or similar). Samples are read from
+    /// stdin when this isn't provided.
+    pub location: Option<PathBuf>,
+    /// The sample format (`cu8`, `cs8`, `cs16` or `cf32`).
+    pub format: String,
+    /// The number of complex samples per second.
+    pub sample_rate: u32,
+    /// The frequency (in Hz) the SDR was tuned to.
+    pub center_frequency: Option<u32>,
+    /// The channel's frequency (in Hz).
+    pub frequency: Option<u32>,
+    /// How far (in Hz) the channel is from the centre frequency, used when
+    /// `frequency` isn't provided.
+    pub offset: f64,
+    /// How the audio is carried (`nbfm`, `am`, `usb` or `lsb`).
+    pub mode: String,
+    /// The width (in Hz) of the channel. Each mode has a sensible default.
+    pub bandwidth: Option<f64>,
+    /// The frequency deviation (in Hz) which gives full-scale NBFM audio.
+    pub deviation: f64,
+    /// The carrier level (in dBFS) needed to let audio through.
+    pub squelch: f64,
+}
+
+impl Sdr {
+    /// The file IQ samples are read from, or `None` for stdin.
+    pub fn location(&self) -> Option<&Path> {
+        self.location
+            .as_ref()
+            .map(|location| location.as_path())
+            .filter(|&location| location != Path::new("-"))
+    }
+
+    /// How far (in Hz) the channel is from the frequency the SDR was tuned
+    /// to.
+    pub fn offset(&self) -> f64 {
+        match (self.frequency, self.center_frequency) {
+            (Some(frequency), Some(center)) => {
+                f64::from(frequency) - f64::from(center)
+            },
+            _ => self.offset,
+        }
+    }
+}
+
+impl Default for Sdr {
+    fn default() -> Sdr {
+        Sdr {
+            location: None,
+            format: String::from("cu8"),
+            sample_rate: 2_048_000,
+            center_frequency: None,
+            frequency: None,
+            offset: 0.0,
+            mode: String::from("nbfm"),
+            bandwidth: None,
+            deviation: 2_500.0,
+            squelch: -200.0,
+        }
+    }
+}
+
+/// Settings passed through to the `rssquelch` element.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Squelch {
+    /// The level (in dBFS) needed to start a transmission.
+    pub open_threshold: f64,
+    /// The level (in dBFS) a transmission must stay above to continue.
+    pub close_threshold: f64,
+    /// How long (in milliseconds) to wait after the signal drops before
+    /// ending a transmission.
+    pub hang_time: u32,
+}
+
+impl Default for Squelch {
+    fn default() -> Squelch {
+        Squelch {
+            open_threshold: -40.0,
+            close_threshold: -45.0,
+            hang_time: 500,
+        }
+    }
+}
+
+/// Settings passed through to the `rslevelmeter` element.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Levels {
+    /// How often (in milliseconds) to measure the signal level.
+    pub interval: u32,
+    /// Samples at or above this level (in dBFS) count as clipped.
+    pub clip_level: f64,
+}
+
+impl Default for Levels {
+    fn default() -> Levels {
+        Levels {
+            interval: 1000,
+            clip_level: -0.1,
+        }
+    }
+}
+
+/// Settings for the watchdog which looks for stuck transmitters and dead
+/// channels.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Alerts {
+    /// The longest (in seconds) a transmission can go for before it's
+    /// treated as a stuck carrier, or 0 to never check.
+    pub max_transmission: u64,
+    /// The longest (in seconds) a channel can be silent before it's treated
+    /// as dead air, or 0 to never check.
+    pub max_silence: u64,
+    /// The level (in dBFS) a channel must stay below to count as silent.
+    pub silence_level: f64,
+    /// A program to run whenever an alert is raised or cleared. It is passed
+    /// the JSON-serialized event on stdin.
+    pub command: Option<PathBuf>,
+}
+
+impl Alerts {
+    pub fn max_transmission(&self) -> Option<Duration> {
+        match self.max_transmission {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+
+    pub fn max_silence(&self) -> Option<Duration> {
+        match self.max_silence {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
+impl Default for Alerts {
+    fn default() -> Alerts {
+        Alerts {
+            max_transmission: 300,
+            max_silence: 0,
+            silence_level: -70.0,
+            command: None,
+        }
+    }
+}
+
+/// Settings passed through to the `rsvoicefilter` element.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct VoiceFilter {
+    /// The de-emphasis time constant (in microseconds), or 0 to turn it off.
+    pub de_emphasis: u32,
+    /// Automatically adjust the gain so every transmission is about as loud.
+    pub agc: bool,
+    /// The RMS level (in dBFS) the AGC aims for.
+    pub target_level: f64,
+    /// The most (in dB) the AGC will amplify by.
+    pub max_gain: f64,
+}
+
+impl Default for VoiceFilter {
+    fn default() -> VoiceFilter {
+        VoiceFilter {
+            de_emphasis: 0,
+            agc: true,
+            target_level: -20.0,
+            max_gain: 30.0,
+        }
+    }
+}
+
+/// Settings passed through to the `rstranscribe` element.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Transcriber {
+    /// The speech-to-text engine (`whisper` or `fake`).
+    pub engine: String,
+    pub model: Option<PathBuf>,
+    pub executable: Option<PathBuf>,
+    pub language: Option<String>,
+}
+
+/// What to do when the pipeline stops.
+#[derive(Debug, Copy, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case", default)]
+pub struct Restart {
+    /// How long (in seconds) to wait before restarting the pipeline, or
+    /// reconnecting a stream received over the network.
+    pub delay: u64,
+    /// Exit instead of restarting when the source runs out of audio (e.g.
+    /// when reading from a file). Network streams are always reconnected.
+    pub exit_on_eos: bool,
+}
+
+impl Restart {
+    pub fn delay(&self) -> Duration {
+        Duration::from_secs(self.delay)
+    }
+}
+
+impl Default for Restart {
+    fn default() -> Restart {
+        Restart {
+            delay: 5,
+            exit_on_eos: false,
+        }
+    }
+}
+
+/// Settings for the embedded HTTP server.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Server {
+    /// The address to listen on (e.g. `127.0.0.1:8080`).
+    pub address: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(channels: &[&str]) -> Result<Config, crate::Error> {
+        let mut src = String::from("recordings = \"recordings\"\n");
+        for (i, name) in channels.iter().enumerate() {
+            // each channel gets its own source
+            src.push_str(&format!(
+                "[[channels]]\nname = {:?}\nuri = \"http://scanner/{}\"\n",
+                name, i
+            ));
+        }
+
+        let config: Config = toml::from_str(&src)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    #[test]
+    fn channel_names_must_be_unique() {
+        assert!(parse(&["fire", "police"]).is_ok());
+        assert!(parse(&["fire", "police", "fire"]).is_err());
+    }
+
+    #[test]
+    fn channel_names_cant_clash_with_the_pipeline() {
+        let reserved = &[
+            "",
+            "receiver",
+            "source0",
+            "source12-convert",
+            "remote3",
+            "sdr0",
+            "sdr1-demod",
+            "sdr1-channelizer",
+        ];
+        for name in reserved {
+            assert!(parse(&[name]).is_err(), "{:?}", name);
+        }
+
+        for name in &["sources", "remote-site", "sdr", "source", "fire-1"] {
+            assert!(parse(&[name]).is_ok(), "{:?}", name);
+        }
+    }
+}
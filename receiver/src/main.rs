@@ -0,0 +1,407 @@
+//! A daemon which listens to one or more radio channels, records each
+//! transmission and converts it to text.
+//!
+//! Usage: `transcribe-receiver [config.toml]`
+
+mod config;
+mod pipeline;
+mod reconnect;
+mod recorder;
+mod watchdog;
+
+use crate::{
+    config::Config, reconnect::Reconnects, recorder::Recorder,
+    watchdog::Watchdog,
+};
+use gstreamer::{
+    prelude::*, ClockTime, Event, MessageType, MessageView, Pipeline, Plugin,
+    State, StateChangeError,
+};
+use std::{
+    collections::HashMap,
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    io,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+use storage::Database;
+use transcribe_server::Server;
+
+fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info"),
+    )
+    .init();
+
+    let config_file = std::env::args_os()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("receiver.toml"));
+
+    if let Err(e) = run(config_file) {
+        log::error!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn run(config_file: PathBuf) -> Result<(), Error> {
+    let config = Config::from_file(&config_file)?;
+
+    gstreamer::init()?;
+    if let Some(ref plugin) = config.plugin {
+        Plugin::load_file(plugin)?;
+    }
+
+    std::fs::create_dir_all(&config.recordings)?;
+    let store = Database::open(config.recordings.join("transmissions.db"))?;
+    let server = Server::new(store.clone());
+    let mut recorders: HashMap<String, Recorder> = config
+        .channels
+        .iter()
+        .map(|channel| {
+            let recorder = Recorder::new(
+                store.clone(),
+                channel.name.clone(),
+                config.transcriber.is_some(),
+                server.events(),
+                server.status(),
+            );
+            (channel.name.clone(), recorder)
+        })
+        .collect();
+    let mut watchdogs: HashMap<String, Watchdog> = config
+        .channels
+        .iter()
+        .map(|channel| {
+            let watchdog = Watchdog::new(
+                channel.name.clone(),
+                config.alerts.clone(),
+                server.events(),
+                server.status(),
+            );
+            (channel.name.clone(), watchdog)
+        })
+        .collect();
+
+    if let Some(ref settings) = config.server {
+        let address = settings.address.clone();
+        log::info!("Serving transmissions on http://{}/", address);
+
+        thread::spawn(move || {
+            if let Err(e) = server.serve(&address) {
+                log::error!("Unable to start the server: {}", e);
+            }
+        });
+    }
+
+    let terminate = Arc::new(AtomicBool::new(false));
+    let t2 = Arc::clone(&terminate);
+    ctrlc::set_handler(move || t2.store(true, Ordering::SeqCst))?;
+
+    loop {
+        let pipeline = pipeline::build(&config, &terminate)?;
+        let outcome = run_pipeline(
+            &pipeline,
+            &mut recorders,
+            &mut watchdogs,
+            &terminate,
+            config.restart.delay(),
+        );
+        pipeline.set_state(State::Null)?;
+
+        match outcome {
+            Outcome::Terminated => return Ok(()),
+            Outcome::Eos if config.restart.exit_on_eos => {
+                log::info!("Reached the end of the stream");
+                return Ok(());
+            },
+            Outcome::Eos => log::warn!("The source stopped sending audio"),
+            Outcome::Failed(msg) => log::error!("{}", msg),
+        }
+
+        recorders.values_mut().for_each(Recorder::reset);
+        watchdogs.values_mut().for_each(Watchdog::reset);
+
+        log::info!(
+            "Restarting the pipeline in {}s",
+            config.restart.delay().as_secs()
+        );
+        if !sleep_unless_terminated(config.restart.delay(), &terminate) {
+            return Ok(());
+        }
+    }
+}
+
+/// Why [`run_pipeline()`] returned.
+#[derive(Debug, Clone, PartialEq)]
+enum Outcome {
+    /// We were asked to shut down.
+    Terminated,
+    /// The source ran out of audio.
+    Eos,
+    Failed(String),
+}
+
+/// How long to wait for the EOS when shutting down. A network stream which
+/// is waiting to be reconnected will never send one, but stopping the
+/// pipeline still writes out any in-progress transmissions.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn run_pipeline(
+    pipeline: &Pipeline,
+    recorders: &mut HashMap<String, Recorder>,
+    watchdogs: &mut HashMap<String, Watchdog>,
+    terminate: &AtomicBool,
+    reconnect_delay: Duration,
+) -> Outcome {
+    if let Err(e) = pipeline.set_state(State::Playing) {
+        return Outcome::Failed(format!("Unable to start the pipeline: {}", e));
+    }
+
+    let bus = pipeline.get_bus().unwrap();
+    let mut stopping: Option<Instant> = None;
+    // network streams which dropped out, and when to reconnect them
+    let mut reconnects = Reconnects::new(reconnect_delay);
+
+    loop {
+        match stopping {
+            None if terminate.load(Ordering::SeqCst) => {
+                // send an EOS so any in-progress transmissions get written out
+                log::info!("Shutting down");
+                pipeline.send_event(Event::new_eos().build());
+                stopping = Some(Instant::now());
+            },
+            Some(started) if started.elapsed() > SHUTDOWN_TIMEOUT => {
+                log::warn!("Gave up waiting for the pipeline to finish");
+                return Outcome::Terminated;
+            },
+            _ => {},
+        }
+
+        if stopping.is_none() {
+            reconnect_due(pipeline, &mut reconnects);
+        }
+
+        // alerts need checking even when the messages stop coming
+        let now = Instant::now();
+        watchdogs.values_mut().for_each(|w| w.check(now));
+
+        let msg = bus.timed_pop_filtered(
+            ClockTime::from_seconds(1),
+            &[
+                MessageType::Error,
+                MessageType::Eos,
+                MessageType::Application,
+                MessageType::Element,
+                MessageType::StateChanged,
+            ],
+        );
+
+        let msg = match msg {
+            Some(msg) => msg,
+            None => continue,
+        };
+
+        match msg.view() {
+            MessageView::Error(err) => {
+                let description = format!(
+                    "Error received from element {:?}: {} ({:?})",
+                    err.get_src().map(|s| s.get_path_string()),
+                    err.get_error(),
+                    err.get_debug()
+                );
+                let remote = err
+                    .get_src()
+                    .and_then(|src| pipeline::remote_source_of(&src));
+
+                // a network stream dropping out doesn't stop the others
+                if let Some(source) = remote {
+                    if reconnects.dropped_out(
+                        &source.get_name(),
+                        stopping.is_some(),
+                        Instant::now(),
+                    ) {
+                        log::warn!("{}", description);
+                        continue;
+                    }
+                }
+
+                return Outcome::Failed(description);
+            },
+            MessageView::Eos(..) if stopping.is_some() => {
+                return Outcome::Terminated
+            },
+            MessageView::Eos(..) => return Outcome::Eos,
+            MessageView::Element(element) => {
+                let channel = element
+                    .get_src()
+                    .and_then(|src| pipeline::channel_of(&src));
+
+                if let (Some(channel), Some(s)) =
+                    (channel, element.get_structure())
+                {
+                    if let Some(recorder) = recorders.get_mut(&channel) {
+                        recorder.handle_message(s);
+                    }
+                    if let Some(watchdog) = watchdogs.get_mut(&channel) {
+                        watchdog.handle_message(s);
+                    }
+                }
+            },
+            MessageView::Application(app) => {
+                let lost = app
+                    .get_structure()
+                    .map(|s| s.get_name() == pipeline::SOURCE_LOST)
+                    .unwrap_or(false);
+                let remote = app
+                    .get_src()
+                    .and_then(|src| pipeline::remote_source_of(&src));
+
+                if let (true, Some(source)) = (lost, remote) {
+                    log::warn!(
+                        "\"{}\" stopped sending audio",
+                        source.get_name()
+                    );
+                    reconnects.dropped_out(
+                        &source.get_name(),
+                        stopping.is_some(),
+                        Instant::now(),
+                    );
+                }
+            },
+            MessageView::StateChanged(change) => {
+                if change.get_src() != Some(pipeline.clone().upcast()) {
+                    // we only care about changes from the pipeline
+                    continue;
+                }
+
+                log::debug!(
+                    "Pipeline state changed from {:?} to {:?}",
+                    change.get_old(),
+                    change.get_current()
+                );
+            },
+            _ => {},
+        }
+    }
+}
+
+/// Reconnect any network streams which have waited long enough.
+fn reconnect_due(pipeline: &Pipeline, reconnects: &mut Reconnects) {
+    let now = Instant::now();
+
+    for name in reconnects.take_due(now) {
+        let source = match pipeline.get_by_name(&name) {
+            Some(source) => source,
+            None => continue,
+        };
+
+        log::info!("Reconnecting \"{}\"", name);
+        if let Err(e) = pipeline::reconnect(&source) {
+            log::warn!("Unable to reconnect \"{}\": {}", name, e);
+            reconnects.retry(&name, now);
+        }
+    }
+}
+
+/// Sleep for the specified duration, returning early (with `false`) if we're
+/// asked to shut down.
+fn sleep_unless_terminated(duration: Duration, terminate: &AtomicBool) -> bool {
+    let start = Instant::now();
+
+    while start.elapsed() < duration {
+        if terminate.load(Ordering::SeqCst) {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    !terminate.load(Ordering::SeqCst)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Config(toml::de::Error),
+    /// The config file parsed, but doesn't make sense.
+    InvalidConfig(String),
+    /// A GStreamer element couldn't be created, normally because the plugin
+    /// providing it isn't installed.
+    MissingElement(String),
+    Glib(glib::Error),
+    Gstreamer(glib::BoolError),
+    StateChange(StateChangeError),
+    Signal(ctrlc::Error),
+    Storage(storage::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Config(e) => write!(f, "Invalid config: {}", e),
+            Error::InvalidConfig(msg) => write!(f, "Invalid config: {}", msg),
+            Error::MissingElement(name) => {
+                write!(f, "Unable to create a \"{}\" element", name)
+            },
+            Error::Glib(e) => write!(f, "{}", e),
+            Error::Gstreamer(e) => write!(f, "{}", e),
+            Error::StateChange(e) => write!(f, "{}", e),
+            Error::Signal(e) => {
+                write!(f, "Unable to set the Ctrl-C handler: {}", e)
+            },
+            Error::Storage(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl StdError for Error {}
+
+impl From<io::Error> for Error {
+    fn from(other: io::Error) -> Error {
+        Error::Io(other)
+    }
+}
+
+impl From<toml::de::Error> for Error {
+    fn from(other: toml::de::Error) -> Error {
+        Error::Config(other)
+    }
+}
+
+impl From<glib::Error> for Error {
+    fn from(other: glib::Error) -> Error {
+        Error::Glib(other)
+    }
+}
+
+impl From<glib::BoolError> for Error {
+    fn from(other: glib::BoolError) -> Error {
+        Error::Gstreamer(other)
+    }
+}
+
+impl From<StateChangeError> for Error {
+    fn from(other: StateChangeError) -> Error {
+        Error::StateChange(other)
+    }
+}
+
+impl From<ctrlc::Error> for Error {
+    fn from(other: ctrlc::Error) -> Error {
+        Error::Signal(other)
+    }
+}
+
+impl From<storage::Error> for Error {
+    fn from(other: storage::Error) -> Error {
+        Error::Storage(other)
+    }
+}
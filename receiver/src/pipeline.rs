@@ -0,0 +1,610 @@
+use crate::{
+    config::{Channel, Config, Rtp, Sdr, Source},
+    reconnect, Error,
+};
+use gstreamer::{
+    prelude::*, Bin, Caps, ClockTime, Element, ElementFactory, EventView,
+    GhostPad, Message, Object, Pad, PadDirection, PadProbeData, PadProbeReturn,
+    PadProbeType, Pipeline, State, Structure,
+};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// The sample rate everything after the resampler runs at. This is what
+/// most speech-to-text engines expect.
+pub const SAMPLE_RATE: i32 = 16_000;
+
+/// The name of the application message posted when a network stream stops
+/// sending audio.
+pub const SOURCE_LOST: &str = "source-lost";
+
+/// Build the receiver's pipeline.
+///
+/// Each channel gets a [`Bin`] (named after the channel) which does all the
+/// processing, so channels run independently of each other. The CTCSS, DCS,
+/// ANI and MDC-1200/FleetSync decoders are only added for channels which ask
+/// for them, as are the `rsdenoise` and `rsvoicefilter` which clean up the
+/// audio and the `rsmdc` which strips data bursts out of the audio being
+/// transcribed. The `rslevelmeter` sees everything the channel receives, so
+/// it can tell when the channel goes quiet or is being overdriven.
+///
+/// ```text
+/// source → <name>
+///
+/// <name>: audioconvert → audioresample → rslevelmeter → rssquelch → [rsctcss →] [rsdcs →] [rsani →] [rsmdc →] [rsdenoise →] [rsvoicefilter →] tee ┬→ queue → rstransmissionsink
+///                                                                                                                                                 └→ queue → [rsmdc →] rstranscribe → fakesink
+/// ```
+///
+/// Channels which share a source (e.g. the left and right side of a sound
+/// card) each get one of its audio channels.
+///
+/// ```text
+/// source → audioconvert → deinterleave ┬→ <left>
+///                                      └→ <right>
+/// ```
+///
+/// Network streams (HTTP, e.g. from an Icecast server, or RTP) are named
+/// `remote<n>` rather than `source<n>`, so [`remote_source_of()`] can find
+/// them and [`reconnect()`] them without touching anything else. An RTP
+/// stream gets a jitter buffer to put its packets back in order before
+/// decoding.
+///
+/// ```text
+/// udpsrc → rtpjitterbuffer → decodebin → <name>
+/// ```
+///
+/// Channels received from a software defined radio get an IQ source and
+/// demodulator, with a channelizer picking out each channel when several
+/// share the same IQ samples.
+///
+/// ```text
+/// rsiqsrc → rsnbfmdemod → <name>
+///
+/// rsiqsrc → rschannelizer ┬→ <first>
+///                         └→ <second>
+/// ```
+///
+/// Once `terminate` is set, network streams are allowed to end so the
+/// pipeline can shut down cleanly.
+pub fn build(
+    config: &Config,
+    terminate: &Arc<AtomicBool>,
+) -> Result<Pipeline, Error> {
+    let pipeline = Pipeline::new(Some("receiver"));
+
+    for (i, (source, channels)) in config.sources().into_iter().enumerate() {
+        let mut branches = Vec::new();
+
+        for channel in channels {
+            let branch = build_channel(config, channel)?;
+            pipeline.add(&branch)?;
+            branches.push((channel.audio_channel, branch));
+        }
+
+        let target = match branches.as_slice() {
+            [(None, branch)] => branch.clone().upcast(),
+            _ => add_splitter(&pipeline, i, branches)?,
+        };
+        add_source(&pipeline, i, source, &target, terminate)?;
+    }
+
+    for (i, (_, channels)) in config.captures().into_iter().enumerate() {
+        let mut branches = Vec::new();
+
+        for (channel, sdr) in channels {
+            let branch = build_channel(config, channel)?;
+            pipeline.add(&branch)?;
+            branches.push((sdr, branch));
+        }
+
+        add_sdr_source(&pipeline, i, branches)?;
+    }
+
+    Ok(pipeline)
+}
+
+/// Find the network stream an element belongs to, if any.
+pub fn remote_source_of(src: &Object) -> Option<Element> {
+    // the sources are direct children of the pipeline
+    let mut object = src.clone();
+    while let Some(parent) = object.get_parent() {
+        if parent.get_parent().is_none() {
+            break;
+        }
+        object = parent;
+    }
+
+    if reconnect::is_remote(&object.get_name()) {
+        object.downcast().ok()
+    } else {
+        None
+    }
+}
+
+/// Restart a network stream, reconnecting to whatever it was receiving
+/// from. Everything downstream keeps running in the meantime.
+pub fn reconnect(source: &Element) -> Result<(), Error> {
+    source.set_state(State::Null)?;
+    source.sync_state_with_parent()?;
+
+    Ok(())
+}
+
+/// Figure out which channel an element message came from.
+pub fn channel_of(src: &Object) -> Option<String> {
+    // everything which posts messages is a direct child of the channel's bin
+    src.get_parent().map(|bin| bin.get_name().to_string())
+}
+
+fn build_channel(config: &Config, channel: &Channel) -> Result<Bin, Error> {
+    let bin = Bin::new(Some(&channel.name));
+
+    let convert = make("audioconvert", "convert")?;
+    let resample = make("audioresample", "resample")?;
+    let caps = make("capsfilter", "caps")?;
+    caps.set_property(
+        "caps",
+        &Caps::new_simple(
+            "audio/x-raw",
+            &[
+                ("format", &"F32LE"),
+                ("channels", &1),
+                ("rate", &SAMPLE_RATE),
+            ],
+        ),
+    )?;
+
+    let meter = make("rslevelmeter", "level")?;
+    meter.set_property("interval", &config.levels.interval)?;
+    meter.set_property("clip-level", &config.levels.clip_level)?;
+
+    let settings = config.squelch_for(channel);
+    let squelch = make("rssquelch", "squelch")?;
+    squelch.set_property("open-threshold", &settings.open_threshold)?;
+    squelch.set_property("close-threshold", &settings.close_threshold)?;
+    squelch.set_property("hang-time", &settings.hang_time)?;
+
+    let tee = make("tee", "tee")?;
+    let record_queue = make("queue", "record-queue")?;
+    let recorder = make("rstransmissionsink", "recorder")?;
+    let location = config
+        .recordings_for(channel)
+        .join("{start}-{sequence}.wav");
+    recorder.set_property("location", &location.display().to_string())?;
+    recorder.set_property("channel", &channel.name)?;
+
+    bin.add_many(&[
+        &convert,
+        &resample,
+        &caps,
+        &meter,
+        &squelch,
+        &tee,
+        &record_queue,
+        &recorder,
+    ])?;
+    Element::link_many(&[&convert, &resample, &caps, &meter, &squelch])?;
+    Element::link_many(&[&tee, &record_queue, &recorder])?;
+
+    // the tone and code detectors are chained between the squelch and the
+    // tee so they see the start and end of each transmission
+    let mut previous = squelch.clone();
+    let detectors = [
+        (channel.ctcss, "rsctcss", "ctcss"),
+        (channel.dcs, "rsdcs", "dcs"),
+        (channel.ani, "rsani", "ani"),
+        (channel.mdc, "rsmdc", "mdc"),
+    ];
+    for &(enabled, factory, name) in &detectors {
+        if enabled {
+            let detector = make(factory, name)?;
+            bin.add(&detector)?;
+            previous.link(&detector)?;
+            previous = detector;
+        }
+    }
+
+    // the band-pass would remove CTCSS and DCS, so these have to go after
+    // the detectors. The noise profile is learned before the AGC changes the
+    // level.
+    if let Some(strength) = channel.noise_reduction {
+        let denoise = make("rsdenoise", "denoise")?;
+        denoise.set_property("strength", &strength)?;
+        bin.add(&denoise)?;
+        previous.link(&denoise)?;
+        previous = denoise;
+    }
+    if let Some(settings) = channel.filter {
+        let filter = make("rsvoicefilter", "filter")?;
+        filter.set_property("de-emphasis", &settings.de_emphasis)?;
+        filter.set_property("agc", &settings.agc)?;
+        filter.set_property("target-level", &settings.target_level)?;
+        filter.set_property("max-gain", &settings.max_gain)?;
+        bin.add(&filter)?;
+        previous.link(&filter)?;
+        previous = filter;
+    }
+    previous.link(&tee)?;
+
+    if let Some(ref settings) = config.transcriber {
+        let queue = make("queue", "transcribe-queue")?;
+        // transcription can take a while, so let as much audio as necessary
+        // pile up instead of blocking the recorder
+        queue.set_property("max-size-buffers", &0_u32)?;
+        queue.set_property("max-size-bytes", &0_u32)?;
+        queue.set_property("max-size-time", &0_u64)?;
+
+        let transcribe = make("rstranscribe", "transcribe")?;
+        transcribe.set_property("engine", &settings.engine)?;
+        if let Some(ref model) = settings.model {
+            transcribe.set_property("model", &model.display().to_string())?;
+        }
+        if let Some(ref executable) = settings.executable {
+            transcribe.set_property(
+                "executable",
+                &executable.display().to_string(),
+            )?;
+        }
+        if let Some(ref language) = settings.language {
+            transcribe.set_property("language", language)?;
+        }
+
+        let text_sink = make("fakesink", "text-sink")?;
+        text_sink.set_property("sync", &false)?;
+
+        bin.add_many(&[&queue, &transcribe, &text_sink])?;
+        Element::link_many(&[&tee, &queue])?;
+        Element::link_many(&[&transcribe, &text_sink])?;
+
+        if channel.strip_mdc {
+            // the decoder before the tee already reports the bursts, this
+            // one just mutes them
+            let strip = make("rsmdc", "mdc-strip")?;
+            strip.set_property("strip", &true)?;
+            strip.set_property("post-messages", &false)?;
+            bin.add(&strip)?;
+            Element::link_many(&[&queue, &strip, &transcribe])?;
+        } else {
+            queue.link(&transcribe)?;
+        }
+    }
+
+    let sink =
+        GhostPad::new(Some("sink"), &convert.get_static_pad("sink").unwrap())
+            .expect("a ghost pad can always be made for a static pad");
+    bin.add_pad(&sink)?;
+
+    Ok(bin)
+}
+
+/// Split an interleaved stream into its individual audio channels, sending
+/// each one to the branch which asked for it.
+fn add_splitter(
+    pipeline: &Pipeline,
+    index: usize,
+    branches: Vec<(Option<u32>, Bin)>,
+) -> Result<Element, Error> {
+    let convert = make("audioconvert", &format!("source{}-convert", index))?;
+    let deinterleave = make("deinterleave", &format!("source{}-split", index))?;
+
+    pipeline.add_many(&[&convert, &deinterleave])?;
+    convert.link(&deinterleave)?;
+
+    // deinterleave adds a pad for each audio channel once it sees the caps
+    let unlinked = branches.clone();
+    deinterleave.connect_pad_added(move |deinterleave, pad| {
+        on_split_pad_added(deinterleave, pad, &branches)
+    });
+    deinterleave.connect_no_more_pads(move |deinterleave| {
+        for (audio_channel, branch) in &unlinked {
+            if !branch.get_static_pad("sink").unwrap().is_linked() {
+                log::warn!(
+                    "\"{}\" has no audio channel {} for \"{}\"",
+                    deinterleave.get_name(),
+                    audio_channel.unwrap_or(0),
+                    branch.get_name()
+                );
+            }
+        }
+    });
+
+    Ok(convert)
+}
+
+fn add_source(
+    pipeline: &Pipeline,
+    index: usize,
+    source: Source<'_>,
+    target: &Element,
+    terminate: &Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let remote = source.is_remote();
+    let name = if remote {
+        reconnect::remote_name(index)
+    } else {
+        format!("source{}", index)
+    };
+
+    match source {
+        Source::Uri(uri) => {
+            let source = make("uridecodebin", &name)?;
+            source.set_property("uri", &uri)?;
+            pipeline.add(&source)?;
+
+            // uridecodebin only creates its pads once it knows what the
+            // stream contains
+            let target = target.clone();
+            let terminate = Arc::clone(terminate);
+            source.connect_pad_added(move |source, pad| {
+                if on_pad_added(source, pad, &target) && remote {
+                    // a reconnected stream starts again from zero, so it
+                    // needs to be shifted to line up with everything else
+                    if let Some(running_time) = running_time(source) {
+                        pad.set_offset(running_time as i64);
+                    }
+                    watch_for_eos(source, pad, &terminate);
+                }
+            });
+        },
+        Source::Rtp(rtp) => {
+            add_rtp_source(pipeline, &name, rtp, target, terminate)?
+        },
+        Source::Default => {
+            let source = make("autoaudiosrc", &name)?;
+            pipeline.add(&source)?;
+            source.link(target)?;
+        },
+    }
+
+    Ok(())
+}
+
+fn add_rtp_source(
+    pipeline: &Pipeline,
+    name: &str,
+    rtp: &Rtp,
+    target: &Element,
+    terminate: &Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let bin = Bin::new(Some(name));
+
+    let udp = make("udpsrc", "udp")?;
+    udp.set_property("address", &rtp.address)?;
+    udp.set_property("port", &i32::from(rtp.port))?;
+    udp.set_property(
+        "caps",
+        &Caps::new_simple(
+            "application/x-rtp",
+            &[
+                ("media", &"audio"),
+                ("encoding-name", &rtp.encoding.to_uppercase()),
+                ("clock-rate", &(rtp.clock_rate().unwrap_or(0) as i32)),
+                ("channels", &(rtp.channels as i32)),
+            ],
+        ),
+    )?;
+    let jitterbuffer = make("rtpjitterbuffer", "jitterbuffer")?;
+    jitterbuffer.set_property("latency", &rtp.latency)?;
+    // decodebin picks the right depayloader and decoder for the encoding
+    let decode = make("decodebin", "decode")?;
+
+    bin.add_many(&[&udp, &jitterbuffer, &decode])?;
+    Element::link_many(&[&udp, &jitterbuffer, &decode])?;
+
+    // the decoder's pad comes and goes every time the stream is
+    // reconnected, so the bin's pad is pointed at whichever one is current
+    let src = GhostPad::new_no_target(Some("src"), PadDirection::Src)?;
+    bin.add_pad(&src)?;
+    watch_for_eos(bin.upcast_ref(), &src, terminate);
+
+    decode.connect_pad_added(move |decode, pad| {
+        if !is_raw_audio(decode, pad) {
+            return;
+        }
+        if let Err(e) = src.set_target(Some(pad)) {
+            log::error!("Unable to use \"{}\": {}", pad.get_name(), e);
+        }
+    });
+
+    pipeline.add(&bin)?;
+    bin.link(target)?;
+
+    Ok(())
+}
+
+/// Network streams should never end, so when one does the EOS is swallowed
+/// (otherwise every channel it feeds would shut down) and a [`SOURCE_LOST`]
+/// message is posted so it can be reconnected.
+fn watch_for_eos(source: &Element, pad: &Pad, terminate: &Arc<AtomicBool>) {
+    let source = source.clone();
+    let terminate = Arc::clone(terminate);
+
+    pad.add_probe(PadProbeType::EVENT_DOWNSTREAM, move |_, info| {
+        let is_eos = match info.data {
+            Some(PadProbeData::Event(ref event)) => match event.view() {
+                EventView::Eos(..) => true,
+                _ => false,
+            },
+            _ => false,
+        };
+
+        if !is_eos || terminate.load(Ordering::SeqCst) {
+            return PadProbeReturn::Ok;
+        }
+
+        let msg = Message::new_application(Structure::new_empty(SOURCE_LOST))
+            .src(Some(&source))
+            .build();
+        let _ = source.post_message(&msg);
+
+        PadProbeReturn::Drop
+    });
+}
+
+/// How long (in nanoseconds) the pipeline has been playing for, or `None`
+/// if it hasn't started yet.
+fn running_time(element: &Element) -> Option<u64> {
+    let clock = element.get_clock()?;
+    let now: ClockTime = clock.get_time() - element.get_base_time();
+
+    now.nseconds()
+}
+
+fn add_sdr_source(
+    pipeline: &Pipeline,
+    index: usize,
+    branches: Vec<(&Sdr, Bin)>,
+) -> Result<(), Error> {
+    // the config makes sure everything except the frequency is shared
+    let sdr = branches[0].0;
+
+    let source = make("rsiqsrc", &format!("sdr{}", index))?;
+    if let Some(location) = sdr.location() {
+        source.set_property("location", &location.display().to_string())?;
+    }
+    source.set_property("format", &sdr.format)?;
+    source.set_property("sample-rate", &sdr.sample_rate)?;
+    pipeline.add(&source)?;
+
+    if let [(sdr, branch)] = branches.as_slice() {
+        let demod = make("rsnbfmdemod", &format!("sdr{}-demod", index))?;
+        demod.set_property("offset", &sdr.offset())?;
+        demod.set_property("mode", &sdr.mode)?;
+        demod.set_property("bandwidth", &sdr.bandwidth.unwrap_or(0.0))?;
+        demod.set_property("deviation", &sdr.deviation)?;
+        demod.set_property("squelch", &sdr.squelch)?;
+        pipeline.add(&demod)?;
+
+        let branch: &Element = branch.upcast_ref();
+        Element::link_many(&[&source, &demod, branch])?;
+        return Ok(());
+    }
+
+    let channelizer =
+        make("rschannelizer", &format!("sdr{}-channelizer", index))?;
+    channelizer
+        .set_property("center-frequency", &sdr.center_frequency.unwrap_or(0))?;
+    channelizer.set_property("mode", &sdr.mode)?;
+    channelizer.set_property("bandwidth", &sdr.bandwidth.unwrap_or(0.0))?;
+    channelizer.set_property("deviation", &sdr.deviation)?;
+    channelizer.set_property("squelch", &sdr.squelch)?;
+    pipeline.add(&channelizer)?;
+    source.link(&channelizer)?;
+
+    // the channelizer has a pad for each frequency, e.g. "src_155200000"
+    for (sdr, branch) in &branches {
+        let name = format!("src_{}", sdr.frequency.unwrap_or(0));
+        channelizer.link_pads(Some(&name), branch, Some("sink"))?;
+    }
+
+    Ok(())
+}
+
+fn make(factory: &str, name: &str) -> Result<Element, Error> {
+    ElementFactory::make(factory, Some(name))
+        .map_err(|_| Error::MissingElement(factory.to_string()))
+}
+
+/// Link a newly decoded stream to `target`, returning `true` if it was
+/// used.
+fn on_pad_added(source: &Element, pad: &Pad, target: &Element) -> bool {
+    if !is_raw_audio(source, pad) {
+        return false;
+    }
+
+    let sink_pad = target.get_static_pad("sink").unwrap();
+    if sink_pad.is_linked() {
+        log::warn!(
+            "Ignoring the extra audio stream from \"{}\"",
+            source.get_name()
+        );
+        return false;
+    }
+
+    if let Err(e) = pad.link(&sink_pad) {
+        log::error!("Unable to link \"{}\": {:?}", pad.get_name(), e);
+        return false;
+    }
+
+    true
+}
+
+fn is_raw_audio(source: &Element, pad: &Pad) -> bool {
+    let caps = match pad.get_current_caps() {
+        Some(caps) => caps,
+        None => return false,
+    };
+    let pad_type = match caps.get_structure(0) {
+        Some(s) => s.get_name().to_string(),
+        None => return false,
+    };
+
+    if !pad_type.starts_with("audio/x-raw") {
+        log::debug!(
+            "Ignoring the \"{}\" pad from \"{}\" ({})",
+            pad.get_name(),
+            source.get_name(),
+            pad_type
+        );
+        return false;
+    }
+
+    true
+}
+
+fn on_split_pad_added(
+    deinterleave: &Element,
+    pad: &Pad,
+    branches: &[(Option<u32>, Bin)],
+) {
+    // the pads are named "src_0", "src_1", etc.
+    let audio_channel = pad
+        .get_name()
+        .trim_start_matches("src_")
+        .parse::<u32>()
+        .ok();
+    let branch = branches
+        .iter()
+        .find(|(c, _)| c.is_some() && *c == audio_channel)
+        .map(|(_, branch)| branch);
+
+    let branch = match branch {
+        Some(branch) => branch,
+        None => {
+            log::debug!(
+                "Discarding audio channel {:?} from \"{}\"",
+                audio_channel,
+                deinterleave.get_name()
+            );
+            if let Err(e) = discard(deinterleave, pad) {
+                log::error!("Unable to discard \"{}\": {}", pad.get_name(), e);
+            }
+            return;
+        },
+    };
+
+    if let Err(e) = pad.link(&branch.get_static_pad("sink").unwrap()) {
+        log::error!("Unable to link \"{}\": {:?}", pad.get_name(), e);
+    }
+}
+
+/// Send an unused audio channel to a `fakesink`, otherwise `deinterleave`
+/// would stop with a "not-linked" error.
+fn discard(deinterleave: &Element, pad: &Pad) -> Result<(), Error> {
+    let pipeline = deinterleave
+        .get_parent()
+        .and_then(|parent| parent.downcast::<Bin>().ok())
+        .expect("the splitter is always in a pipeline");
+    let name =
+        format!("{}-{}-discard", deinterleave.get_name(), pad.get_name());
+
+    let sink = make("fakesink", &name)?;
+    sink.set_property("sync", &false)?;
+    pipeline.add(&sink)?;
+    sink.sync_state_with_parent()?;
+    deinterleave.link_pads(Some(&pad.get_name()), &sink, None)?;
+
+    Ok(())
+}
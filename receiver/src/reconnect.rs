@@ -0,0 +1,181 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+/// The prefix given to the names of network stream sources, so they can be
+/// told apart from sound cards, files and SDRs.
+const REMOTE_PREFIX: &str = "remote";
+
+/// The name to give the `index`'th source if it's a network stream.
+pub fn remote_name(index: usize) -> String {
+    format!("{}{}", REMOTE_PREFIX, index)
+}
+
+/// Is `name` one given out by [`remote_name()`]?
+pub fn is_remote(name: &str) -> bool {
+    name.starts_with(REMOTE_PREFIX)
+        && name.len() > REMOTE_PREFIX.len()
+        && name[REMOTE_PREFIX.len()..]
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+}
+
+/// Keeps track of which network streams have dropped out, and when they
+/// should be reconnected.
+///
+/// A stream is reconnected `delay` after it first drops out. Whatever else
+/// it reports in the meantime (an error is normally followed by an EOS) is
+/// ignored, and a reconnect which fails waits another `delay` before trying
+/// again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reconnects {
+    delay: Duration,
+    pending: HashMap<String, Instant>,
+}
+
+impl Reconnects {
+    pub fn new(delay: Duration) -> Reconnects {
+        Reconnects {
+            delay,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Note that the network stream called `name` stopped sending audio (it
+    /// either failed or posted a `SOURCE_LOST`), returning whether it'll be
+    /// reconnected. Nothing is reconnected once we've started `stopping`.
+    pub fn dropped_out(
+        &mut self,
+        name: &str,
+        stopping: bool,
+        now: Instant,
+    ) -> bool {
+        if stopping {
+            return false;
+        }
+
+        self.schedule(name, now);
+        true
+    }
+
+    /// Try again later after reconnecting `name` failed.
+    pub fn retry(&mut self, name: &str, now: Instant) {
+        self.schedule(name, now);
+    }
+
+    /// Take the streams which have waited long enough to be reconnected.
+    pub fn take_due(&mut self, now: Instant) -> Vec<String> {
+        let mut due: Vec<String> = self
+            .pending
+            .iter()
+            .filter(|(_, &when)| when <= now)
+            .map(|(name, _)| name.clone())
+            .collect();
+        due.sort();
+
+        for name in &due {
+            self.pending.remove(name);
+        }
+
+        due
+    }
+
+    fn schedule(&mut self, name: &str, now: Instant) {
+        let delay = self.delay;
+
+        // only the first report counts
+        self.pending.entry(name.to_string()).or_insert_with(|| {
+            log::info!("Reconnecting \"{}\" in {}s", name, delay.as_secs());
+            now + delay
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DELAY: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn remote_sources_are_recognised_by_name() {
+        assert!(is_remote(&remote_name(0)));
+        assert!(is_remote(&remote_name(12)));
+
+        for name in &["remote", "remotely", "remote1a", "source0", "left"] {
+            assert!(!is_remote(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn streams_are_reconnected_after_the_delay() {
+        let start = Instant::now();
+        let mut reconnects = Reconnects::new(DELAY);
+
+        assert!(reconnects.dropped_out("remote0", false, start));
+
+        let almost = start + DELAY - Duration::from_millis(1);
+        assert!(reconnects.take_due(almost).is_empty());
+        assert_eq!(reconnects.take_due(start + DELAY), vec!["remote0"]);
+
+        // ... and only once
+        assert!(reconnects.take_due(start + DELAY * 10).is_empty());
+    }
+
+    #[test]
+    fn only_the_first_dropout_counts() {
+        let start = Instant::now();
+        let mut reconnects = Reconnects::new(DELAY);
+
+        // an error followed by the EOS (and SOURCE_LOST) a moment later
+        assert!(reconnects.dropped_out("remote0", false, start));
+        let later = start + Duration::from_secs(2);
+        assert!(reconnects.dropped_out("remote0", false, later));
+
+        assert_eq!(reconnects.take_due(start + DELAY), vec!["remote0"]);
+    }
+
+    #[test]
+    fn failed_reconnects_wait_another_delay() {
+        let start = Instant::now();
+        let mut reconnects = Reconnects::new(DELAY);
+
+        reconnects.dropped_out("remote0", false, start);
+        let first = start + DELAY;
+        assert_eq!(reconnects.take_due(first), vec!["remote0"]);
+
+        reconnects.retry("remote0", first);
+        assert!(reconnects.take_due(first).is_empty());
+        assert_eq!(reconnects.take_due(first + DELAY), vec!["remote0"]);
+    }
+
+    #[test]
+    fn each_stream_has_its_own_schedule() {
+        let start = Instant::now();
+        let mut reconnects = Reconnects::new(DELAY);
+
+        reconnects.dropped_out("remote1", false, start);
+        let later = start + Duration::from_secs(3);
+        reconnects.dropped_out("remote0", false, later);
+
+        assert_eq!(reconnects.take_due(start + DELAY), vec!["remote1"]);
+        assert_eq!(reconnects.take_due(later + DELAY), vec!["remote0"]);
+
+        reconnects.dropped_out("remote0", false, start);
+        reconnects.dropped_out("remote1", false, start);
+        assert_eq!(
+            reconnects.take_due(start + DELAY),
+            vec!["remote0", "remote1"]
+        );
+    }
+
+    #[test]
+    fn nothing_is_reconnected_while_shutting_down() {
+        let start = Instant::now();
+        let mut reconnects = Reconnects::new(DELAY);
+
+        assert!(!reconnects.dropped_out("remote0", true, start));
+        assert!(reconnects.take_due(start + DELAY).is_empty());
+    }
+}
@@ -0,0 +1,496 @@
+use chrono::Utc;
+use gstreamer::StructureRef;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use storage::Database;
+use transcribe_server::{Broadcaster, Level, Status};
+use transmission::{
+    messages::{
+        ANI, CTCSS, DCS, LEVEL, MDC, TRANSCRIPTION, TRANSMISSION_END,
+        TRANSMISSION_START, TRANSMISSION_WRITTEN,
+    },
+    Event, Transmission,
+};
+use uuid::Uuid;
+
+/// How long a transmission can wait for its transcript (or a transcript for
+/// its transmission) before we stop expecting it to turn up.
+const STALE_AFTER: Duration = Duration::from_secs(15 * 60);
+
+/// Turns the element messages posted by a channel's branch of the pipeline
+/// into stored [`Transmission`]s, letting anyone connected to the server know
+/// as things happen. Signal level measurements are passed on to the server's
+/// status API.
+///
+/// The recording and transcription branches run independently, so the
+/// transcript for a transmission may arrive before or after the recording is
+/// finished.
+#[derive(Debug)]
+pub struct Recorder {
+    store: Database,
+    channel: String,
+    expect_transcripts: bool,
+    events: Broadcaster,
+    status: Status,
+    /// Was the last level measurement clipped?
+    clipping: bool,
+    /// Saved transmissions which are still waiting for a transcript, keyed by
+    /// sequence number, and when they were saved.
+    awaiting_transcript: HashMap<u32, (Uuid, Instant)>,
+    /// Transcripts which arrived before their recording was saved, and when
+    /// they arrived. A transcript of `None` means transcription failed.
+    early_transcripts: HashMap<u32, (Option<String>, Instant)>,
+    /// CTCSS tones for transmissions which haven't been saved yet, and when
+    /// they were detected. These are always posted before the transmission is
+    /// written.
+    tones: HashMap<u32, (f64, Instant)>,
+    /// DCS codes for transmissions which haven't been saved yet, and when
+    /// they were detected.
+    codes: HashMap<u32, (String, Instant)>,
+    /// Unit IDs for transmissions which haven't been saved yet, and when they
+    /// were decoded.
+    units: HashMap<u32, (String, Instant)>,
+    /// Transmissions which haven't been saved yet that raised an emergency
+    /// alarm, and when they raised it.
+    emergencies: HashMap<u32, Instant>,
+}
+
+impl Recorder {
+    pub fn new(
+        store: Database,
+        channel: String,
+        expect_transcripts: bool,
+        events: Broadcaster,
+        status: Status,
+    ) -> Recorder {
+        status.add_channel(&channel);
+
+        Recorder {
+            store,
+            channel,
+            expect_transcripts,
+            events,
+            status,
+            clipping: false,
+            awaiting_transcript: HashMap::new(),
+            early_transcripts: HashMap::new(),
+            tones: HashMap::new(),
+            codes: HashMap::new(),
+            units: HashMap::new(),
+            emergencies: HashMap::new(),
+        }
+    }
+
+    /// Forget about anything in progress. Sequence numbers start again when
+    /// the pipeline is restarted.
+    pub fn reset(&mut self) {
+        self.awaiting_transcript.clear();
+        self.early_transcripts.clear();
+        self.tones.clear();
+        self.codes.clear();
+        self.units.clear();
+        self.emergencies.clear();
+        self.clipping = false;
+    }
+
+    /// Give up on anything which has been waiting for its transcript (or
+    /// for its recording) for too long, e.g. because the other branch dropped
+    /// it. Tones, codes and unit IDs for a transmission which was never saved
+    /// are dropped too.
+    fn evict_stale(&mut self, now: Instant) {
+        let channel = &self.channel;
+
+        self.awaiting_transcript.retain(|sequence, (_, saved)| {
+            let keep = now.duration_since(*saved) < STALE_AFTER;
+            if !keep {
+                log::warn!(
+                    "Transmission {} on \"{}\" never got a transcript",
+                    sequence,
+                    channel
+                );
+            }
+            keep
+        });
+        self.early_transcripts.retain(|_, (_, received)| {
+            now.duration_since(*received) < STALE_AFTER
+        });
+
+        let fresh = |at: &Instant| now.duration_since(*at) < STALE_AFTER;
+        self.tones.retain(|_, (_, detected)| fresh(detected));
+        self.codes.retain(|_, (_, detected)| fresh(detected));
+        self.units.retain(|_, (_, decoded)| fresh(decoded));
+        self.emergencies.retain(|_, raised| fresh(raised));
+    }
+
+    pub fn handle_message(&mut self, s: &StructureRef) {
+        match s.get_name() {
+            TRANSMISSION_START => {
+                let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+                log::debug!("Transmission {} started", sequence);
+
+                self.events.publish(&Event::TransmissionStarted {
+                    channel: self.channel.clone(),
+                    sequence,
+                    start: Utc::now(),
+                });
+            },
+            TRANSMISSION_END => {
+                log::debug!(
+                    "Transmission {} ended",
+                    s.get_some::<u32>("sequence").unwrap_or(0)
+                );
+            },
+            TRANSMISSION_WRITTEN => self.on_written(s),
+            TRANSCRIPTION => self.on_transcription(s),
+            CTCSS => {
+                if let (Ok(sequence), Ok(frequency)) = (
+                    s.get_some::<u32>("sequence"),
+                    s.get_some::<f64>("frequency"),
+                ) {
+                    log::debug!(
+                        "Transmission {} used a {} Hz tone",
+                        sequence,
+                        frequency
+                    );
+                    self.tones.insert(sequence, (frequency, Instant::now()));
+                }
+            },
+            DCS => {
+                if let (Ok(sequence), Ok(Some(code))) =
+                    (s.get_some::<u32>("sequence"), s.get::<String>("code"))
+                {
+                    log::debug!("Transmission {} used {}", sequence, code);
+                    self.codes.insert(sequence, (code, Instant::now()));
+                }
+            },
+            ANI => {
+                if let (Ok(sequence), Ok(Some(unit))) =
+                    (s.get_some::<u32>("sequence"), s.get::<String>("unit"))
+                {
+                    log::debug!(
+                        "Transmission {} came from unit {}",
+                        sequence,
+                        unit
+                    );
+                    self.units.insert(sequence, (unit, Instant::now()));
+                }
+            },
+            MDC => self.on_data_burst(s),
+            LEVEL => self.on_level(s),
+            _ => {},
+        }
+    }
+
+    fn on_written(&mut self, s: &StructureRef) {
+        let json = match s.get::<String>("transmission") {
+            Ok(Some(json)) => json,
+            _ => {
+                log::warn!(
+                    "Received a \"{}\" message without a transmission",
+                    s
+                );
+                return;
+            },
+        };
+        let transmission: Transmission = match serde_json::from_str(&json) {
+            Ok(t) => t,
+            Err(e) => {
+                log::error!("Unable to parse the transmission: {}", e);
+                return;
+            },
+        };
+
+        self.save_transmission(transmission, Instant::now());
+    }
+
+    /// Save a transmission which has just been written to disk, along with
+    /// anything we've already learned about it.
+    fn save_transmission(
+        &mut self,
+        mut transmission: Transmission,
+        now: Instant,
+    ) {
+        self.evict_stale(now);
+
+        let sequence = transmission.sequence;
+        let early = self.early_transcripts.remove(&sequence);
+        let transcript_pending = early.is_none();
+        transmission.transcript = early.and_then(|(text, _)| text);
+        transmission.ctcss = self.tones.remove(&sequence).map(|(tone, _)| tone);
+        transmission.dcs = self.codes.remove(&sequence).map(|(code, _)| code);
+        transmission.unit = self.units.remove(&sequence).map(|(unit, _)| unit);
+        transmission.emergency = self.emergencies.remove(&sequence).is_some();
+
+        log::info!(
+            "Received transmission {} on \"{}\" ({:.1}s, saved to \"{}\")",
+            transmission.sequence,
+            transmission.channel,
+            transmission.duration.as_millis() as f64 / 1000.0,
+            transmission.audio.display()
+        );
+
+        if let Err(e) = self.store.save(&transmission) {
+            log::error!(
+                "Unable to save transmission {}: {}",
+                transmission.id,
+                e
+            );
+            return;
+        }
+
+        if self.expect_transcripts && transcript_pending {
+            self.awaiting_transcript
+                .insert(transmission.sequence, (transmission.id, now));
+        }
+
+        let transcribed = transmission.transcript.is_some();
+        self.events.publish(&Event::TransmissionRecorded {
+            transmission: transmission.clone(),
+        });
+        if transcribed {
+            self.events
+                .publish(&Event::TranscriptionFinished { transmission });
+        }
+    }
+
+    fn on_data_burst(&mut self, s: &StructureRef) {
+        let now = Instant::now();
+        let (sequence, unit) =
+            match (s.get_some::<u32>("sequence"), s.get::<String>("unit")) {
+                (Ok(sequence), Ok(Some(unit))) => (sequence, unit),
+                _ => return,
+            };
+
+        if s.get_some::<bool>("emergency").unwrap_or(false) {
+            log::warn!(
+                "Unit {} raised an emergency alarm on \"{}\"",
+                unit,
+                self.channel
+            );
+            self.emergencies.insert(sequence, now);
+        } else {
+            log::debug!("Transmission {} came from unit {}", sequence, unit);
+        }
+
+        self.units.insert(sequence, (unit, now));
+    }
+
+    fn on_level(&mut self, s: &StructureRef) {
+        let (rms, peak) =
+            match (s.get_some::<f64>("rms"), s.get_some::<f64>("peak")) {
+                (Ok(rms), Ok(peak)) => (rms, peak),
+                _ => return,
+            };
+        let clipped = s.get_some::<u32>("clipped").unwrap_or(0);
+
+        log::debug!(
+            "\"{}\" level: {:.1} dBFS RMS, {:.1} dBFS peak, {} clipped",
+            self.channel,
+            rms,
+            peak,
+            clipped
+        );
+
+        // only mention clipping when it starts and stops, otherwise we'd
+        // fill the logs with a warning every interval
+        match (self.clipping, clipped > 0) {
+            (false, true) => log::warn!(
+                "The audio on \"{}\" is clipping ({} samples)",
+                self.channel,
+                clipped
+            ),
+            (true, false) => {
+                log::info!("The audio on \"{}\" stopped clipping", self.channel)
+            },
+            _ => {},
+        }
+        self.clipping = clipped > 0;
+
+        self.status.update_level(
+            &self.channel,
+            Level {
+                measured: Utc::now(),
+                rms,
+                peak,
+                clipped,
+            },
+        );
+    }
+
+    fn on_transcription(&mut self, s: &StructureRef) {
+        let now = Instant::now();
+        self.evict_stale(now);
+
+        let sequence = s.get_some::<u32>("sequence").unwrap_or(0);
+
+        if let Ok(Some(error)) = s.get::<String>("error") {
+            log::warn!(
+                "Unable to transcribe transmission {} on \"{}\": {}",
+                sequence,
+                self.channel,
+                error
+            );
+            // the transmission is left without a transcript
+            if self.awaiting_transcript.remove(&sequence).is_none() {
+                self.early_transcripts.insert(sequence, (None, now));
+            }
+            return;
+        }
+
+        let text = match s.get::<String>("text") {
+            Ok(Some(text)) => text,
+            _ => return,
+        };
+
+        log::info!("Transmission {} said \"{}\"", sequence, text);
+
+        let id = match self.awaiting_transcript.remove(&sequence) {
+            Some((id, _)) => id,
+            None => {
+                self.early_transcripts.insert(sequence, (Some(text), now));
+                return;
+            },
+        };
+
+        let mut transmission = match self.store.load(id) {
+            Ok(Some(t)) => t,
+            Ok(None) => return,
+            Err(e) => {
+                log::error!("Unable to load transmission {}: {}", id, e);
+                return;
+            },
+        };
+        transmission.transcript = Some(text);
+
+        if let Err(e) = self.store.save(&transmission) {
+            log::error!("Unable to save the transcript for {}: {}", id, e);
+            return;
+        }
+
+        self.events
+            .publish(&Event::TranscriptionFinished { transmission });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANNEL: &str = "fire";
+
+    fn recorder() -> Recorder {
+        Recorder::new(
+            Database::in_memory().unwrap(),
+            CHANNEL.to_string(),
+            false,
+            Broadcaster::new(),
+            Status::new(),
+        )
+    }
+
+    fn transmission(sequence: u32) -> Transmission {
+        Transmission::new(
+            CHANNEL,
+            sequence,
+            Utc::now(),
+            Duration::from_secs(0),
+            Duration::from_secs(5),
+            format!("{}.wav", sequence),
+        )
+    }
+
+    /// Pretend every decoder had something to say about `sequence`.
+    fn tag(recorder: &mut Recorder, sequence: u32, now: Instant) {
+        recorder.tones.insert(sequence, (100.0, now));
+        recorder.codes.insert(sequence, ("D023N".to_string(), now));
+        recorder.units.insert(sequence, ("1234".to_string(), now));
+        recorder.emergencies.insert(sequence, now);
+    }
+
+    fn nothing_pending(recorder: &Recorder) -> bool {
+        recorder.tones.is_empty()
+            && recorder.codes.is_empty()
+            && recorder.units.is_empty()
+            && recorder.emergencies.is_empty()
+    }
+
+    #[test]
+    fn tags_are_attached_to_their_transmission() {
+        let mut recorder = recorder();
+        let start = Instant::now();
+        tag(&mut recorder, 1, start);
+        tag(&mut recorder, 2, start);
+        let t = transmission(1);
+        let id = t.id;
+
+        recorder.save_transmission(t, start + Duration::from_secs(1));
+
+        let saved = recorder.store.load(id).unwrap().unwrap();
+        assert_eq!(saved.ctcss, Some(100.0));
+        assert_eq!(saved.dcs.as_deref(), Some("D023N"));
+        assert_eq!(saved.unit.as_deref(), Some("1234"));
+        assert!(saved.emergency);
+        // the other transmission's tags are still waiting
+        assert_eq!(recorder.tones.keys().collect::<Vec<_>>(), vec![&2]);
+    }
+
+    #[test]
+    fn stale_tags_are_evicted() {
+        let mut recorder = recorder();
+        let start = Instant::now();
+        tag(&mut recorder, 1, start);
+
+        recorder.evict_stale(start + STALE_AFTER - Duration::from_secs(1));
+        assert!(!nothing_pending(&recorder));
+        assert_eq!(recorder.emergencies.len(), 1);
+
+        recorder.evict_stale(start + STALE_AFTER);
+        assert!(nothing_pending(&recorder));
+    }
+
+    #[test]
+    fn tags_for_transmissions_which_were_never_saved_dont_leak() {
+        let mut recorder = recorder();
+        let start = Instant::now();
+
+        // the recording branch dropped transmission 1
+        tag(&mut recorder, 1, start);
+        let later = start + STALE_AFTER;
+        tag(&mut recorder, 2, later);
+        let t = transmission(2);
+        let id = t.id;
+        recorder.save_transmission(t, later);
+
+        assert!(nothing_pending(&recorder));
+        assert!(recorder.store.load(id).unwrap().unwrap().emergency);
+    }
+
+    #[test]
+    fn stale_tags_arent_attached_to_a_reused_sequence_number() {
+        let mut recorder = recorder();
+        let start = Instant::now();
+        tag(&mut recorder, 1, start);
+        let t = transmission(1);
+        let id = t.id;
+
+        recorder.save_transmission(t, start + STALE_AFTER);
+
+        let saved = recorder.store.load(id).unwrap().unwrap();
+        assert_eq!(saved.ctcss, None);
+        assert_eq!(saved.dcs, None);
+        assert_eq!(saved.unit, None);
+        assert!(!saved.emergency);
+    }
+
+    #[test]
+    fn reset_forgets_everything() {
+        let mut recorder = recorder();
+        tag(&mut recorder, 1, Instant::now());
+
+        recorder.reset();
+
+        assert!(nothing_pending(&recorder));
+    }
+}
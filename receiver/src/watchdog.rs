@@ -0,0 +1,485 @@
+use crate::config::Alerts;
+use chrono::Utc;
+use gstreamer::StructureRef;
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+use transcribe_server::{Broadcaster, Status};
+use transmission::{
+    messages::{LEVEL, TRANSMISSION_END, TRANSMISSION_START},
+    Alert, AlertKind, Event,
+};
+
+/// Watches the squelch and level meter messages from a channel, raising an
+/// [`Alert`] when a transmission goes on for too long or the channel has been
+/// silent for too long.
+///
+/// Alerts are logged, shown on the server's status API, sent to anyone
+/// listening for events and passed to the (optional) alert command. Time is
+/// measured using the timestamps in the messages, so this works the same when
+/// the audio is read from a file faster than real time. Between messages,
+/// [`Watchdog::check()`] keeps the clock ticking so a channel which stops
+/// sending audio altogether is noticed too.
+#[derive(Debug)]
+pub struct Watchdog {
+    channel: String,
+    settings: Alerts,
+    events: Broadcaster,
+    status: Status,
+    /// When the current transmission started, if the squelch is open.
+    transmission_start: Option<u64>,
+    /// When the channel went silent, if it is silent.
+    silent_since: Option<u64>,
+    /// The timestamp at the end of the last level measurement, and when it
+    /// arrived.
+    last_level: Option<(u64, Instant)>,
+    /// The alerts which are currently raised.
+    active: Vec<Alert>,
+    /// Events waiting to be passed to the alert command.
+    commands: Option<Sender<Event>>,
+}
+
+impl Watchdog {
+    pub fn new(
+        channel: String,
+        settings: Alerts,
+        events: Broadcaster,
+        status: Status,
+    ) -> Watchdog {
+        let commands = settings.command.clone().map(spawn_command_runner);
+
+        Watchdog {
+            channel,
+            settings,
+            events,
+            status,
+            transmission_start: None,
+            silent_since: None,
+            last_level: None,
+            active: Vec::new(),
+            commands,
+        }
+    }
+
+    /// Forget about the current transmission and silence. Timestamps start
+    /// again when the pipeline is restarted.
+    ///
+    /// Any raised alerts stay raised until the channel is seen working again.
+    pub fn reset(&mut self) {
+        self.transmission_start = None;
+        self.silent_since = None;
+        self.last_level = None;
+    }
+
+    /// Check for problems using the wall clock, for when the messages have
+    /// dried up. This should be called regularly (e.g. every second).
+    ///
+    /// A channel which hasn't measured any audio for a while counts as
+    /// silent.
+    pub fn check(&mut self, now: Instant) {
+        let (timestamp, received) = match self.last_level {
+            Some(last) => last,
+            None => return,
+        };
+
+        // assume the stream carries on in real time from the last message
+        let elapsed = now.saturating_duration_since(received);
+        if elapsed >= STALLED_AFTER {
+            self.silent_since.get_or_insert(timestamp);
+        }
+
+        self.check_alerts(timestamp + elapsed.as_nanos() as u64);
+    }
+
+    pub fn handle_message(&mut self, s: &StructureRef) {
+        match s.get_name() {
+            TRANSMISSION_START => {
+                self.transmission_started(s.get_some::<u64>("timestamp").ok())
+            },
+            TRANSMISSION_END => self.transmission_ended(),
+            LEVEL => self.on_level(s),
+            _ => {},
+        }
+    }
+
+    fn on_level(&mut self, s: &StructureRef) {
+        let (timestamp, duration, rms) = match (
+            s.get_some::<u64>("timestamp"),
+            s.get_some::<u64>("duration"),
+            s.get_some::<f64>("rms"),
+        ) {
+            (Ok(timestamp), Ok(duration), Ok(rms)) => {
+                (timestamp, duration, rms)
+            },
+            _ => return,
+        };
+
+        self.level_measured(timestamp, duration, rms, Instant::now());
+    }
+
+    fn transmission_started(&mut self, timestamp: Option<u64>) {
+        self.transmission_start = timestamp;
+    }
+
+    fn transmission_ended(&mut self) {
+        self.transmission_start = None;
+        self.update(AlertKind::StuckCarrier, None);
+    }
+
+    /// The level meter measured `rms` over the `duration` from `timestamp`,
+    /// and the reading arrived at `received`.
+    fn level_measured(
+        &mut self,
+        timestamp: u64,
+        duration: u64,
+        rms: f64,
+        received: Instant,
+    ) {
+        let now = timestamp + duration;
+        self.last_level = Some((now, received));
+
+        if rms < self.settings.silence_level {
+            self.silent_since.get_or_insert(timestamp);
+        } else {
+            self.silent_since = None;
+        }
+
+        self.check_alerts(now);
+    }
+
+    /// Raise or clear alerts, as of `now` (a message timestamp).
+    fn check_alerts(&mut self, now: u64) {
+        let max_transmission = self.settings.max_transmission();
+        let stuck_carrier = self
+            .transmission_start
+            .and_then(|start| exceeded(start, now, max_transmission))
+            .map(|max| {
+                format!(
+                    "\"{}\" has been transmitting for more than {}s, the \
+                     transmitter may be stuck on",
+                    self.channel,
+                    max.as_secs()
+                )
+            });
+        self.update(AlertKind::StuckCarrier, stuck_carrier);
+
+        let max_silence = self.settings.max_silence();
+        let dead_air = self
+            .silent_since
+            .and_then(|since| exceeded(since, now, max_silence))
+            .map(|max| {
+                format!(
+                    "\"{}\" has been silent for more than {}s, the receiver \
+                     may be disconnected",
+                    self.channel,
+                    max.as_secs()
+                )
+            });
+        self.update(AlertKind::DeadAir, dead_air);
+    }
+
+    /// Raise or clear an alert, depending on whether there's a problem.
+    fn update(&mut self, kind: AlertKind, problem: Option<String>) {
+        let index = self.active.iter().position(|a| a.kind == kind);
+
+        match (problem, index) {
+            (Some(message), None) => {
+                log::warn!("{}", message);
+
+                let alert = Alert {
+                    channel: self.channel.clone(),
+                    kind,
+                    raised: Utc::now(),
+                    message,
+                };
+                self.active.push(alert.clone());
+                self.status.raise_alert(alert.clone());
+                self.notify(Event::AlertRaised { alert });
+            },
+            (None, Some(index)) => {
+                let alert = self.active.remove(index);
+                log::info!(
+                    "The {} alert on \"{}\" has cleared",
+                    alert.kind,
+                    self.channel
+                );
+
+                self.status.clear_alert(&self.channel, kind);
+                self.notify(Event::AlertCleared { alert });
+            },
+            _ => {},
+        }
+    }
+
+    fn notify(&self, event: Event) {
+        self.events.publish(&event);
+
+        if let Some(ref commands) = self.commands {
+            let _ = commands.send(event);
+        }
+    }
+}
+
+/// How long a channel can go without a level measurement before it counts
+/// as silent.
+const STALLED_AFTER: Duration = Duration::from_secs(10);
+
+/// Start a background thread which runs the alert command for each event, so
+/// the pipeline isn't held up while it runs. Events are handled one at a time
+/// so the command sees them in order.
+fn spawn_command_runner(command: PathBuf) -> Sender<Event> {
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        for event in rx {
+            if let Err(e) = run_command(&command, &event) {
+                log::error!(
+                    "Unable to run the alert command, \"{}\": {}",
+                    command.display(),
+                    e
+                );
+            }
+        }
+    });
+
+    tx
+}
+
+/// If more than `max` has passed between `since` and `now` (both in
+/// nanoseconds), get `max` back.
+fn exceeded(since: u64, now: u64, max: Option<Duration>) -> Option<Duration> {
+    max.filter(|max| now.saturating_sub(since) >= max.as_nanos() as u64)
+}
+
+/// Run the alert command, passing it the event as JSON on stdin.
+fn run_command(command: &Path, event: &Event) -> Result<(), String> {
+    let json = serde_json::to_string(event).map_err(|e| e.to_string())?;
+
+    let mut child = Command::new(command)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    child
+        .stdin
+        .take()
+        .expect("stdin is always piped")
+        .write_all(json.as_bytes())
+        .map_err(|e| e.to_string())?;
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("it exited with {}", status))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{mpsc::Receiver, Arc};
+
+    const SECOND: u64 = 1_000_000_000;
+    const CHANNEL: &str = "fire";
+
+    struct Harness {
+        watchdog: Watchdog,
+        status: Status,
+        events: Receiver<Arc<str>>,
+    }
+
+    impl Harness {
+        fn new(max_transmission: u64, max_silence: u64) -> Harness {
+            let settings = Alerts {
+                max_transmission,
+                max_silence,
+                ..Alerts::default()
+            };
+            let events = Broadcaster::new();
+            let status = Status::new();
+            let rx = events.subscribe();
+
+            Harness {
+                watchdog: Watchdog::new(
+                    CHANNEL.to_string(),
+                    settings,
+                    events,
+                    status.clone(),
+                ),
+                status,
+                events: rx,
+            }
+        }
+
+        /// The kinds of alert currently shown on the status API.
+        fn alerts(&self) -> Vec<AlertKind> {
+            self.status
+                .snapshot()
+                .channels
+                .get(CHANNEL)
+                .map(|c| c.alerts.iter().map(|a| a.kind).collect())
+                .unwrap_or_default()
+        }
+
+        fn events(&self) -> Vec<Event> {
+            self.events
+                .try_iter()
+                .map(|json| serde_json::from_str(&json).unwrap())
+                .collect()
+        }
+
+        /// The alert which was raised then cleared, checking nothing else
+        /// happened.
+        fn raised_then_cleared(&self) -> Alert {
+            let mut events = self.events().into_iter();
+
+            match (events.next(), events.next(), events.next()) {
+                (
+                    Some(Event::AlertRaised { alert: raised }),
+                    Some(Event::AlertCleared { alert: cleared }),
+                    None,
+                ) => {
+                    assert_eq!(cleared, raised);
+                    raised
+                },
+                other => panic!("Unexpected events: {:?}", other),
+            }
+        }
+
+        /// One second of audio at `rms` dBFS, measured `t` seconds in.
+        fn level(&mut self, t: u64, rms: f64, received: Instant) {
+            self.watchdog
+                .level_measured(t * SECOND, SECOND, rms, received);
+        }
+    }
+
+    #[test]
+    fn stuck_carrier_is_raised_then_cleared_at_the_end() {
+        let mut harness = Harness::new(300, 0);
+
+        harness.watchdog.transmission_started(Some(10 * SECOND));
+        harness.watchdog.check_alerts(309 * SECOND);
+        assert!(harness.alerts().is_empty());
+
+        harness.watchdog.check_alerts(310 * SECOND);
+        assert_eq!(harness.alerts(), vec![AlertKind::StuckCarrier]);
+
+        // it's only raised once
+        harness.watchdog.check_alerts(400 * SECOND);
+
+        harness.watchdog.transmission_ended();
+        assert!(harness.alerts().is_empty());
+
+        let alert = harness.raised_then_cleared();
+        assert_eq!(alert.kind, AlertKind::StuckCarrier);
+        assert_eq!(alert.channel, CHANNEL);
+        assert!(alert.message.contains("300s"), "{}", alert.message);
+    }
+
+    #[test]
+    fn short_transmissions_are_fine() {
+        let mut harness = Harness::new(300, 0);
+
+        for start in 0..10 {
+            let start = start * 100 * SECOND;
+            harness.watchdog.transmission_started(Some(start));
+            harness.watchdog.check_alerts(start + 60 * SECOND);
+            harness.watchdog.transmission_ended();
+        }
+
+        assert!(harness.alerts().is_empty());
+        assert!(harness.events().is_empty());
+    }
+
+    #[test]
+    fn dead_air_after_max_silence() {
+        let mut harness = Harness::new(0, 60);
+        let received = Instant::now();
+
+        // the channel goes quiet 5s in
+        for t in 0..5 {
+            harness.level(t, -30.0, received);
+        }
+        for t in 5..64 {
+            harness.level(t, -90.0, received);
+        }
+        assert!(harness.alerts().is_empty());
+
+        // 60s of silence at the end of this one
+        harness.level(64, -90.0, received);
+        assert_eq!(harness.alerts(), vec![AlertKind::DeadAir]);
+
+        // and someone talks again
+        harness.level(65, -30.0, received);
+        assert!(harness.alerts().is_empty());
+
+        let alert = harness.raised_then_cleared();
+        assert_eq!(alert.kind, AlertKind::DeadAir);
+        assert!(alert.message.contains("60s"), "{}", alert.message);
+    }
+
+    #[test]
+    fn noise_keeps_resetting_the_silence() {
+        let mut harness = Harness::new(0, 60);
+        let received = Instant::now();
+
+        for t in 0..300 {
+            let rms = if t % 50 == 0 { -30.0 } else { -90.0 };
+            harness.level(t, rms, received);
+        }
+
+        assert!(harness.alerts().is_empty());
+    }
+
+    #[test]
+    fn stalled_channels_count_as_silent() {
+        let mut harness = Harness::new(0, 60);
+        let received = Instant::now();
+
+        // loud audio, then the level readings stop arriving altogether
+        harness.level(0, -30.0, received);
+
+        // a short gap is just a slow pipeline
+        harness
+            .watchdog
+            .check(received + STALLED_AFTER - Duration::from_secs(1));
+        assert!(harness.watchdog.silent_since.is_none());
+
+        // ... but after STALLED_AFTER, the silence dates from the last reading
+        harness.watchdog.check(received + STALLED_AFTER);
+        assert_eq!(harness.watchdog.silent_since, Some(SECOND));
+        assert!(harness.alerts().is_empty());
+
+        harness.watchdog.check(received + Duration::from_secs(59));
+        assert!(harness.alerts().is_empty());
+        harness.watchdog.check(received + Duration::from_secs(60));
+        assert_eq!(harness.alerts(), vec![AlertKind::DeadAir]);
+    }
+
+    #[test]
+    fn nothing_is_checked_before_the_first_reading() {
+        let mut harness = Harness::new(300, 60);
+
+        harness
+            .watchdog
+            .check(Instant::now() + Duration::from_secs(3600));
+
+        assert!(harness.alerts().is_empty());
+    }
+
+    #[test]
+    fn exceeded_needs_a_limit() {
+        let max = Some(Duration::from_secs(5));
+
+        assert_eq!(exceeded(0, 5 * SECOND, max), max);
+        assert_eq!(exceeded(SECOND, 5 * SECOND, max), None);
+        assert_eq!(exceeded(0, 1000 * SECOND, None), None);
+        // out of order timestamps never count
+        assert_eq!(exceeded(10 * SECOND, 0, max), None);
+    }
+}
@@ -0,0 +1,44 @@
+//! The *Frontend*, a static web UI which gets embedded into the server
+//! binary so there's nothing else to deploy.
+
+/// A file that makes up the frontend.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Asset {
+    pub content_type: &'static str,
+    pub body: &'static [u8],
+}
+
+const INDEX: Asset = Asset {
+    content_type: "text/html; charset=utf-8",
+    body: include_bytes!("../static/index.html"),
+};
+
+const ASSETS: &[(&str, Asset)] = &[
+    ("index.html", INDEX),
+    (
+        "app.js",
+        Asset {
+            content_type: "application/javascript; charset=utf-8",
+            body: include_bytes!("../static/app.js"),
+        },
+    ),
+    (
+        "style.css",
+        Asset {
+            content_type: "text/css; charset=utf-8",
+            body: include_bytes!("../static/style.css"),
+        },
+    ),
+];
+
+/// Look up the asset for a URL path (without the leading `/`).
+pub fn lookup(path: &str) -> Option<Asset> {
+    if path.is_empty() {
+        return Some(INDEX);
+    }
+
+    ASSETS
+        .iter()
+        .find(|(name, _)| *name == path)
+        .map(|(_, asset)| *asset)
+}
@@ -0,0 +1,44 @@
+use serde::Serialize;
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Arc, Mutex,
+};
+
+/// Fans events out to everyone listening on the `/api/events` endpoint.
+///
+/// Cloning a [`Broadcaster`] gives you another handle to the same set of
+/// subscribers.
+#[derive(Debug, Default, Clone)]
+pub struct Broadcaster {
+    subscribers: Arc<Mutex<Vec<Sender<Arc<str>>>>>,
+}
+
+impl Broadcaster {
+    pub fn new() -> Broadcaster {
+        Broadcaster::default()
+    }
+
+    /// Send an event to every subscriber as JSON.
+    pub fn publish<T: Serialize>(&self, event: &T) {
+        let json: Arc<str> = match serde_json::to_string(event) {
+            Ok(json) => json.into(),
+            Err(e) => {
+                log::error!("Unable to serialize the event: {}", e);
+                return;
+            },
+        };
+
+        // subscribers who have gone away get dropped
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(Arc::clone(&json)).is_ok());
+    }
+
+    /// Start receiving events.
+    pub fn subscribe(&self) -> Receiver<Arc<str>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+}
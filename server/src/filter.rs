@@ -0,0 +1,64 @@
+use chrono::{DateTime, Utc};
+use storage::Query;
+use url::form_urlencoded;
+
+/// The query parameters used to narrow down a list of transmissions.
+///
+/// - `channel` - only include transmissions from this channel
+/// - `from` - only include transmissions starting at or after this time
+///   (RFC 3339)
+/// - `to` - only include transmissions starting before this time (RFC 3339)
+/// - `q` - only include transmissions whose transcript contains every word
+/// - `limit` - return at most this many transmissions (the most recent ones)
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Filter {
+    pub channel: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub text: Option<String>,
+    pub limit: Option<usize>,
+}
+
+impl Filter {
+    /// Parse a [`Filter`] from a URL's query string.
+    pub fn from_query(query: &str) -> Result<Filter, String> {
+        let mut filter = Filter::default();
+
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "channel" => filter.channel = Some(value.into_owned()),
+                "from" => filter.from = Some(parse_timestamp(&value)?),
+                "to" => filter.to = Some(parse_timestamp(&value)?),
+                "q" if !value.trim().is_empty() => {
+                    filter.text = Some(value.into_owned())
+                },
+                "limit" => {
+                    let limit = value.parse().map_err(|_| {
+                        format!("\"{}\" isn't a valid limit", value)
+                    })?;
+                    filter.limit = Some(limit);
+                },
+                _ => {},
+            }
+        }
+
+        Ok(filter)
+    }
+
+    /// The equivalent database [`Query`].
+    pub fn to_query(&self) -> Query {
+        Query {
+            channel: self.channel.clone(),
+            from: self.from,
+            to: self.to,
+            text: self.text.clone(),
+            limit: self.limit,
+        }
+    }
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, String> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .map_err(|e| format!("\"{}\" isn't a valid timestamp: {}", value, e))
+}
@@ -0,0 +1,550 @@
+//! A HTTP server which lets the *Frontend* browse received transmissions.
+//!
+//! The frontend itself is embedded in the binary and served from `/`.
+//!
+//! # Endpoints
+//!
+//! - `GET /api/channels` - the names of every channel with a transmission
+//! - `GET /api/transmissions` - a list of transmissions, optionally filtered
+//!   or searched (see [`Filter`] for the query parameters)
+//! - `GET /api/transmissions/{id}` - a single transmission
+//! - `GET /api/transmissions/{id}/transcript` - a transmission's transcript
+//! - `GET /api/transmissions/{id}/audio` - the recorded audio (supports HTTP
+//!   range requests)
+//! - `GET /api/status` - the latest signal level measured on each channel
+//!   and any active alerts (only available when the server runs inside the
+//!   receiver)
+//! - `GET /api/events` - a stream of [`transmission::Event`]s, using
+//!   [Server-Sent Events][sse] (events are only sent when the server runs
+//!   inside the receiver, otherwise the stream just stays idle)
+//!
+//! [sse]: https://developer.mozilla.org/en-US/docs/Web/API/Server-sent_events
+
+mod assets;
+mod events;
+mod filter;
+mod range;
+mod status;
+
+pub use events::Broadcaster;
+pub use filter::Filter;
+pub use range::{parse_range, ByteRange};
+pub use status::{ChannelStatus, Level, Status, StatusReport};
+
+use serde::Serialize;
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
+    net::ToSocketAddrs,
+    path::Path,
+    sync::{mpsc::RecvTimeoutError, Arc},
+    thread,
+    time::Duration,
+};
+use storage::Database;
+use tiny_http::{Header, Method, Request, Response, ResponseBox, StatusCode};
+use transmission::Transmission;
+use uuid::Uuid;
+
+#[derive(Debug, Clone)]
+pub struct Server {
+    db: Database,
+    events: Broadcaster,
+    status: Status,
+}
+
+/// How often to send something down an idle event stream so we notice when
+/// the client goes away.
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+impl Server {
+    pub fn new(db: Database) -> Server {
+        Server {
+            db,
+            events: Broadcaster::new(),
+            status: Status::new(),
+        }
+    }
+
+    /// A handle which can be used to push events to connected clients.
+    pub fn events(&self) -> Broadcaster {
+        self.events.clone()
+    }
+
+    /// A handle which can be used to update the health information served
+    /// from `/api/status`.
+    pub fn status(&self) -> Status {
+        self.status.clone()
+    }
+
+    /// Listen for requests on `address`, handling each one on its own thread.
+    ///
+    /// This only returns if the server couldn't be started.
+    pub fn serve<A: ToSocketAddrs>(
+        self,
+        address: A,
+    ) -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+        let http = tiny_http::Server::http(address)?;
+        let server = Arc::new(self);
+
+        for request in http.incoming_requests() {
+            let server = Arc::clone(&server);
+            thread::spawn(move || server.respond(request));
+        }
+
+        Ok(())
+    }
+
+    fn respond(&self, request: Request) {
+        let path = request.url().split('?').next().unwrap_or_default();
+        if *request.method() == Method::Get && path == "/api/events" {
+            self.stream_events(request);
+            return;
+        }
+
+        let range = request
+            .headers()
+            .iter()
+            .find(|h| h.field.equiv("Range"))
+            .map(|h| h.value.as_str().to_string());
+        let response =
+            self.route(request.method(), request.url(), range.as_deref());
+
+        log::debug!("{} {}", request.method(), request.url());
+
+        if let Err(e) = request.respond(response) {
+            // normally just the browser hanging up on us
+            log::debug!("Unable to send the response: {}", e);
+        }
+    }
+
+    /// Keep the connection open, sending each event to the client as it
+    /// happens.
+    fn stream_events(&self, request: Request) {
+        let events = self.events.subscribe();
+        // tiny_http buffers chunked responses, so we need to write the
+        // response ourselves to make sure each event is sent immediately
+        let mut writer = request.into_writer();
+
+        let result = (|| -> io::Result<()> {
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\n\
+                 Content-Type: text/event-stream\r\n\
+                 Cache-Control: no-cache\r\n\
+                 Connection: close\r\n\r\n"
+            )?;
+            writer.flush()?;
+
+            loop {
+                match events.recv_timeout(KEEP_ALIVE_INTERVAL) {
+                    Ok(event) => write!(writer, "data: {}\n\n", event)?,
+                    Err(RecvTimeoutError::Timeout) => {
+                        write!(writer, ": keep-alive\n\n")?
+                    },
+                    Err(RecvTimeoutError::Disconnected) => return Ok(()),
+                }
+                writer.flush()?;
+            }
+        })();
+
+        if let Err(e) = result {
+            log::debug!("Event stream closed: {}", e);
+        }
+    }
+
+    /// Figure out how to respond to a request.
+    pub fn route(
+        &self,
+        method: &Method,
+        url: &str,
+        range: Option<&str>,
+    ) -> ResponseBox {
+        if *method != Method::Get && *method != Method::Head {
+            return error(405, "Only GET requests are supported");
+        }
+
+        let (path, query) = match url.find('?') {
+            Some(ix) => (&url[..ix], &url[ix + 1..]),
+            None => (url, ""),
+        };
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+
+        match segments.as_slice() {
+            ["api", "channels"] => self.channels(),
+            ["api", "status"] => json(200, &self.status.snapshot()),
+            ["api", "transmissions"] => self.transmissions(query),
+            ["api", "transmissions", id] => {
+                self.with_transmission(id, |t| json(200, &t))
+            },
+            ["api", "transmissions", id, "transcript"] => self
+                .with_transmission(id, |t| {
+                    json(
+                        200,
+                        &TranscriptResponse {
+                            id: t.id,
+                            transcript: t.transcript,
+                        },
+                    )
+                }),
+            ["api", "transmissions", id, "audio"] => {
+                self.with_transmission(id, |t| audio(&t.audio, range))
+            },
+            [name] => match assets::lookup(name) {
+                Some(asset) => Response::from_data(asset.body)
+                    .with_header(header("Content-Type", asset.content_type))
+                    .boxed(),
+                None => error(404, "Not found"),
+            },
+            _ => error(404, "Not found"),
+        }
+    }
+
+    fn channels(&self) -> ResponseBox {
+        match self.db.channels() {
+            Ok(channels) => json(200, &channels),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    fn transmissions(&self, query: &str) -> ResponseBox {
+        let filter = match Filter::from_query(query) {
+            Ok(f) => f,
+            Err(msg) => return error(400, &msg),
+        };
+
+        match self.db.find(&filter.to_query()) {
+            Ok(transmissions) => json(200, &transmissions),
+            Err(e) => internal_error(e),
+        }
+    }
+
+    fn with_transmission<F>(&self, id: &str, then: F) -> ResponseBox
+    where
+        F: FnOnce(Transmission) -> ResponseBox,
+    {
+        let id = match Uuid::parse_str(id) {
+            Ok(id) => id,
+            Err(_) => return error(404, "Not found"),
+        };
+
+        match self.db.load(id) {
+            Ok(Some(transmission)) => then(transmission),
+            Ok(None) => error(404, "Not found"),
+            Err(e) => internal_error(e),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct TranscriptResponse {
+    id: Uuid,
+    transcript: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorResponse<'a> {
+    error: &'a str,
+}
+
+fn header(field: &str, value: &str) -> Header {
+    Header::from_bytes(field.as_bytes(), value.as_bytes()).unwrap()
+}
+
+fn json<T: Serialize>(status: u16, value: &T) -> ResponseBox {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::from_data(body)
+            .with_status_code(status)
+            .with_header(header("Content-Type", "application/json"))
+            .boxed(),
+        Err(e) => internal_error(e),
+    }
+}
+
+fn error(status: u16, msg: &str) -> ResponseBox {
+    let body =
+        serde_json::to_vec(&ErrorResponse { error: msg }).unwrap_or_default();
+
+    Response::from_data(body)
+        .with_status_code(status)
+        .with_header(header("Content-Type", "application/json"))
+        .boxed()
+}
+
+fn internal_error<E: Error>(e: E) -> ResponseBox {
+    log::error!("{}", e);
+    error(500, "Internal server error")
+}
+
+fn content_type(path: &Path) -> &'static str {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("wav") => "audio/wav",
+        Some("flac") => "audio/flac",
+        Some("ogg") => "audio/ogg",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Send back the audio file, honouring any `Range` header.
+fn audio(path: &Path, range: Option<&str>) -> ResponseBox {
+    match send_audio(path, range) {
+        Ok(response) => response,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            error(404, "The audio for this transmission is missing")
+        },
+        Err(e) => internal_error(e),
+    }
+}
+
+fn send_audio(path: &Path, range: Option<&str>) -> io::Result<ResponseBox> {
+    let mut f = File::open(path)?;
+    let length = f.metadata()?.len();
+
+    let mut headers = vec![
+        header("Content-Type", content_type(path)),
+        header("Accept-Ranges", "bytes"),
+    ];
+
+    let range = match range.map(|r| parse_range(r, length)) {
+        Some(ByteRange::Partial(r)) => r,
+        Some(ByteRange::Unsatisfiable) => {
+            let response = Response::new(
+                StatusCode(416),
+                vec![header("Content-Range", &format!("bytes */{}", length))],
+                Box::new(Cursor::new(Vec::new())) as Box<dyn Read + Send>,
+                Some(0),
+                None,
+            );
+            return Ok(response);
+        },
+        // a range we can't make sense of gets the whole file
+        Some(ByteRange::Ignored) | None => {
+            return Ok(Response::new(
+                StatusCode(200),
+                headers,
+                Box::new(f),
+                Some(length as usize),
+                None,
+            ));
+        },
+    };
+
+    f.seek(SeekFrom::Start(range.start))?;
+    let len = range.end - range.start;
+    headers.push(header(
+        "Content-Range",
+        &format!("bytes {}-{}/{}", range.start, range.end - 1, length),
+    ));
+
+    Ok(Response::new(
+        StatusCode(206),
+        headers,
+        Box::new(f.take(len)),
+        Some(len as usize),
+        None,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+    use tempfile::TempDir;
+
+    fn transmission(channel: &str, minute: u32, audio: &Path) -> Transmission {
+        Transmission::new(
+            channel,
+            minute,
+            "2020-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+                + chrono::Duration::minutes(i64::from(minute)),
+            Duration::from_secs(u64::from(minute) * 60),
+            Duration::from_secs(5),
+            audio,
+        )
+    }
+
+    /// A server with a few transmissions, the first of which has some audio.
+    fn server() -> (Server, Vec<Transmission>, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let audio = dir.path().join("first.wav");
+        std::fs::write(&audio, (0..100).collect::<Vec<u8>>()).unwrap();
+
+        let mut transmissions = vec![
+            transmission("fire", 0, &audio),
+            transmission("police", 1, &dir.path().join("missing.wav")),
+            transmission("fire", 2, &dir.path().join("missing.wav")),
+        ];
+        transmissions[0].transcript = Some("Structure fire on Main St".into());
+        transmissions[2].transcript = Some("Engine 12 responding".into());
+
+        let db = Database::in_memory().unwrap();
+        for t in &transmissions {
+            db.save(t).unwrap();
+        }
+
+        (Server::new(db), transmissions, dir)
+    }
+
+    fn get(
+        server: &Server,
+        url: &str,
+        range: Option<&str>,
+    ) -> (u16, Vec<Header>, Vec<u8>) {
+        let response = server.route(&Method::Get, url, range);
+        let status = response.status_code().0;
+        let headers = response.headers().to_vec();
+        let mut body = Vec::new();
+        response.into_reader().read_to_end(&mut body).unwrap();
+
+        (status, headers, body)
+    }
+
+    fn get_header(headers: &[Header], field: &'static str) -> Option<String> {
+        headers
+            .iter()
+            .find(|h| h.field.equiv(field))
+            .map(|h| h.value.as_str().to_string())
+    }
+
+    /// The sequence numbers of the transmissions at `url`.
+    fn sequences(server: &Server, url: &str) -> Vec<u32> {
+        let (status, _, body) = get(server, url, None);
+        assert_eq!(status, 200, "{}", String::from_utf8_lossy(&body));
+        let got: Vec<Transmission> = serde_json::from_slice(&body).unwrap();
+
+        got.into_iter().map(|t| t.sequence).collect()
+    }
+
+    #[test]
+    fn list_every_transmission() {
+        let (server, _, _dir) = server();
+
+        assert_eq!(sequences(&server, "/api/transmissions"), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn filter_transmissions() {
+        let (server, _, _dir) = server();
+
+        assert_eq!(
+            sequences(&server, "/api/transmissions?channel=fire"),
+            vec![0, 2]
+        );
+        assert_eq!(
+            sequences(
+                &server,
+                "/api/transmissions?from=2020-01-01T12:01:00Z&to=2020-01-01T12:02:00Z"
+            ),
+            vec![1]
+        );
+        assert_eq!(
+            sequences(&server, "/api/transmissions?limit=2"),
+            vec![1, 2]
+        );
+        assert_eq!(
+            sequences(&server, "/api/transmissions?q=structure+fire"),
+            vec![0]
+        );
+    }
+
+    #[test]
+    fn bad_filters_are_rejected() {
+        let (server, _, _dir) = server();
+
+        let (status, _, _) =
+            get(&server, "/api/transmissions?limit=lots", None);
+        assert_eq!(status, 400);
+        let (status, _, _) =
+            get(&server, "/api/transmissions?from=today", None);
+        assert_eq!(status, 400);
+    }
+
+    #[test]
+    fn list_channels() {
+        let (server, _, _dir) = server();
+
+        let (status, _, body) = get(&server, "/api/channels", None);
+
+        assert_eq!(status, 200);
+        let channels: Vec<String> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(channels, vec!["fire", "police"]);
+    }
+
+    #[test]
+    fn unknown_transmissions_are_not_found() {
+        let (server, _, _dir) = server();
+
+        let (status, _, _) = get(&server, "/api/transmissions/nope", None);
+        assert_eq!(status, 404);
+        let url = format!("/api/transmissions/{}", Uuid::new_v4());
+        let (status, _, _) = get(&server, &url, None);
+        assert_eq!(status, 404);
+    }
+
+    #[test]
+    fn serve_the_whole_audio_file() {
+        let (server, transmissions, _dir) = server();
+        let url = format!("/api/transmissions/{}/audio", transmissions[0].id);
+
+        let (status, headers, body) = get(&server, &url, None);
+
+        assert_eq!(status, 200);
+        assert_eq!(body, (0..100).collect::<Vec<u8>>());
+        assert_eq!(get_header(&headers, "Content-Type").unwrap(), "audio/wav");
+        assert_eq!(get_header(&headers, "Accept-Ranges").unwrap(), "bytes");
+    }
+
+    #[test]
+    fn serve_part_of_the_audio_file() {
+        let (server, transmissions, _dir) = server();
+        let url = format!("/api/transmissions/{}/audio", transmissions[0].id);
+
+        let (status, headers, body) = get(&server, &url, Some("bytes=10-19"));
+
+        assert_eq!(status, 206);
+        assert_eq!(body, (10..20).collect::<Vec<u8>>());
+        assert_eq!(
+            get_header(&headers, "Content-Range").unwrap(),
+            "bytes 10-19/100"
+        );
+    }
+
+    #[test]
+    fn unusable_ranges_get_the_whole_audio_file() {
+        let (server, transmissions, _dir) = server();
+        let url = format!("/api/transmissions/{}/audio", transmissions[0].id);
+
+        for range in &["bytes=0-1,5-6", "bytes=oops", "seconds=1-2"] {
+            let (status, _, body) = get(&server, &url, Some(range));
+
+            assert_eq!(status, 200, "{}", range);
+            assert_eq!(body.len(), 100, "{}", range);
+        }
+    }
+
+    #[test]
+    fn ranges_past_the_end_are_unsatisfiable() {
+        let (server, transmissions, _dir) = server();
+        let url = format!("/api/transmissions/{}/audio", transmissions[0].id);
+
+        let (status, headers, body) = get(&server, &url, Some("bytes=100-"));
+
+        assert_eq!(status, 416);
+        assert!(body.is_empty());
+        assert_eq!(
+            get_header(&headers, "Content-Range").unwrap(),
+            "bytes */100"
+        );
+    }
+
+    #[test]
+    fn missing_audio_is_not_found() {
+        let (server, transmissions, _dir) = server();
+        let url = format!("/api/transmissions/{}/audio", transmissions[1].id);
+
+        let (status, _, _) = get(&server, &url, None);
+
+        assert_eq!(status, 404);
+    }
+}
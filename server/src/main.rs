@@ -0,0 +1,50 @@
+//! Serve up the transmissions recorded by `transcribe-receiver`.
+//!
+//! Usage: `transcribe-server <recordings-dir> [address]`
+//!
+//! The standalone server only reads from the database, so nothing is ever
+//! published on `/api/events` and `/api/status` stays empty. Use the
+//! receiver's `[server]` section to get live updates.
+
+use std::{path::PathBuf, process};
+use storage::Database;
+use transcribe_server::Server;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1:8080";
+
+fn main() {
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or("info"),
+    )
+    .init();
+
+    let mut args = std::env::args().skip(1);
+    let recordings = args
+        .next()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("recordings"));
+    let address = args.next().unwrap_or_else(|| DEFAULT_ADDRESS.to_string());
+
+    let db = match Database::open(recordings.join("transmissions.db")) {
+        Ok(db) => db,
+        Err(e) => {
+            log::error!(
+                "Unable to open the recordings in \"{}\": {}",
+                recordings.display(),
+                e
+            );
+            process::exit(1);
+        },
+    };
+
+    log::info!("Listening on http://{}/", address);
+    log::info!(
+        "Live events and status are only available from the server built \
+         into transcribe-receiver"
+    );
+
+    if let Err(e) = Server::new(db).serve(&address) {
+        log::error!("Unable to start the server: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,169 @@
+use std::ops::Range;
+
+/// What a HTTP `Range` header asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ByteRange {
+    /// The header can't be used (it's malformed, uses a unit other than
+    /// `bytes`, or asks for several ranges at once), so it should be ignored
+    /// and the whole resource sent back.
+    Ignored,
+    /// Send back just this part of the resource.
+    Partial(Range<u64>),
+    /// The range is valid, but lies entirely outside the resource.
+    Unsatisfiable,
+}
+
+/// Parse the value of a HTTP `Range` header for a resource `length` bytes
+/// long, following [RFC 7233][rfc].
+///
+/// Only single byte ranges are supported (which is all browsers ask for when
+/// playing audio).
+///
+/// [rfc]: https://tools.ietf.org/html/rfc7233#section-2.1
+pub fn parse_range(header: &str, length: u64) -> ByteRange {
+    let header = header.trim();
+    if !header.starts_with("bytes=") {
+        return ByteRange::Ignored;
+    }
+    let spec = &header["bytes=".len()..];
+
+    // there's nothing to take a part of
+    if spec.contains(',') || length == 0 {
+        return ByteRange::Ignored;
+    }
+
+    let dash = match spec.find('-') {
+        Some(dash) => dash,
+        None => return ByteRange::Ignored,
+    };
+    let (start, end) = (spec[..dash].trim(), spec[dash + 1..].trim());
+    let (start, end) = match (parse_position(start), parse_position(end)) {
+        (Ok(start), Ok(end)) => (start, end),
+        _ => return ByteRange::Ignored,
+    };
+
+    match (start, end) {
+        // "bytes=-500" means the last 500 bytes
+        (None, Some(0)) => ByteRange::Unsatisfiable,
+        (None, Some(suffix)) => {
+            ByteRange::Partial(length.saturating_sub(suffix)..length)
+        },
+        // "bytes=500-" means everything from byte 500 onwards
+        (Some(start), None) if start < length => {
+            ByteRange::Partial(start..length)
+        },
+        // "bytes=500-999" is inclusive
+        (Some(start), Some(end)) if end < start => ByteRange::Ignored,
+        (Some(start), Some(end)) if start < length => ByteRange::Partial(
+            start..std::cmp::min(end.saturating_add(1), length),
+        ),
+        (Some(_), _) => ByteRange::Unsatisfiable,
+        (None, None) => ByteRange::Ignored,
+    }
+}
+
+/// Parse one end of a byte range, where an empty string means it was left
+/// out.
+fn parse_position(position: &str) -> Result<Option<u64>, ()> {
+    if position.is_empty() {
+        Ok(None)
+    } else if position.bytes().all(|b| b.is_ascii_digit()) {
+        position.parse().map(Some).map_err(|_| ())
+    } else {
+        Err(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn suffix_ranges_count_back_from_the_end() {
+        assert_eq!(
+            parse_range("bytes=-500", 1000),
+            ByteRange::Partial(500..1000)
+        );
+        assert_eq!(
+            parse_range("bytes=-5000", 1000),
+            ByteRange::Partial(0..1000)
+        );
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges_go_to_the_end() {
+        assert_eq!(
+            parse_range("bytes=500-", 1000),
+            ByteRange::Partial(500..1000)
+        );
+        assert_eq!(parse_range("bytes=0-", 1000), ByteRange::Partial(0..1000));
+    }
+
+    #[test]
+    fn ranges_include_the_last_byte() {
+        assert_eq!(parse_range("bytes=0-0", 1000), ByteRange::Partial(0..1));
+        assert_eq!(
+            parse_range("bytes=500-999", 1000),
+            ByteRange::Partial(500..1000)
+        );
+        assert_eq!(
+            parse_range(" bytes=100-199 ", 1000),
+            ByteRange::Partial(100..200)
+        );
+    }
+
+    #[test]
+    fn ranges_running_past_the_end_are_cut_short() {
+        assert_eq!(
+            parse_range("bytes=500-5000", 1000),
+            ByteRange::Partial(500..1000)
+        );
+    }
+
+    #[test]
+    fn ranges_starting_past_the_end_are_unsatisfiable() {
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(
+            parse_range("bytes=1000-1999", 1000),
+            ByteRange::Unsatisfiable
+        );
+    }
+
+    #[test]
+    fn malformed_headers_are_ignored() {
+        let headers = &[
+            "",
+            "bytes=",
+            "bytes=-",
+            "bytes=500",
+            "bytes=abc-def",
+            "bytes=+1-2",
+            "bytes=-+5",
+            "bytes=999-500",
+            "bytes=99999999999999999999999-",
+            "items=0-10",
+            "0-10",
+        ];
+
+        for header in headers {
+            assert_eq!(
+                parse_range(header, 1000),
+                ByteRange::Ignored,
+                "{:?}",
+                header
+            );
+        }
+    }
+
+    #[test]
+    fn multiple_ranges_are_ignored() {
+        assert_eq!(parse_range("bytes=0-10,20-30", 1000), ByteRange::Ignored);
+    }
+
+    #[test]
+    fn ranges_over_an_empty_file_are_ignored() {
+        assert_eq!(parse_range("bytes=0-", 0), ByteRange::Ignored);
+        assert_eq!(parse_range("bytes=-10", 0), ByteRange::Ignored);
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+};
+use transmission::{Alert, AlertKind};
+
+/// The health of each channel the receiver is listening to, as served from
+/// the `/api/status` endpoint.
+///
+/// Cloning a [`Status`] gives you another handle to the same information.
+#[derive(Debug, Default, Clone)]
+pub struct Status {
+    channels: Arc<Mutex<BTreeMap<String, ChannelStatus>>>,
+}
+
+impl Status {
+    pub fn new() -> Status {
+        Status::default()
+    }
+
+    /// Make sure a channel is listed, even if nothing has been heard from it
+    /// yet.
+    pub fn add_channel(&self, channel: &str) {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(channel.to_string())
+            .or_default();
+    }
+
+    /// Record the most recent signal level measured on a channel.
+    pub fn update_level(&self, channel: &str, level: Level) {
+        self.channels
+            .lock()
+            .unwrap()
+            .entry(channel.to_string())
+            .or_default()
+            .level = Some(level);
+    }
+
+    /// Add an alert to a channel, replacing any other alert of the same kind.
+    pub fn raise_alert(&self, alert: Alert) {
+        let mut channels = self.channels.lock().unwrap();
+        let alerts =
+            &mut channels.entry(alert.channel.clone()).or_default().alerts;
+
+        alerts.retain(|a| a.kind != alert.kind);
+        alerts.push(alert);
+    }
+
+    /// Remove a channel's alert of a particular kind, if it has one.
+    pub fn clear_alert(&self, channel: &str, kind: AlertKind) {
+        if let Some(status) = self.channels.lock().unwrap().get_mut(channel) {
+            status.alerts.retain(|a| a.kind != kind);
+        }
+    }
+
+    /// Get a copy of every channel's current status.
+    pub fn snapshot(&self) -> StatusReport {
+        StatusReport {
+            channels: self.channels.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Everything we know about the receiver's channels at a point in time.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StatusReport {
+    pub channels: BTreeMap<String, ChannelStatus>,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct ChannelStatus {
+    /// The last signal level measured, if there has been one.
+    pub level: Option<Level>,
+    /// Any problems which are currently affecting the channel.
+    pub alerts: Vec<Alert>,
+}
+
+/// A signal level measurement.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize)]
+pub struct Level {
+    /// The wall-clock time the measurement was received.
+    pub measured: DateTime<Utc>,
+    /// The RMS signal level, in dBFS.
+    pub rms: f64,
+    /// The loudest sample, in dBFS.
+    pub peak: f64,
+    /// How many samples were clipped.
+    pub clipped: u32,
+}
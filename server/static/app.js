@@ -0,0 +1,362 @@
+// The frontend for transcribe-server. Deliberately written as plain
+// JavaScript with no build step so it can be embedded in the server binary.
+"use strict";
+
+const state = {
+  transmissions: [],
+  channels: [],
+  selected: null,
+  filter: { q: "", channel: "", window: 24 },
+  samples: null,
+  waveform: null,
+};
+
+const elements = {
+  search: document.getElementById("search"),
+  channel: document.querySelector("#search select[name=channel]"),
+  status: document.getElementById("status"),
+  timeline: document.getElementById("timeline"),
+  player: document.getElementById("player"),
+  playerTitle: document.getElementById("player-title"),
+  playerMeta: document.getElementById("player-meta"),
+  playerTranscript: document.getElementById("player-transcript"),
+  waveform: document.getElementById("waveform"),
+  audio: document.getElementById("audio"),
+  rows: document.querySelector("#transmissions tbody"),
+  empty: document.getElementById("empty"),
+};
+
+// ---- Talking to the server ----
+
+async function getJson(url) {
+  const response = await fetch(url);
+  if (!response.ok) {
+    throw new Error(`${url} returned ${response.status}`);
+  }
+  return response.json();
+}
+
+function timeWindow() {
+  const to = new Date();
+  const from = new Date(to.getTime() - state.filter.window * 60 * 60 * 1000);
+  return { from, to };
+}
+
+async function refresh() {
+  const { from } = timeWindow();
+  const params = new URLSearchParams({ from: from.toISOString(), limit: "1000" });
+  if (state.filter.q) params.set("q", state.filter.q);
+  if (state.filter.channel) params.set("channel", state.filter.channel);
+
+  try {
+    const [channels, transmissions] = await Promise.all([
+      getJson("/api/channels"),
+      getJson(`/api/transmissions?${params}`),
+    ]);
+    state.channels = channels;
+    state.transmissions = transmissions;
+  } catch (e) {
+    console.error(e);
+    setStatus("Unable to load transmissions", false);
+  }
+
+  render();
+}
+
+function matchesFilter(transmission) {
+  if (state.filter.channel && transmission.channel !== state.filter.channel) {
+    return false;
+  }
+  if (state.filter.q) {
+    // a rough approximation of the server's full-text search, good enough
+    // for deciding whether a live update belongs in the search results
+    const transcript = (transmission.transcript || "").toLowerCase();
+    return state.filter.q
+      .toLowerCase()
+      .split(/\s+/)
+      .every((word) => transcript.includes(word));
+  }
+  return true;
+}
+
+function upsert(transmission) {
+  const index = state.transmissions.findIndex((t) => t.id === transmission.id);
+
+  if (!matchesFilter(transmission)) {
+    if (index >= 0) state.transmissions.splice(index, 1);
+  } else if (index >= 0) {
+    state.transmissions[index] = transmission;
+  } else {
+    state.transmissions.push(transmission);
+  }
+
+  if (!state.channels.includes(transmission.channel)) {
+    state.channels.push(transmission.channel);
+    state.channels.sort();
+  }
+  if (state.selected && state.selected.id === transmission.id) {
+    state.selected = transmission;
+    renderPlayerDetails();
+  }
+
+  render();
+}
+
+function listen() {
+  const events = new EventSource("/api/events");
+
+  events.onopen = () => setStatus("Live", true);
+  events.onerror = () => setStatus("Reconnecting…", false);
+  events.onmessage = (msg) => {
+    const event = JSON.parse(msg.data);
+
+    switch (event.type) {
+      case "transmission-started":
+        setStatus(`Receiving on ${event.channel}…`, true);
+        break;
+      case "transmission-recorded":
+      case "transcription-finished":
+        setStatus("Live", true);
+        upsert(event.transmission);
+        break;
+    }
+  };
+}
+
+function setStatus(text, live) {
+  elements.status.textContent = text;
+  elements.status.classList.toggle("live", live);
+}
+
+// ---- Rendering ----
+
+function render() {
+  renderChannelOptions();
+  renderTimeline();
+  renderTable();
+}
+
+function renderChannelOptions() {
+  const select = elements.channel;
+  const current = select.value;
+
+  while (select.options.length > 1) select.remove(1);
+  for (const channel of state.channels) {
+    select.add(new Option(channel, channel));
+  }
+  select.value = current;
+}
+
+function renderTimeline() {
+  const { from, to } = timeWindow();
+  const span = to - from;
+  const channels = state.filter.channel ? [state.filter.channel] : state.channels;
+
+  elements.timeline.replaceChildren();
+
+  for (const channel of channels) {
+    const lane = document.createElement("div");
+    lane.className = "lane";
+
+    const name = document.createElement("span");
+    name.className = "lane-name";
+    name.textContent = channel;
+    name.title = channel;
+
+    const track = document.createElement("div");
+    track.className = "lane-track";
+
+    for (const t of state.transmissions) {
+      if (t.channel !== channel) continue;
+
+      const start = new Date(t.start) - from;
+      if (start < 0) continue;
+
+      const block = document.createElement("div");
+      block.className = "transmission";
+      block.classList.toggle("selected", isSelected(t));
+      block.classList.toggle("emergency", Boolean(t.emergency));
+      block.style.left = `${(100 * start) / span}%`;
+      block.style.width = `${(100 * t.duration * 1000) / span}%`;
+      block.title = `${formatTime(t.start)} (${formatDuration(t.duration)})` +
+        (t.transcript ? `\n${t.transcript}` : "");
+      block.addEventListener("click", () => select(t));
+      track.appendChild(block);
+    }
+
+    lane.append(name, track);
+    elements.timeline.appendChild(lane);
+  }
+
+  const axis = document.createElement("div");
+  axis.className = "axis meta";
+  for (let i = 0; i <= 4; i++) {
+    const label = document.createElement("span");
+    label.textContent = formatTime(new Date(from.getTime() + (span * i) / 4));
+    axis.appendChild(label);
+  }
+  elements.timeline.appendChild(axis);
+}
+
+function renderTable() {
+  elements.rows.replaceChildren();
+  elements.empty.hidden = state.transmissions.length > 0;
+
+  // newest first
+  const transmissions = [...state.transmissions].sort(
+    (a, b) => new Date(b.start) - new Date(a.start),
+  );
+
+  for (const t of transmissions) {
+    const row = elements.rows.insertRow();
+    row.classList.toggle("selected", isSelected(t));
+    row.classList.toggle("pending", !t.transcript);
+    row.classList.toggle("emergency", Boolean(t.emergency));
+    row.addEventListener("click", () => select(t));
+
+    row.insertCell().textContent = formatTime(t.start);
+    row.insertCell().textContent = t.channel;
+    row.insertCell().textContent = formatDuration(t.duration);
+    row.insertCell().textContent = t.transcript || "(no transcript yet)";
+  }
+}
+
+function isSelected(transmission) {
+  return state.selected !== null && state.selected.id === transmission.id;
+}
+
+// ---- Playback ----
+
+function select(transmission) {
+  state.selected = transmission;
+  state.samples = null;
+  state.waveform = null;
+
+  elements.player.hidden = false;
+  elements.audio.src = `/api/transmissions/${transmission.id}/audio`;
+  elements.audio.play().catch(() => {});
+
+  renderPlayerDetails();
+  render();
+  drawWaveform();
+  loadWaveform(transmission);
+}
+
+function renderPlayerDetails() {
+  const t = state.selected;
+
+  elements.playerTitle.textContent = `${t.channel} #${t.sequence}`;
+  elements.playerMeta.textContent =
+    `${formatTime(t.start)}, ${formatDuration(t.duration)}, ` +
+    `peak ${t.peak.toFixed(1)} dBFS` +
+    (t.ctcss ? `, CTCSS ${t.ctcss.toFixed(1)} Hz` : "") +
+    (t.dcs ? `, DCS ${t.dcs}` : "") +
+    (t.unit ? `, unit ${t.unit}` : "") +
+    (t.emergency ? ", EMERGENCY" : "");
+  elements.playerTranscript.textContent =
+    t.transcript || "This transmission hasn't been transcribed yet.";
+}
+
+async function loadWaveform(transmission) {
+  try {
+    const response = await fetch(`/api/transmissions/${transmission.id}/audio`);
+    if (!response.ok) throw new Error(`Unable to fetch the audio (${response.status})`);
+
+    const context = new (window.AudioContext || window.webkitAudioContext)();
+    const buffer = await context.decodeAudioData(await response.arrayBuffer());
+    context.close();
+
+    if (isSelected(transmission)) {
+      state.samples = buffer.getChannelData(0);
+      state.waveform = peaks(state.samples, elements.waveform.clientWidth);
+      drawWaveform();
+    }
+  } catch (e) {
+    console.error(e);
+  }
+}
+
+// Reduce the samples to one peak per pixel.
+function peaks(samples, width) {
+  const buckets = new Float32Array(Math.max(width, 1));
+  const size = samples.length / buckets.length;
+
+  for (let i = 0; i < samples.length; i++) {
+    const bucket = Math.floor(i / size);
+    buckets[bucket] = Math.max(buckets[bucket], Math.abs(samples[i]));
+  }
+
+  return buckets;
+}
+
+function drawWaveform() {
+  const canvas = elements.waveform;
+  canvas.width = canvas.clientWidth;
+  const ctx = canvas.getContext("2d");
+  const middle = canvas.height / 2;
+
+  ctx.clearRect(0, 0, canvas.width, canvas.height);
+  if (!state.waveform) return;
+
+  const style = getComputedStyle(document.documentElement);
+  const played = elements.audio.duration
+    ? (elements.audio.currentTime / elements.audio.duration) * canvas.width
+    : 0;
+
+  state.waveform.forEach((peak, x) => {
+    ctx.fillStyle = x < played
+      ? style.getPropertyValue("--accent")
+      : style.getPropertyValue("--muted");
+    const height = Math.max(peak * middle, 0.5);
+    ctx.fillRect(x, middle - height, 1, height * 2);
+  });
+}
+
+elements.audio.addEventListener("timeupdate", drawWaveform);
+elements.waveform.addEventListener("click", (e) => {
+  if (!elements.audio.duration) return;
+  const fraction = e.offsetX / elements.waveform.clientWidth;
+  elements.audio.currentTime = fraction * elements.audio.duration;
+});
+window.addEventListener("resize", () => {
+  if (!state.samples) return;
+  state.waveform = peaks(state.samples, elements.waveform.clientWidth);
+  drawWaveform();
+});
+
+// ---- Formatting ----
+
+function formatTime(timestamp) {
+  return new Date(timestamp).toLocaleString(undefined, {
+    month: "short",
+    day: "numeric",
+    hour: "2-digit",
+    minute: "2-digit",
+    second: "2-digit",
+  });
+}
+
+function formatDuration(seconds) {
+  return seconds < 60
+    ? `${seconds.toFixed(1)}s`
+    : `${Math.floor(seconds / 60)}m ${Math.round(seconds % 60)}s`;
+}
+
+// ---- Startup ----
+
+elements.search.addEventListener("submit", (e) => {
+  e.preventDefault();
+  const form = new FormData(elements.search);
+  state.filter = {
+    q: form.get("q").trim(),
+    channel: form.get("channel"),
+    window: Number(form.get("window")),
+  };
+  refresh();
+});
+
+// keep the timeline moving
+setInterval(renderTimeline, 60 * 1000);
+
+refresh();
+listen();
@@ -0,0 +1,112 @@
+use crate::{Error, Transcriber, Transcript, Word};
+use std::time::Duration;
+
+/// A [`Transcriber`] which doesn't listen to the audio at all and just hands
+/// back canned responses, in order.
+///
+/// The words in each response are spread evenly across the transmission so
+/// timestamps look sensible. This is mainly useful for testing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeTranscriber {
+    responses: Vec<String>,
+    next: usize,
+}
+
+impl FakeTranscriber {
+    /// Create a [`FakeTranscriber`] which cycles through `responses`.
+    pub fn new<I, S>(responses: I) -> FakeTranscriber
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        FakeTranscriber {
+            responses: responses.into_iter().map(Into::into).collect(),
+            next: 0,
+        }
+    }
+}
+
+impl Default for FakeTranscriber {
+    fn default() -> FakeTranscriber {
+        FakeTranscriber::new(vec!["this is a test transmission"])
+    }
+}
+
+impl Transcriber for FakeTranscriber {
+    fn transcribe(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+    ) -> Result<Transcript, Error> {
+        if self.responses.is_empty() {
+            return Ok(Transcript::default());
+        }
+
+        let response = &self.responses[self.next % self.responses.len()];
+        self.next += 1;
+
+        let duration_ns = if sample_rate == 0 {
+            0
+        } else {
+            samples.len() as u64 * 1_000_000_000 / u64::from(sample_rate)
+        };
+        let texts: Vec<&str> = response.split_whitespace().collect();
+        let step = duration_ns / std::cmp::max(texts.len() as u64, 1);
+
+        let words = texts
+            .into_iter()
+            .enumerate()
+            .map(|(i, text)| Word {
+                text: text.to_string(),
+                start: Duration::from_nanos(step * i as u64),
+                end: Duration::from_nanos(step * (i as u64 + 1)),
+                confidence: 1.0,
+            })
+            .collect();
+
+        Ok(Transcript::new(words))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cycle_through_the_responses() {
+        let mut fake = FakeTranscriber::new(vec!["first", "second"]);
+        let samples = vec![0.0; 16_000];
+
+        let got: Vec<String> = (0..3)
+            .map(|_| fake.transcribe(&samples, 16_000).unwrap().text())
+            .collect();
+
+        assert_eq!(got, vec!["first", "second", "first"]);
+    }
+
+    #[test]
+    fn words_are_spread_across_the_transmission() {
+        let mut fake = FakeTranscriber::new(vec!["engine twelve responding"]);
+        // 1.5 seconds of audio
+        let samples = vec![0.0; 12_000];
+
+        let transcript = fake.transcribe(&samples, 8_000).unwrap();
+
+        let times: Vec<(u64, u64)> = transcript
+            .words
+            .iter()
+            .map(|w| (w.start.as_millis() as u64, w.end.as_millis() as u64))
+            .collect();
+        assert_eq!(times, vec![(0, 500), (500, 1000), (1000, 1500)]);
+        assert!(transcript.words.iter().all(|w| w.confidence == 1.0));
+    }
+
+    #[test]
+    fn no_responses_gives_an_empty_transcript() {
+        let mut fake = FakeTranscriber::new(Vec::<String>::new());
+
+        let transcript = fake.transcribe(&[0.0; 100], 16_000).unwrap();
+
+        assert!(transcript.is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+//! Pluggable backends for converting the audio from a transmission into text.
+
+mod fake;
+mod whisper;
+
+pub use fake::FakeTranscriber;
+pub use whisper::WhisperCpp;
+
+use serde::{Deserialize, Serialize};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    io,
+    time::Duration,
+};
+
+/// Something which can turn speech into text.
+pub trait Transcriber {
+    /// Transcribe a finished transmission.
+    ///
+    /// The `samples` are mono PCM audio in the range `[-1.0, 1.0]`, sampled
+    /// at `sample_rate` Hz.
+    fn transcribe(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+    ) -> Result<Transcript, Error>;
+}
+
+impl<T: Transcriber + ?Sized> Transcriber for Box<T> {
+    fn transcribe(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+    ) -> Result<Transcript, Error> {
+        (**self).transcribe(samples, sample_rate)
+    }
+}
+
+/// The text recognised in a transmission.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transcript {
+    pub words: Vec<Word>,
+}
+
+impl Transcript {
+    pub fn new(words: Vec<Word>) -> Transcript {
+        Transcript { words }
+    }
+
+    /// The transcript as a single string.
+    pub fn text(&self) -> String {
+        let words: Vec<&str> =
+            self.words.iter().map(|w| w.text.as_str()).collect();
+        words.join(" ")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.words.is_empty()
+    }
+}
+
+/// A single recognised word.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Word {
+    pub text: String,
+    /// When the word starts, relative to the start of the transmission.
+    pub start: Duration,
+    /// When the word ends, relative to the start of the transmission.
+    pub end: Duration,
+    /// How confident the engine is that it recognised this word correctly,
+    /// from `0.0` to `1.0`.
+    pub confidence: f32,
+}
+
+/// The reasons transcription can fail.
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    /// The speech-to-text engine ran but didn't succeed.
+    Engine(String),
+    /// The engine's output couldn't be understood.
+    Parse(serde_json::Error),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "I/O error: {}", e),
+            Error::Engine(msg) => write!(f, "The engine failed: {}", msg),
+            Error::Parse(e) => {
+                write!(f, "Unable to parse the engine's output: {}", e)
+            },
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Engine(_) => None,
+            Error::Parse(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(other: io::Error) -> Error {
+        Error::Io(other)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(other: serde_json::Error) -> Error {
+        Error::Parse(other)
+    }
+}
@@ -0,0 +1,301 @@
+use crate::{Error, Transcriber, Transcript, Word};
+use hound::{SampleFormat, WavSpec, WavWriter};
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    process::Command,
+    time::Duration,
+};
+
+/// The sample rate `whisper.cpp` expects its input to be in.
+const WHISPER_SAMPLE_RATE: u32 = 16_000;
+
+/// A [`Transcriber`] which runs the [`whisper.cpp`][whisper] command-line
+/// program against a model on disk, so everything stays offline.
+///
+/// [whisper]: https://github.com/ggerganov/whisper.cpp
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhisperCpp {
+    executable: PathBuf,
+    model: PathBuf,
+    language: Option<String>,
+    threads: Option<u32>,
+}
+
+impl WhisperCpp {
+    /// Use the `whisper-cli` executable from `$PATH` with the given model
+    /// (e.g. `ggml-base.en.bin`).
+    pub fn new<P: Into<PathBuf>>(model: P) -> WhisperCpp {
+        WhisperCpp {
+            executable: PathBuf::from("whisper-cli"),
+            model: model.into(),
+            language: None,
+            threads: None,
+        }
+    }
+
+    pub fn with_executable<P: Into<PathBuf>>(self, executable: P) -> Self {
+        WhisperCpp {
+            executable: executable.into(),
+            ..self
+        }
+    }
+
+    pub fn with_language<S: Into<String>>(self, language: S) -> Self {
+        WhisperCpp {
+            language: Some(language.into()),
+            ..self
+        }
+    }
+
+    pub fn with_threads(self, threads: u32) -> Self {
+        WhisperCpp {
+            threads: Some(threads),
+            ..self
+        }
+    }
+
+    pub fn model(&self) -> &Path {
+        &self.model
+    }
+
+    fn run(&self, wav: &Path, output: &Path) -> Result<(), Error> {
+        let mut cmd = Command::new(&self.executable);
+        cmd.arg("--model")
+            .arg(&self.model)
+            .arg("--file")
+            .arg(wav)
+            .arg("--output-json-full")
+            .arg("--output-file")
+            .arg(output)
+            .arg("--no-prints");
+
+        if let Some(ref language) = self.language {
+            cmd.arg("--language").arg(language);
+        }
+        if let Some(threads) = self.threads {
+            cmd.arg("--threads").arg(threads.to_string());
+        }
+
+        let result = cmd.output()?;
+
+        if result.status.success() {
+            Ok(())
+        } else {
+            Err(Error::Engine(format!(
+                "{} exited with {}: {}",
+                self.executable.display(),
+                result.status,
+                String::from_utf8_lossy(&result.stderr).trim()
+            )))
+        }
+    }
+}
+
+impl Transcriber for WhisperCpp {
+    fn transcribe(
+        &mut self,
+        samples: &[f32],
+        sample_rate: u32,
+    ) -> Result<Transcript, Error> {
+        if samples.is_empty() {
+            return Ok(Transcript::default());
+        }
+
+        let dir = tempfile::tempdir()?;
+        let wav = dir.path().join("transmission.wav");
+        let output = dir.path().join("transcript");
+
+        write_wav(&wav, &resample(samples, sample_rate, WHISPER_SAMPLE_RATE))?;
+        self.run(&wav, &output)?;
+
+        let f = File::open(output.with_extension("json"))?;
+        let parsed: Output = serde_json::from_reader(BufReader::new(f))?;
+
+        Ok(Transcript::new(parsed.into_words()))
+    }
+}
+
+fn write_wav(path: &Path, samples: &[f32]) -> Result<(), Error> {
+    let spec = WavSpec {
+        channels: 1,
+        sample_rate: WHISPER_SAMPLE_RATE,
+        bits_per_sample: 16,
+        sample_format: SampleFormat::Int,
+    };
+    let mut writer = WavWriter::create(path, spec).map_err(hound_error)?;
+
+    for &sample in samples {
+        let sample = sample.clamp(-1.0, 1.0) * f32::from(i16::MAX);
+        writer.write_sample(sample as i16).map_err(hound_error)?;
+    }
+
+    writer.finalize().map_err(hound_error)
+}
+
+fn hound_error(e: hound::Error) -> Error {
+    match e {
+        hound::Error::IoError(e) => Error::Io(e),
+        other => Error::Engine(other.to_string()),
+    }
+}
+
+/// A simple linear-interpolation resampler. Speech doesn't need anything
+/// fancier.
+fn resample(samples: &[f32], from: u32, to: u32) -> Vec<f32> {
+    if from == to || from == 0 {
+        return samples.to_vec();
+    }
+
+    let ratio = f64::from(from) / f64::from(to);
+    let len = (samples.len() as f64 / ratio) as usize;
+
+    (0..len)
+        .map(|i| {
+            let position = i as f64 * ratio;
+            let index = position as usize;
+            let fraction = (position - index as f64) as f32;
+            let current = samples[index];
+            let next = samples.get(index + 1).cloned().unwrap_or(current);
+
+            current + (next - current) * fraction
+        })
+        .collect()
+}
+
+/// The bits of `whisper.cpp`'s `--output-json-full` output we care about.
+#[derive(Debug, Deserialize)]
+struct Output {
+    transcription: Vec<Segment>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Segment {
+    tokens: Vec<Token>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Token {
+    text: String,
+    offsets: Offsets,
+    p: f32,
+}
+
+/// Offsets from the start of the audio, in milliseconds.
+#[derive(Debug, Deserialize)]
+struct Offsets {
+    from: u64,
+    to: u64,
+}
+
+impl Token {
+    /// Special tokens like `[_BEG_]` and `[_TT_42]` aren't part of the text.
+    fn is_special(&self) -> bool {
+        self.text.starts_with("[_") && self.text.ends_with(']')
+    }
+}
+
+impl Output {
+    /// Whisper works with sub-word tokens, where a leading space marks the
+    /// start of a new word. Stitch them back together.
+    fn into_words(self) -> Vec<Word> {
+        let mut words = Vec::new();
+        let mut current: Option<(Word, usize)> = None;
+
+        let tokens = self
+            .transcription
+            .into_iter()
+            .flat_map(|s| s.tokens)
+            .filter(|t| !t.is_special());
+
+        for token in tokens {
+            let starts_word = token.text.starts_with(char::is_whitespace);
+            let text = token.text.trim();
+
+            if text.is_empty() {
+                continue;
+            }
+
+            match current {
+                Some((ref mut word, ref mut count)) if !starts_word => {
+                    word.text.push_str(text);
+                    word.end = Duration::from_millis(token.offsets.to);
+                    word.confidence += token.p;
+                    *count += 1;
+                },
+                _ => {
+                    words.extend(current.take().map(finish_word));
+                    let word = Word {
+                        text: text.to_string(),
+                        start: Duration::from_millis(token.offsets.from),
+                        end: Duration::from_millis(token.offsets.to),
+                        confidence: token.p,
+                    };
+                    current = Some((word, 1));
+                },
+            }
+        }
+
+        words.extend(current.map(finish_word));
+        words
+    }
+}
+
+/// Turn the summed token probabilities into an average.
+fn finish_word((mut word, tokens): (Word, usize)) -> Word {
+    word.confidence /= tokens as f32;
+    word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resampling_to_the_same_rate_does_nothing() {
+        let samples = vec![0.0, 0.5, -0.5, 1.0];
+
+        assert_eq!(resample(&samples, 16_000, 16_000), samples);
+    }
+
+    #[test]
+    fn downsampling_skips_samples() {
+        let samples: Vec<f32> = (0..8).map(|i| i as f32).collect();
+
+        let got = resample(&samples, 32_000, 16_000);
+
+        assert_eq!(got, vec![0.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn upsampling_interpolates_between_samples() {
+        let samples = vec![0.0, 1.0, 0.0];
+
+        let got = resample(&samples, 8_000, 16_000);
+
+        assert_eq!(got, vec![0.0, 0.5, 1.0, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn stitch_whisper_tokens_back_into_words() {
+        let src = include_str!("../tests/data/whisper-output.json");
+        let output: Output = serde_json::from_str(src).unwrap();
+
+        let words = output.into_words();
+
+        let texts: Vec<&str> = words.iter().map(|w| w.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["Engine", "12", "responding.", "Structure", "fire."]
+        );
+        // "respond" + "ing" + "."
+        let responding = &words[2];
+        assert_eq!(responding.start, Duration::from_millis(980));
+        assert_eq!(responding.end, Duration::from_millis(2400));
+        assert!((responding.confidence - 0.8).abs() < 1e-6);
+        // special tokens never turn into words
+        assert!(words.iter().all(|w| !w.text.contains("[_")));
+    }
+}
@@ -0,0 +1,484 @@
+//! An embedded SQLite database for storing [`Transmission`]s, with full-text
+//! search over their transcripts.
+
+mod migrations;
+
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{types::ToSql, Connection, OptionalExtension, Row, NO_PARAMS};
+use std::{
+    error::Error as StdError,
+    fmt::{self, Display, Formatter},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use transmission::Transmission;
+use uuid::Uuid;
+
+/// A handle to the database. Cloning it gives you another handle to the same
+/// connection.
+#[derive(Debug, Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Open the database at `path`, creating it and applying any outstanding
+    /// migrations if necessary.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Database, Error> {
+        let conn = Connection::open(path)?;
+        // the receiver and server are often separate processes, write-ahead
+        // logging lets them read and write at the same time
+        conn.query_row("PRAGMA journal_mode = WAL", NO_PARAMS, |_| Ok(()))?;
+        conn.busy_timeout(Duration::from_secs(5))?;
+
+        Database::from_connection(conn)
+    }
+
+    /// Create a temporary database which only lives in memory.
+    pub fn in_memory() -> Result<Database, Error> {
+        Database::from_connection(Connection::open_in_memory()?)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Database, Error> {
+        migrations::run(&mut conn)?;
+
+        Ok(Database {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Save a [`Transmission`], overwriting any previous version.
+    pub fn save(&self, transmission: &Transmission) -> Result<(), Error> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.execute(
+            "INSERT INTO transmissions (
+                id, channel, sequence, start, running_time, duration, rms,
+                peak, audio, transcript, ctcss, dcs, unit, emergency
+            )
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12,
+                    ?13, ?14)
+            ON CONFLICT (id) DO UPDATE SET
+                channel = excluded.channel,
+                sequence = excluded.sequence,
+                start = excluded.start,
+                running_time = excluded.running_time,
+                duration = excluded.duration,
+                rms = excluded.rms,
+                peak = excluded.peak,
+                audio = excluded.audio,
+                transcript = excluded.transcript,
+                ctcss = excluded.ctcss,
+                dcs = excluded.dcs,
+                unit = excluded.unit,
+                emergency = excluded.emergency",
+            &[
+                &transmission.id.to_string() as &dyn ToSql,
+                &transmission.channel,
+                &transmission.sequence,
+                &to_nanos(transmission.start),
+                &secs(transmission.running_time),
+                &secs(transmission.duration),
+                &transmission.rms,
+                &transmission.peak,
+                &transmission.audio.display().to_string(),
+                &transmission.transcript,
+                &transmission.ctcss,
+                &transmission.dcs,
+                &transmission.unit,
+                &transmission.emergency,
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    /// Look up a [`Transmission`] by its ID.
+    pub fn load(&self, id: Uuid) -> Result<Option<Transmission>, Error> {
+        let conn = self.conn.lock().unwrap();
+
+        conn.query_row(
+            "SELECT * FROM transmissions WHERE id = ?1",
+            &[id.to_string()],
+            |row| Ok(from_row(row)),
+        )
+        .optional()?
+        .transpose()
+    }
+
+    /// Find every [`Transmission`] matching a [`Query`], oldest first.
+    pub fn find(&self, query: &Query) -> Result<Vec<Transmission>, Error> {
+        let mut sql = String::from("SELECT t.* FROM transmissions AS t");
+        let mut conditions = Vec::new();
+        let mut params: Vec<Box<dyn ToSql>> = Vec::new();
+
+        if let Some(ref text) = query.text {
+            sql.push_str(" JOIN transcripts ON transcripts.rowid = t.rowid");
+            conditions.push("transcripts MATCH ?");
+            params.push(Box::new(to_fts_query(text)));
+        }
+        if let Some(ref channel) = query.channel {
+            conditions.push("t.channel = ?");
+            params.push(Box::new(channel.clone()));
+        }
+        if let Some(from) = query.from {
+            conditions.push("t.start >= ?");
+            params.push(Box::new(to_nanos(from)));
+        }
+        if let Some(to) = query.to {
+            conditions.push("t.start < ?");
+            params.push(Box::new(to_nanos(to)));
+        }
+
+        if !conditions.is_empty() {
+            sql.push_str(" WHERE ");
+            sql.push_str(&conditions.join(" AND "));
+        }
+
+        // when limiting, we want the most recent transmissions
+        sql.push_str(" ORDER BY t.start DESC");
+        if let Some(limit) = query.limit {
+            sql.push_str(" LIMIT ?");
+            params.push(Box::new(limit as i64));
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&sql)?;
+        let params: Vec<&dyn ToSql> = params.iter().map(|p| &**p).collect();
+
+        let mut transmissions = stmt
+            .query_map(&params, |row| Ok(from_row(row)))?
+            .map(|result| result.map_err(Error::from).and_then(|t| t))
+            .collect::<Result<Vec<_>, Error>>()?;
+        transmissions.reverse();
+
+        Ok(transmissions)
+    }
+
+    /// The names of every channel a transmission has been received on.
+    pub fn channels(&self) -> Result<Vec<String>, Error> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT DISTINCT channel FROM transmissions ORDER BY channel",
+        )?;
+
+        let channels = stmt
+            .query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()?;
+
+        Ok(channels)
+    }
+}
+
+/// The criteria used by [`Database::find()`].
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Query {
+    /// Only include transmissions from this channel.
+    pub channel: Option<String>,
+    /// Only include transmissions starting at or after this time.
+    pub from: Option<DateTime<Utc>>,
+    /// Only include transmissions starting before this time.
+    pub to: Option<DateTime<Utc>>,
+    /// Only include transmissions whose transcript contains all of these
+    /// words.
+    pub text: Option<String>,
+    /// Return at most this many transmissions (the most recent ones).
+    pub limit: Option<usize>,
+}
+
+/// Turn free text into a FTS5 query matching every word, so users don't
+/// need to know the FTS5 query syntax.
+fn to_fts_query(text: &str) -> String {
+    let words: Vec<String> = text
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+
+    words.join(" ")
+}
+
+fn to_nanos(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp() * 1_000_000_000
+        + i64::from(timestamp.timestamp_subsec_nanos())
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) / 1e9
+}
+
+fn from_row(row: &Row<'_>) -> Result<Transmission, Error> {
+    let id: String = row.get("id")?;
+    let id = Uuid::parse_str(&id)
+        .map_err(|_| Error::Corrupt(format!("\"{}\" isn't a valid ID", id)))?;
+    let start: i64 = row.get("start")?;
+    let audio: String = row.get("audio")?;
+
+    Ok(Transmission {
+        id,
+        channel: row.get("channel")?,
+        sequence: row.get("sequence")?,
+        start: Utc.timestamp_nanos(start),
+        running_time: from_secs(row.get("running_time")?),
+        duration: from_secs(row.get("duration")?),
+        rms: row.get("rms")?,
+        peak: row.get("peak")?,
+        audio: PathBuf::from(audio),
+        transcript: row.get("transcript")?,
+        ctcss: row.get("ctcss")?,
+        dcs: row.get("dcs")?,
+        unit: row.get("unit")?,
+        emergency: row.get("emergency")?,
+    })
+}
+
+fn from_secs(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Sqlite(rusqlite::Error),
+    /// The database contains something we can't make sense of.
+    Corrupt(String),
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Sqlite(e) => write!(f, "Database error: {}", e),
+            Error::Corrupt(msg) => {
+                write!(f, "The database is corrupt: {}", msg)
+            },
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match self {
+            Error::Sqlite(e) => Some(e),
+            Error::Corrupt(_) => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(other: rusqlite::Error) -> Error {
+        Error::Sqlite(other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(minute: i64) -> DateTime<Utc> {
+        "2020-01-01T12:00:00Z".parse::<DateTime<Utc>>().unwrap()
+            + chrono::Duration::minutes(minute)
+    }
+
+    fn transmission(channel: &str, minute: i64) -> Transmission {
+        let mut t = Transmission::new(
+            channel,
+            minute as u32,
+            start(minute),
+            Duration::from_secs(minute as u64 * 60),
+            Duration::from_millis(2500),
+            format!("recordings/{}/{}.wav", channel, minute),
+        );
+        t.rms = -20.5;
+        t.peak = -3.25;
+        t
+    }
+
+    /// A database with a handful of transmissions, one per minute.
+    fn database() -> (Database, Vec<Transmission>) {
+        let db = Database::in_memory().unwrap();
+
+        let mut transmissions = vec![
+            transmission("fire", 0),
+            transmission("police", 1),
+            transmission("fire", 2),
+            transmission("ambulance", 3),
+        ];
+        transmissions[0].transcript =
+            Some("Engine 12, structure fire on Main Street".into());
+        transmissions[1].transcript =
+            Some("Units respond to a fire alarm".into());
+        transmissions[2].transcript = Some("Fire is under control".into());
+
+        for t in &transmissions {
+            db.save(t).unwrap();
+        }
+
+        (db, transmissions)
+    }
+
+    fn sequences(db: &Database, query: &Query) -> Vec<u32> {
+        db.find(query)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.sequence)
+            .collect()
+    }
+
+    #[test]
+    fn save_and_load_a_transmission() {
+        let db = Database::in_memory().unwrap();
+        let mut original = transmission("fire", 5);
+        original.transcript = Some("Engine 12 responding".into());
+        original.ctcss = Some(123.0);
+        original.dcs = Some("023N".into());
+        original.unit = Some("1234".into());
+        original.emergency = true;
+
+        db.save(&original).unwrap();
+        let got = db.load(original.id).unwrap().unwrap();
+
+        assert_eq!(got, original);
+    }
+
+    #[test]
+    fn silent_transmissions_can_be_saved() {
+        let db = Database::in_memory().unwrap();
+        let original = Transmission::new(
+            "fire",
+            0,
+            start(0),
+            Duration::default(),
+            Duration::default(),
+            "silence.wav",
+        );
+
+        db.save(&original).unwrap();
+        let got = db.load(original.id).unwrap().unwrap();
+
+        assert_eq!(got.rms, transmission::MIN_LEVEL);
+        assert_eq!(got.peak, transmission::MIN_LEVEL);
+    }
+
+    #[test]
+    fn unknown_transmissions_arent_found() {
+        let (db, _) = database();
+
+        assert!(db.load(Uuid::new_v4()).unwrap().is_none());
+    }
+
+    #[test]
+    fn saving_again_updates_the_transmission() {
+        let (db, mut transmissions) = database();
+        let t = &mut transmissions[3];
+        assert!(db.load(t.id).unwrap().unwrap().transcript.is_none());
+
+        t.transcript = Some("Patient transported".into());
+        db.save(t).unwrap();
+
+        assert_eq!(db.load(t.id).unwrap().unwrap(), *t);
+        assert_eq!(db.find(&Query::default()).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn find_everything_oldest_first() {
+        let (db, transmissions) = database();
+
+        let got = db.find(&Query::default()).unwrap();
+
+        assert_eq!(got, transmissions);
+    }
+
+    #[test]
+    fn filter_by_channel() {
+        let (db, _) = database();
+        let query = Query {
+            channel: Some("fire".into()),
+            ..Default::default()
+        };
+
+        assert_eq!(sequences(&db, &query), vec![0, 2]);
+    }
+
+    #[test]
+    fn filter_by_start_time() {
+        let (db, _) = database();
+        let query = Query {
+            from: Some(start(1)),
+            to: Some(start(3)),
+            ..Default::default()
+        };
+
+        assert_eq!(sequences(&db, &query), vec![1, 2]);
+    }
+
+    #[test]
+    fn limits_keep_the_most_recent_transmissions() {
+        let (db, _) = database();
+        let query = Query {
+            limit: Some(2),
+            ..Default::default()
+        };
+
+        assert_eq!(sequences(&db, &query), vec![2, 3]);
+    }
+
+    #[test]
+    fn search_transcripts() {
+        let (db, _) = database();
+        let search = |text: &str| {
+            let query = Query {
+                text: Some(text.into()),
+                ..Default::default()
+            };
+            sequences(&db, &query)
+        };
+
+        assert_eq!(search("structure fire"), vec![0]);
+        assert_eq!(search("FIRE"), vec![0, 1, 2]);
+        assert_eq!(search("fire structure"), vec![0]);
+        assert_eq!(search("structure ambulance"), Vec::<u32>::new());
+        // FTS5 syntax is treated as plain words
+        assert_eq!(search("\"fire\" AND OR"), Vec::<u32>::new());
+        assert_eq!(search("fire*"), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn search_and_filter_at_the_same_time() {
+        let (db, _) = database();
+        let query = Query {
+            text: Some("fire".into()),
+            channel: Some("fire".into()),
+            limit: Some(1),
+            ..Default::default()
+        };
+
+        assert_eq!(sequences(&db, &query), vec![2]);
+    }
+
+    #[test]
+    fn search_finds_updated_transcripts() {
+        let (db, mut transmissions) = database();
+        let query = Query {
+            text: Some("control".into()),
+            ..Default::default()
+        };
+        assert_eq!(sequences(&db, &query), vec![2]);
+
+        transmissions[2].transcript = Some("Returning to station".into());
+        db.save(&transmissions[2]).unwrap();
+
+        assert!(sequences(&db, &query).is_empty());
+        let query = Query {
+            text: Some("station".into()),
+            ..Default::default()
+        };
+        assert_eq!(sequences(&db, &query), vec![2]);
+    }
+
+    #[test]
+    fn list_channels() {
+        let (db, _) = database();
+
+        assert_eq!(db.channels().unwrap(), vec!["ambulance", "fire", "police"]);
+    }
+}
@@ -0,0 +1,162 @@
+//! The database schema, expressed as a list of migrations.
+//!
+//! The index of the last migration applied is stored in SQLite's
+//! `user_version` pragma. Migrations must never be edited once they've been
+//! released, add a new one instead.
+
+use rusqlite::{Connection, NO_PARAMS};
+
+const MIGRATIONS: &[&str] = &[
+    // 1: the transmissions themselves
+    r#"
+    CREATE TABLE transmissions (
+        id TEXT PRIMARY KEY NOT NULL,
+        channel TEXT NOT NULL,
+        sequence INTEGER NOT NULL,
+        -- nanoseconds since the unix epoch
+        start INTEGER NOT NULL,
+        -- all durations are in seconds
+        running_time REAL NOT NULL,
+        duration REAL NOT NULL,
+        rms REAL NOT NULL,
+        peak REAL NOT NULL,
+        audio TEXT NOT NULL,
+        transcript TEXT
+    );
+
+    CREATE INDEX transmissions_by_start ON transmissions (start);
+    CREATE INDEX transmissions_by_channel ON transmissions (channel, start);
+    "#,
+    // 2: full-text search over transcripts
+    r#"
+    CREATE VIRTUAL TABLE transcripts USING fts5(
+        transcript,
+        content = 'transmissions',
+        content_rowid = 'rowid'
+    );
+
+    INSERT INTO transcripts (rowid, transcript)
+        SELECT rowid, transcript FROM transmissions;
+
+    CREATE TRIGGER transmissions_after_insert AFTER INSERT ON transmissions
+    BEGIN
+        INSERT INTO transcripts (rowid, transcript)
+            VALUES (new.rowid, new.transcript);
+    END;
+
+    CREATE TRIGGER transmissions_after_delete AFTER DELETE ON transmissions
+    BEGIN
+        INSERT INTO transcripts (transcripts, rowid, transcript)
+            VALUES ('delete', old.rowid, old.transcript);
+    END;
+
+    CREATE TRIGGER transmissions_after_update AFTER UPDATE ON transmissions
+    BEGIN
+        INSERT INTO transcripts (transcripts, rowid, transcript)
+            VALUES ('delete', old.rowid, old.transcript);
+        INSERT INTO transcripts (rowid, transcript)
+            VALUES (new.rowid, new.transcript);
+    END;
+    "#,
+    // 3: CTCSS tones
+    r#"
+    ALTER TABLE transmissions ADD COLUMN ctcss REAL;
+    "#,
+    // 4: DCS codes
+    r#"
+    ALTER TABLE transmissions ADD COLUMN dcs TEXT;
+    "#,
+    // 5: unit IDs
+    r#"
+    ALTER TABLE transmissions ADD COLUMN unit TEXT;
+    "#,
+    // 6: emergency alarms
+    r#"
+    ALTER TABLE transmissions
+        ADD COLUMN emergency INTEGER NOT NULL DEFAULT 0;
+    "#,
+];
+
+/// Bring the database schema up to date.
+pub fn run(conn: &mut Connection) -> rusqlite::Result<()> {
+    let current: i64 =
+        conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute_batch(&format!("PRAGMA user_version = {}", i + 1))?;
+        tx.commit()?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(conn: &Connection) -> usize {
+        conn.query_row("PRAGMA user_version", NO_PARAMS, |row| row.get(0))
+            .map(|v: i64| v as usize)
+            .unwrap()
+    }
+
+    #[test]
+    fn migrate_a_new_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+
+        run(&mut conn).unwrap();
+
+        assert_eq!(version(&conn), MIGRATIONS.len());
+        conn.execute_batch("SELECT emergency FROM transmissions")
+            .unwrap();
+    }
+
+    #[test]
+    fn migrating_twice_does_nothing() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn).unwrap();
+
+        run(&mut conn).unwrap();
+
+        assert_eq!(version(&conn), MIGRATIONS.len());
+    }
+
+    #[test]
+    fn existing_transcripts_become_searchable() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(MIGRATIONS[0]).unwrap();
+        conn.execute_batch("PRAGMA user_version = 1").unwrap();
+        conn.execute_batch(
+            "INSERT INTO transmissions
+                (id, channel, sequence, start, running_time, duration, rms,
+                 peak, audio, transcript)
+             VALUES ('x', 'fire', 0, 0, 0.0, 1.0, -20.0, -3.0, 'x.wav',
+                     'Structure fire on Main Street')",
+        )
+        .unwrap();
+
+        run(&mut conn).unwrap();
+
+        assert_eq!(version(&conn), MIGRATIONS.len());
+        let id: String = conn
+            .query_row(
+                "SELECT t.id FROM transmissions AS t
+                 JOIN transcripts ON transcripts.rowid = t.rowid
+                 WHERE transcripts MATCH 'structure'",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(id, "x");
+        let emergency: bool = conn
+            .query_row(
+                "SELECT emergency FROM transmissions",
+                NO_PARAMS,
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert!(!emergency);
+    }
+}
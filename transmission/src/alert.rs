@@ -0,0 +1,36 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display, Formatter};
+
+/// Something looks wrong with a channel.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Alert {
+    /// The name of the radio channel with the problem.
+    pub channel: String,
+    pub kind: AlertKind,
+    /// The wall-clock time the problem was noticed.
+    pub raised: DateTime<Utc>,
+    /// A human-readable description of the problem.
+    pub message: String,
+}
+
+/// The kinds of problem the receiver watches for.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AlertKind {
+    /// A transmission has gone on for much longer than normal, normally
+    /// because a transmitter is stuck on.
+    StuckCarrier,
+    /// The channel has been silent for much longer than normal, normally
+    /// because the receiver or antenna was disconnected.
+    DeadAir,
+}
+
+impl Display for AlertKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            AlertKind::StuckCarrier => write!(f, "stuck carrier"),
+            AlertKind::DeadAir => write!(f, "dead air"),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{Alert, Transmission};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Something interesting happened while receiving transmissions.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Event {
+    /// The squelch opened and a new transmission is being received.
+    TransmissionStarted {
+        channel: String,
+        sequence: u32,
+        start: DateTime<Utc>,
+    },
+    /// A transmission finished and its audio was saved.
+    TransmissionRecorded { transmission: Transmission },
+    /// A saved transmission has been converted to text.
+    TranscriptionFinished { transmission: Transmission },
+    /// The receiver noticed something wrong with a channel.
+    AlertRaised { alert: Alert },
+    /// A problem the receiver noticed has gone away.
+    AlertCleared { alert: Alert },
+}
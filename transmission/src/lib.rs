@@ -0,0 +1,156 @@
+//! Types shared by the radio receiver, the storage layer and the server.
+
+mod alert;
+mod event;
+pub mod messages;
+
+pub use alert::{Alert, AlertKind};
+pub use event::Event;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::{path::PathBuf, time::Duration};
+use uuid::Uuid;
+
+/// The quietest level (in dBFS) ever reported. Silence is clamped to this
+/// rather than being negative infinity, which JSON can't represent.
+pub const MIN_LEVEL: f64 = -200.0;
+
+/// A single radio transmission.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Transmission {
+    /// A unique identifier for this transmission.
+    pub id: Uuid,
+    /// The name of the radio channel this transmission was received on.
+    pub channel: String,
+    /// The transmission's sequence number, as assigned by the squelch.
+    pub sequence: u32,
+    /// The wall-clock time the transmission started.
+    pub start: DateTime<Utc>,
+    /// When the transmission started, relative to the pipeline's running
+    /// time.
+    #[serde(with = "seconds")]
+    pub running_time: Duration,
+    /// How long the transmission went for.
+    #[serde(with = "seconds")]
+    pub duration: Duration,
+    /// The RMS signal level over the entire transmission, in dBFS.
+    pub rms: f64,
+    /// The loudest sample in the transmission, in dBFS.
+    pub peak: f64,
+    /// Where the recorded audio was saved to.
+    pub audio: PathBuf,
+    /// The text version of the transmission, if it has been transcribed.
+    pub transcript: Option<String>,
+    /// The CTCSS tone (in Hz) the transmission used, if one was detected.
+    #[serde(default)]
+    pub ctcss: Option<f64>,
+    /// The DCS code (e.g. `"D023N"`) the transmission used, if one was
+    /// detected.
+    #[serde(default)]
+    pub dcs: Option<String>,
+    /// The ID the sending radio identified itself with, if it sent one.
+    #[serde(default)]
+    pub unit: Option<String>,
+    /// Did the sending radio raise an emergency alarm?
+    #[serde(default)]
+    pub emergency: bool,
+}
+
+impl Transmission {
+    /// Create a new [`Transmission`] with a random ID, no transcript, no
+    /// CTCSS tone or DCS code, no unit ID and no emergency alarm.
+    pub fn new<C, P>(
+        channel: C,
+        sequence: u32,
+        start: DateTime<Utc>,
+        running_time: Duration,
+        duration: Duration,
+        audio: P,
+    ) -> Transmission
+    where
+        C: Into<String>,
+        P: Into<PathBuf>,
+    {
+        Transmission {
+            id: Uuid::new_v4(),
+            channel: channel.into(),
+            sequence,
+            start,
+            running_time,
+            duration,
+            rms: MIN_LEVEL,
+            peak: MIN_LEVEL,
+            audio: audio.into(),
+            transcript: None,
+            ctcss: None,
+            dcs: None,
+            unit: None,
+            emergency: false,
+        }
+    }
+
+    /// The wall-clock time the transmission finished.
+    pub fn end(&self) -> DateTime<Utc> {
+        match chrono::Duration::from_std(self.duration) {
+            Ok(duration) => self.start + duration,
+            Err(_) => self.start,
+        }
+    }
+}
+
+/// (De)serialize a [`Duration`] as a fractional number of seconds.
+mod seconds {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S>(duration: &Duration, ser: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let secs = duration.as_secs() as f64
+            + f64::from(duration.subsec_nanos()) / 1e9;
+        ser.serialize_f64(secs)
+    }
+
+    pub fn deserialize<'de, D>(de: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let secs = f64::deserialize(de)?;
+
+        if secs.is_finite() && secs >= 0.0 {
+            let nanos = (secs.fract() * 1e9).round() as u32;
+            Ok(Duration::new(secs.trunc() as u64, nanos))
+        } else {
+            Err(D::Error::custom(format!(
+                "{} is not a valid duration",
+                secs
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn silent_transmissions_survive_a_round_trip_through_json() {
+        let original = Transmission::new(
+            "fire-dispatch",
+            1,
+            Utc::now(),
+            Duration::from_secs(5),
+            Duration::from_secs(0),
+            "recordings/fire-dispatch/1.wav",
+        );
+
+        let json = serde_json::to_string(&original).unwrap();
+        let got: Transmission = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(got, original);
+        assert_eq!(got.rms, MIN_LEVEL);
+        assert_eq!(got.peak, MIN_LEVEL);
+    }
+}
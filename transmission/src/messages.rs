@@ -0,0 +1,40 @@
+//! The names used for the custom events and element messages passed around
+//! by the GStreamer elements.
+
+/// The squelch opened. Sent downstream as a custom event and posted on the bus
+/// as an element message.
+pub const TRANSMISSION_START: &str = "transmission-start";
+/// The squelch closed. Sent downstream as a custom event and posted on the bus
+/// as an element message.
+pub const TRANSMISSION_END: &str = "transmission-end";
+/// A transmission was written to disk. The element message's `transmission`
+/// field contains the JSON-serialized [`crate::Transmission`].
+pub const TRANSMISSION_WRITTEN: &str = "transmission-written";
+/// A transmission was transcribed. The element message contains the
+/// `sequence` and `timestamp` of the original transmission, the `text`, and a
+/// JSON-serialized `transcript` with per-word timings. If transcription
+/// failed, the `text` is empty and there is an `error` field instead of a
+/// `transcript`.
+pub const TRANSCRIPTION: &str = "transcription";
+/// The CTCSS tone used by a transmission was detected. The element message
+/// contains the transmission's `sequence`, the tone's `frequency` (in Hz) and
+/// its `level` (in dBFS).
+pub const CTCSS: &str = "ctcss";
+/// The DCS code used by a transmission was decoded. The element message
+/// contains the transmission's `sequence`, the `code` (e.g. `"D023N"`) and
+/// whether it was `inverted`.
+pub const DCS: &str = "dcs";
+/// The radio which sent a transmission identified itself. The element message
+/// contains the transmission's `sequence`, the `unit` ID and the `format` it
+/// was sent in (`"dtmf"`, `"zvei"` or `"ccir"`).
+pub const ANI: &str = "ani";
+/// An MDC-1200 or FleetSync data burst was decoded. The element message
+/// contains the transmission's `sequence`, the `unit` ID, the `format` it was
+/// sent in (`"mdc1200"` or `"fleetsync"`) and whether it was an `emergency`
+/// alarm.
+pub const MDC: &str = "mdc";
+/// The signal level was measured. Posted periodically by the level meter, the
+/// element message contains the `timestamp` and `duration` (in nanoseconds) of
+/// the interval measured, its `rms` and `peak` levels (in dBFS) and how many
+/// samples were `clipped`.
+pub const LEVEL: &str = "level";